@@ -0,0 +1,49 @@
+// Embeds the simulator in an analysis program. The T junction is warmed up
+// once, then simulated for ten minutes from the same snapshot with several
+// seeds, and the spread of the mean travel time and delay between runs is
+// printed.
+
+use std::time::Duration;
+
+use traffic_rs::{
+    simulation_rng::SimulationRng, NodeGraph, Simulation, SimulationConfig, SimulationSnapshot,
+};
+
+fn main() {
+    let config = SimulationConfig {
+        seed: Some(0),
+        ..SimulationConfig::default()
+    };
+    let mut warm_up = Simulation::new(NodeGraph::create_t_junction(), &config);
+    warm_up.run_for(Duration::from_secs(120));
+    let snapshot = SimulationSnapshot::capture(&warm_up);
+
+    println!(
+        "{:>6} {:>8} {:>13} {:>11}",
+        "seed", "trips", "travel time", "delay"
+    );
+    for seed in 0..5 {
+        let mut simulation = snapshot
+            .clone()
+            .into_simulation(&config)
+            .expect("snapshots taken from a simulation can be restored");
+        simulation.rng = SimulationRng::from_seed(seed);
+        simulation.run_for(Duration::from_secs(600));
+
+        let records = &simulation.trip_log.records;
+        let trips = records.len().max(1) as f32;
+        let travel_time = records
+            .iter()
+            .map(|record| record.travel_time())
+            .sum::<f32>()
+            / trips;
+        let delay = records.iter().map(|record| record.delay()).sum::<f32>() / trips;
+        println!(
+            "{:>6} {:>8} {:>11.1} s {:>9.1} s",
+            seed,
+            records.len(),
+            travel_time,
+            delay
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+    render::camera::ScalingMode,
+};
+
+use crate::node_graph::NodeGraph;
+
+// How quickly the camera turns and moves with the mouse, per pixel dragged
+const ORBIT_SPEED: f32 = 0.005;
+const PAN_SPEED: f32 = 0.0015;
+// The fraction of the distance zoomed per scroll line
+const ZOOM_SPEED: f32 = 0.1;
+// Scroll lines per pixel for touchpads
+const PIXELS_PER_SCROLL_LINE: f32 = 16.;
+const MIN_DISTANCE: f32 = 5.;
+const MAX_DISTANCE: f32 = 2000.;
+// The lowest and highest the camera can orbit, as an angle above the ground
+const MIN_PITCH: f32 = 0.1;
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+// Extra space left around the network when fitting the camera to it
+const FIT_MARGIN: f32 = 1.15;
+// How far the ground extends past the network on each side
+const GROUND_MARGIN: f32 = 10.;
+// The ground is never smaller than this so small networks don't float
+const MIN_GROUND_SIZE: f32 = 25.;
+
+#[derive(Component)]
+pub struct Ground;
+
+// Orbits the camera around a focus point on the ground. The transform and
+// projection of the camera are derived from this each frame.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct CameraController {
+    pub focus: Vec3,
+    // Rotation around the focus point, 0 looks towards -z
+    pub yaw: f32,
+    // Angle above the ground
+    pub pitch: f32,
+    pub distance: f32,
+    // Looks straight down with an orthographic projection
+    pub top_down: bool,
+}
+
+impl Default for CameraController {
+    // Matches the original fixed view from (0, 25, 15)
+    fn default() -> Self {
+        CameraController {
+            focus: Vec3::ZERO,
+            yaw: 0.,
+            pitch: 25_f32.atan2(15.),
+            distance: Vec2::new(25., 15.).length(),
+            top_down: false,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn transform(&self) -> Transform {
+        let yaw_rotation = Quat::from_rotation_y(self.yaw);
+        if self.top_down {
+            return Transform::from_translation(self.focus + Vec3::Y * self.distance)
+                .looking_at(self.focus, yaw_rotation * Vec3::NEG_Z);
+        }
+        let direction = Vec3::new(0., self.pitch.sin(), self.pitch.cos());
+        Transform::from_translation(self.focus + yaw_rotation * direction * self.distance)
+            .looking_at(self.focus, Vec3::Y)
+    }
+
+    // An orthographic projection showing the same ground height as the
+    // perspective projection does at the focus point
+    pub fn projection(&self) -> Projection {
+        if !self.top_down {
+            return Projection::Perspective(PerspectiveProjection::default());
+        }
+        let fov = PerspectiveProjection::default().fov;
+        Projection::Orthographic(OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical(2. * self.distance * (fov / 2.).tan()),
+            far: MAX_DISTANCE * 2.,
+            ..default()
+        })
+    }
+
+    // Centers the view on the bounds and moves back until they fit
+    pub fn fit(&mut self, (min, max): (Vec3, Vec3)) {
+        let fov = PerspectiveProjection::default().fov;
+        let radius = ((max - min).xz().length() / 2.).max(MIN_DISTANCE);
+        self.focus = (min + max) / 2.;
+        self.distance = (radius * FIT_MARGIN / (fov / 2.).sin()).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+
+    fn orbit(&mut self, delta: Vec2) {
+        self.yaw -= delta.x * ORBIT_SPEED;
+        self.pitch = (self.pitch + delta.y * ORBIT_SPEED).clamp(MIN_PITCH, MAX_PITCH);
+    }
+
+    // Moves the focus across the ground, following the mouse
+    fn pan(&mut self, delta: Vec2) {
+        let yaw_rotation = Quat::from_rotation_y(self.yaw);
+        let right = yaw_rotation * Vec3::X;
+        let forward = yaw_rotation * Vec3::NEG_Z;
+        let scale = self.distance * PAN_SPEED;
+        self.focus += (forward * delta.y - right * delta.x) * scale;
+    }
+
+    fn zoom(&mut self, scroll_lines: f32) {
+        self.distance =
+            (self.distance * (1. - scroll_lines * ZOOM_SPEED)).clamp(MIN_DISTANCE, MAX_DISTANCE);
+    }
+}
+
+// The corners of the box containing every node, None if there are no nodes
+pub fn network_bounds(node_graph: &NodeGraph) -> Option<(Vec3, Vec3)> {
+    let mut positions = node_graph.nodes.iter().map(|node| node.position);
+    let first = positions.next()?;
+    Some(positions.fold((first, first), |(min, max), position| {
+        (min.min(position), max.max(position))
+    }))
+}
+
+pub fn spawn_camera(mut commands: Commands) {
+    let controller = CameraController::default();
+    commands.spawn((
+        Camera3dBundle {
+            transform: controller.transform(),
+            ..default()
+        },
+        controller,
+    ));
+}
+
+// Dragging with the middle mouse button orbits the camera, or pans it with
+// shift held, and scrolling zooms. F fits the view to the network and T
+// toggles the top down view.
+pub fn control_camera(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut motion_events: EventReader<MouseMotion>,
+    mut wheel_events: EventReader<MouseWheel>,
+    node_graph: Res<NodeGraph>,
+    mut controller_query: Query<&mut CameraController>,
+) {
+    let Ok(mut controller) = controller_query.get_single_mut() else {
+        return;
+    };
+
+    let mouse_delta: Vec2 = motion_events.read().map(|event| event.delta).sum();
+    if mouse.pressed(MouseButton::Middle) && mouse_delta != Vec2::ZERO {
+        if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+            controller.pan(mouse_delta);
+        } else {
+            controller.orbit(mouse_delta);
+        }
+    }
+
+    let scroll_lines: f32 = wheel_events
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_SCROLL_LINE,
+        })
+        .sum();
+    if scroll_lines != 0. {
+        controller.zoom(scroll_lines);
+    }
+
+    if keyboard.just_pressed(KeyCode::KeyF) {
+        if let Some(bounds) = network_bounds(&node_graph) {
+            controller.fit(bounds);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::KeyT) {
+        controller.top_down = !controller.top_down;
+    }
+}
+
+// Fits the camera to the network and resizes the ground to cover it whenever
+// the extent of the network changes
+pub fn fit_camera_to_network(
+    node_graph: Res<NodeGraph>,
+    mut controller_query: Query<&mut CameraController>,
+    mut ground_query: Query<&mut Transform, With<Ground>>,
+    mut last_bounds: Local<Option<(Vec3, Vec3)>>,
+) {
+    let bounds = network_bounds(&node_graph);
+    if *last_bounds == bounds {
+        return;
+    }
+    *last_bounds = bounds;
+    let Some((min, max)) = bounds else {
+        return;
+    };
+
+    if let Ok(mut controller) = controller_query.get_single_mut() {
+        controller.fit((min, max));
+    }
+    let center = (min + max) / 2.;
+    let size = ((max - min).xz() + GROUND_MARGIN * 2.).max(Vec2::splat(MIN_GROUND_SIZE));
+    for mut transform in &mut ground_query {
+        transform.translation.x = center.x;
+        transform.translation.z = center.z;
+        transform.scale = Vec3::new(size.x, 1., size.y);
+    }
+}
+
+// Moves the camera to match its controller
+pub fn update_camera(
+    mut camera_query: Query<
+        (&CameraController, &mut Transform, &mut Projection),
+        Changed<CameraController>,
+    >,
+) {
+    for (controller, mut transform, mut projection) in &mut camera_query {
+        *transform = controller.transform();
+        *projection = controller.projection();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_view_matches_the_original_camera() {
+        let transform = CameraController::default().transform();
+        assert!(transform.translation.distance(Vec3::new(0., 25., 15.)) < 1e-4);
+
+        // The top down view looks straight down with -z at the top of the screen
+        let controller = CameraController {
+            top_down: true,
+            ..default()
+        };
+        let transform = controller.transform();
+        assert!(transform.forward().dot(Vec3::NEG_Y) > 0.9999);
+        assert!(transform.up().dot(Vec3::NEG_Z) > 0.9999);
+    }
+
+    #[test]
+    fn fit_keeps_the_network_in_view() {
+        let graph = NodeGraph::create_roundabout(4);
+        let (min, max) = network_bounds(&graph).unwrap();
+        let mut controller = CameraController::default();
+        controller.fit((min, max));
+        assert_eq!(controller.focus, (min + max) / 2.);
+
+        // Every node is within the field of view of the camera
+        let transform = controller.transform();
+        let half_fov = PerspectiveProjection::default().fov / 2.;
+        for node in graph.nodes.iter() {
+            let direction = (node.position - transform.translation).normalize();
+            assert!(direction.angle_between(*transform.forward()) < half_fov);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    intersection_delay,
+    node_graph::{NodeGraph, LANE_WIDTH},
+    vehicles::Vehicle,
+};
+#[cfg(feature = "bevy")]
+use crate::{simulation_clock::SimulationClock, simulation_rng::SimulationRng};
+
+// How far back from the junction node the middle of a crosswalk is, in world
+// units. This keeps vehicles waiting for the node clear of the crosswalk.
+const CROSSWALK_SETBACK: f32 = 2.5;
+// The depth of a crosswalk along the road
+const CROSSWALK_WIDTH: f32 = 1.;
+// How fast pedestrians cross, in world units per second
+const WALKING_SPEED: f32 = 1.2;
+// Spacing of the stripes drawn across the road
+#[cfg(feature = "bevy")]
+const STRIPE_SPACING: f32 = 0.25;
+#[cfg(feature = "bevy")]
+const PEDESTRIAN_RADIUS: f32 = 0.12;
+
+// A fixed time pedestrian signal. Pedestrians may start crossing during the
+// walk phase at the start of each cycle and vehicles are stopped for it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PedestrianSignal {
+    // Seconds from the start of one walk phase to the next
+    pub cycle: f32,
+    // Seconds of each cycle pedestrians are shown walk
+    pub walk: f32,
+    // Shifts the cycle start so crosswalks can be coordinated
+    #[serde(default)]
+    pub offset: f32,
+}
+
+impl PedestrianSignal {
+    pub fn is_walk(&self, time: f32) -> bool {
+        (time - self.offset).rem_euclid(self.cycle) < self.walk
+    }
+}
+
+// How crosswalks are placed and used. Crosswalks are turned off by default so
+// runs without them are unchanged.
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CrosswalkSettings {
+    pub enabled: bool,
+    // Pedestrians arriving at each crosswalk per second
+    pub arrival_rate: f32,
+    // Crosswalks without a signal give way to pedestrians as soon as they
+    // arrive
+    pub signal: Option<PedestrianSignal>,
+}
+
+impl Default for CrosswalkSettings {
+    fn default() -> Self {
+        CrosswalkSettings {
+            enabled: false,
+            arrival_rate: 0.05,
+            signal: Some(PedestrianSignal {
+                cycle: 40.,
+                walk: 10.,
+                offset: 0.,
+            }),
+        }
+    }
+}
+
+// A crosswalk across an edge just before a junction, and the pedestrians
+// waiting at and walking across it
+#[cfg_attr(feature = "bevy", derive(Component))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Crosswalk {
+    pub edge: (usize, usize),
+    // How far along the edge the middle of the crosswalk is, from 0 at the
+    // start to 1 at the end
+    pub position: f32,
+    // Seconds it takes a pedestrian to cross
+    pub crossing_time: f32,
+    pub arrival_rate: f32,
+    pub signal: Option<PedestrianSignal>,
+    // The simulation times the pedestrians waiting at the curb arrived
+    pub waiting: Vec<f32>,
+    // The seconds each pedestrian on the crosswalk has been walking for
+    pub crossing: Vec<f32>,
+    pub pedestrians_crossed: usize,
+    // The total seconds crossed pedestrians waited before starting to cross
+    pub total_wait: f32,
+}
+
+impl Crosswalk {
+    pub fn new(
+        edge: (usize, usize),
+        position: f32,
+        crossing_time: f32,
+        settings: &CrosswalkSettings,
+    ) -> Self {
+        Crosswalk {
+            edge,
+            position,
+            crossing_time,
+            arrival_rate: settings.arrival_rate,
+            signal: settings.signal,
+            waiting: Vec::new(),
+            crossing: Vec::new(),
+            pedestrians_crossed: 0,
+            total_wait: 0.,
+        }
+    }
+
+    // The part of the edge the crosswalk covers, in edge space
+    pub fn extent(&self, node_graph: &NodeGraph) -> (f32, f32) {
+        let edge_length = node_graph.nodes[self.edge.0]
+            .position
+            .distance(node_graph.nodes[self.edge.1].position);
+        let half_width = CROSSWALK_WIDTH / 2. / edge_length;
+        (self.position - half_width, self.position + half_width)
+    }
+
+    pub fn is_walk(&self, time: f32) -> bool {
+        match self.signal {
+            Some(signal) => signal.is_walk(time),
+            None => true,
+        }
+    }
+
+    // Checks whether any part of a vehicle is over the crosswalk
+    pub fn is_vehicle_on<'a>(
+        &self,
+        vehicles: impl IntoIterator<Item = &'a Vehicle>,
+        node_graph: &NodeGraph,
+    ) -> bool {
+        let (start, end) = self.extent(node_graph);
+        let edge_length = node_graph.nodes[self.edge.0]
+            .position
+            .distance(node_graph.nodes[self.edge.1].position);
+        vehicles.into_iter().any(|vehicle| {
+            let half_length = vehicle.vehicle_type().length / 2. / edge_length;
+            vehicle.get_edge() == Some(self.edge)
+                && vehicle.edge_position() + half_length > start
+                && vehicle.edge_position() - half_length < end
+        })
+    }
+
+    // Vehicles have to stop before the crosswalk while pedestrians are on it
+    // or have right of way, which is during the walk phase when there is a
+    // signal and whenever someone is waiting when there isn't
+    pub fn is_blocked(&self, time: f32) -> bool {
+        if !self.crossing.is_empty() {
+            return true;
+        }
+        match self.signal {
+            Some(signal) => signal.is_walk(time),
+            None => !self.waiting.is_empty(),
+        }
+    }
+
+    // Moves pedestrians along for one update. New pedestrians arrive at
+    // random, and waiting ones start crossing when they may and no vehicle is
+    // in the way.
+    pub fn update(
+        &mut self,
+        time: f32,
+        delta_seconds: f32,
+        is_vehicle_on_crosswalk: bool,
+        rng: &mut impl Rng,
+    ) {
+        for walked in self.crossing.iter_mut() {
+            *walked += delta_seconds;
+        }
+        let crossing_time = self.crossing_time;
+        self.crossing.retain(|walked| *walked < crossing_time);
+
+        if rng.gen::<f32>() < self.arrival_rate * delta_seconds {
+            self.waiting.push(time);
+        }
+
+        if self.is_walk(time) && !is_vehicle_on_crosswalk {
+            for arrival_time in self.waiting.drain(..) {
+                self.total_wait += time - arrival_time;
+                self.pedestrians_crossed += 1;
+                self.crossing.push(0.);
+            }
+        }
+    }
+}
+
+// Places a crosswalk on each edge entering a junction from outside it, where
+// the stop lines are. Edges too short to fit one are skipped.
+pub fn place_crosswalks(node_graph: &NodeGraph, settings: &CrosswalkSettings) -> Vec<Crosswalk> {
+    let junction_nodes = intersection_delay::junction_nodes(node_graph);
+    let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+    edges.sort();
+    edges
+        .into_iter()
+        .filter(|(source, dest)| junction_nodes.contains(dest) && !junction_nodes.contains(source))
+        .filter_map(|edge| {
+            let length = node_graph.nodes[edge.0]
+                .position
+                .distance(node_graph.nodes[edge.1].position);
+            if length < CROSSWALK_SETBACK + CROSSWALK_WIDTH {
+                return None;
+            }
+            let road_width = node_graph.lane_count(edge) as f32 * LANE_WIDTH;
+            Some(Crosswalk::new(
+                edge,
+                1. - CROSSWALK_SETBACK / length,
+                road_width / WALKING_SPEED,
+                settings,
+            ))
+        })
+        .collect()
+}
+
+// What crosswalks were last placed from, kept to tell when they need
+// replacing
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default)]
+pub struct CrosswalkPlacement {
+    // None until crosswalks are first placed
+    placed_from: Option<(HashSet<(usize, usize)>, CrosswalkSettings)>,
+}
+
+impl CrosswalkPlacement {
+    // Treats the current crosswalks as placed from the network and settings,
+    // so crosswalks restored along with them are kept
+    pub fn current(node_graph: &NodeGraph, settings: &CrosswalkSettings) -> Self {
+        CrosswalkPlacement {
+            placed_from: Some((node_graph.edges.clone(), settings.clone())),
+        }
+    }
+}
+
+// Gives the crosswalks to use instead of the current ones if the edges of
+// the network or the settings have changed since they were placed, or None
+// if they are still current
+pub fn replace_crosswalks(
+    last_placement: &mut CrosswalkPlacement,
+    node_graph: &NodeGraph,
+    settings: &CrosswalkSettings,
+) -> Option<Vec<Crosswalk>> {
+    if last_placement
+        .placed_from
+        .as_ref()
+        .is_some_and(|(edges, placed_settings)| {
+            *edges == node_graph.edges && placed_settings == settings
+        })
+    {
+        return None;
+    }
+    *last_placement = CrosswalkPlacement::current(node_graph, settings);
+
+    if settings.enabled {
+        Some(place_crosswalks(node_graph, settings))
+    } else {
+        Some(Vec::new())
+    }
+}
+
+// Respawns the crosswalks whenever the edges of the network or the settings
+// change
+#[cfg(feature = "bevy")]
+pub fn update_crosswalk_placement(
+    mut commands: Commands,
+    node_graph: Res<NodeGraph>,
+    settings: Res<CrosswalkSettings>,
+    crosswalk_query: Query<Entity, With<Crosswalk>>,
+    mut last_placement: ResMut<CrosswalkPlacement>,
+) {
+    let Some(crosswalks) = replace_crosswalks(&mut last_placement, &node_graph, &settings) else {
+        return;
+    };
+    for entity in &crosswalk_query {
+        commands.entity(entity).despawn();
+    }
+    for crosswalk in crosswalks {
+        commands.spawn(crosswalk);
+    }
+}
+
+// Moves pedestrians across the crosswalks
+#[cfg(feature = "bevy")]
+pub fn update_crosswalks(
+    node_graph: Res<NodeGraph>,
+    clock: Res<SimulationClock>,
+    mut rng: ResMut<SimulationRng>,
+    mut crosswalk_query: Query<&mut Crosswalk>,
+    vehicle_query: Query<&Vehicle>,
+) {
+    for mut crosswalk in &mut crosswalk_query {
+        let is_vehicle_on_crosswalk = crosswalk.is_vehicle_on(&vehicle_query, &node_graph);
+        crosswalk.update(
+            clock.elapsed_seconds(),
+            clock.delta_seconds(),
+            is_vehicle_on_crosswalk,
+            &mut rng.0,
+        );
+    }
+}
+
+// Turns crosswalks on and off when C is pressed
+#[cfg(feature = "bevy")]
+pub fn toggle_crosswalks(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut settings: ResMut<CrosswalkSettings>,
+) {
+    if keyboard.just_pressed(KeyCode::KeyC) {
+        settings.enabled = !settings.enabled;
+    }
+}
+
+// Draws crosswalks as stripes across the road, red while vehicles have to
+// stop for them, with pedestrians waiting at the right curb and walking to
+// the left
+#[cfg(feature = "bevy")]
+pub fn show_crosswalks(
+    mut gizmos: Gizmos,
+    node_graph: Res<NodeGraph>,
+    clock: Res<SimulationClock>,
+    crosswalk_query: Query<&Crosswalk>,
+) {
+    let pedestrian_color = Color::srgb(0.2, 0.6, 1.);
+    for crosswalk in &crosswalk_query {
+        let (Some(start), Some(end)) = (
+            node_graph.nodes.get(crosswalk.edge.0),
+            node_graph.nodes.get(crosswalk.edge.1),
+        ) else {
+            continue;
+        };
+        let forward = (end.position - start.position).normalize_or_zero();
+        let right = forward.cross(Vec3::Y).normalize_or_zero();
+        let center = start.position.lerp(end.position, crosswalk.position);
+        let half_width = node_graph.lane_count(crosswalk.edge) as f32 * LANE_WIDTH / 2.;
+        let color = if crosswalk.is_blocked(clock.elapsed_seconds()) {
+            Color::srgb(0.9, 0.2, 0.2)
+        } else {
+            Color::srgb(0.95, 0.95, 0.9)
+        };
+
+        let stripes = (half_width * 2. / STRIPE_SPACING) as usize;
+        for stripe in 0..=stripes {
+            let offset = right * (stripe as f32 * STRIPE_SPACING - half_width);
+            gizmos.line(
+                center + offset - forward * CROSSWALK_WIDTH / 2.,
+                center + offset + forward * CROSSWALK_WIDTH / 2.,
+                color,
+            );
+        }
+
+        for (index, _) in crosswalk.waiting.iter().enumerate() {
+            let position =
+                center + right * (half_width + PEDESTRIAN_RADIUS * (2 * index + 2) as f32);
+            gizmos.sphere(
+                position,
+                Quat::IDENTITY,
+                PEDESTRIAN_RADIUS,
+                pedestrian_color,
+            );
+        }
+        for walked in crosswalk.crossing.iter() {
+            let progress = walked / crosswalk.crossing_time;
+            let position = center + right * half_width * (1. - 2. * progress);
+            gizmos.sphere(
+                position,
+                Quat::IDENTITY,
+                PEDESTRIAN_RADIUS,
+                pedestrian_color,
+            );
+        }
+    }
+}
+
+// Writes the pedestrians crossed and their mean wait at each crosswalk
+pub fn write_table<'a>(
+    crosswalks: impl IntoIterator<Item = &'a Crosswalk>,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:<12} {:>11} {:>15}",
+        "crosswalk", "pedestrians", "mean wait (s)"
+    )?;
+    for crosswalk in crosswalks {
+        let mean_wait = if crosswalk.pedestrians_crossed > 0 {
+            crosswalk.total_wait / crosswalk.pedestrians_crossed as f32
+        } else {
+            0.
+        };
+        writeln!(
+            writer,
+            "{:<12} {:>11} {:>15.1}",
+            format!("{}->{}", crosswalk.edge.0, crosswalk.edge.1),
+            crosswalk.pedestrians_crossed,
+            mean_wait
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn crosswalks_are_placed_on_junction_approaches() {
+        let graph = NodeGraph::create();
+        let crosswalks = place_crosswalks(&graph, &CrosswalkSettings::default());
+
+        // One on each of the 4 approaches, where the stop lines are
+        let junction_nodes = intersection_delay::junction_nodes(&graph);
+        assert_eq!(crosswalks.len(), 4);
+        for crosswalk in crosswalks.iter() {
+            assert!(junction_nodes.contains(&crosswalk.edge.1));
+            assert!(!junction_nodes.contains(&crosswalk.edge.0));
+            let (start, end) = crosswalk.extent(&graph);
+            assert!(0. < start && end < 1.);
+        }
+    }
+
+    #[test]
+    fn pedestrians_cross_during_the_walk_phase() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let settings = CrosswalkSettings {
+            enabled: true,
+            // A pedestrian arrives every update
+            arrival_rate: 100.,
+            signal: Some(PedestrianSignal {
+                cycle: 10.,
+                walk: 4.,
+                offset: 0.,
+            }),
+        };
+        let mut crosswalk = Crosswalk::new((0, 1), 0.9, 2., &settings);
+
+        // Pedestrians wait through don't walk and vehicles carry on
+        crosswalk.update(5., 1., false, &mut rng);
+        crosswalk.update(6., 1., false, &mut rng);
+        assert_eq!(crosswalk.waiting, vec![5., 6.]);
+        assert!(!crosswalk.is_blocked(6.));
+
+        // A vehicle on the crosswalk holds them back even once walk starts
+        crosswalk.update(10., 1., true, &mut rng);
+        assert_eq!(crosswalk.waiting.len(), 3);
+        assert!(crosswalk.is_blocked(10.));
+
+        crosswalk.update(11., 1., false, &mut rng);
+        assert_eq!(crosswalk.crossing.len(), 4);
+        assert_eq!(crosswalk.pedestrians_crossed, 4);
+        assert_eq!(crosswalk.total_wait, 6. + 5. + 1. + 0.);
+
+        // Vehicles stay stopped after walk ends until the last have crossed
+        crosswalk.update(14., 1., false, &mut rng);
+        assert!(crosswalk.is_blocked(14.));
+        crosswalk.update(15., 1., false, &mut rng);
+        assert!(crosswalk.crossing.is_empty());
+        assert!(!crosswalk.is_blocked(15.));
+    }
+}
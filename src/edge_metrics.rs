@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+use crate::node_graph::NodeGraph;
+
+// Running totals for a single edge over a time bin. Values are weighted by the
+// length of each tick so that results don't depend on the frame rate.
+#[derive(Clone, Debug, Default)]
+struct EdgeAccumulator {
+    // Vehicles which reached the end of the edge
+    crossings: usize,
+    // Sum of vehicle count * tick duration
+    occupancy_time: f32,
+    // Sum of vehicle speed * tick duration
+    speed_time: f32,
+    // Sum of queued vehicle count * tick duration
+    queue_time: f32,
+    max_queue_length: usize,
+}
+
+// The standard traffic measures of an edge over a time bin
+#[derive(Clone, Debug, PartialEq)]
+pub struct EdgeMeasures {
+    // Vehicles per hour crossing the end of the edge
+    pub flow: f32,
+    // Average vehicles per kilometer of edge, None if the edge has no length
+    pub density: Option<f32>,
+    // Average speed of the vehicles on the edge, None if the edge was empty
+    pub space_mean_speed: Option<f32>,
+    // Average and maximum number of stopped vehicles on the edge
+    pub mean_queue_length: f32,
+    pub max_queue_length: usize,
+}
+
+// Measurements of every edge over a period of simulation time
+#[derive(Clone, Debug, Default)]
+pub struct EdgeMetricsBin {
+    pub start_time: f32,
+    // The amount of simulation time sampled into the bin
+    pub sampled_time: f32,
+    edges: HashMap<(usize, usize), EdgeAccumulator>,
+}
+
+impl EdgeMetricsBin {
+    fn new(start_time: f32) -> Self {
+        EdgeMetricsBin {
+            start_time,
+            ..Default::default()
+        }
+    }
+
+    // Calculates the measures of an edge, world space units are treated as meters
+    pub fn measures(&self, edge: (usize, usize), edge_length: f32) -> Option<EdgeMeasures> {
+        let accumulator = self.edges.get(&edge)?;
+        if self.sampled_time <= 0. {
+            return None;
+        }
+        let space_mean_speed = if accumulator.occupancy_time > 0. {
+            Some(accumulator.speed_time / accumulator.occupancy_time)
+        } else {
+            None
+        };
+        let density = if edge_length > 0. {
+            Some(accumulator.occupancy_time / self.sampled_time / edge_length * 1000.)
+        } else {
+            None
+        };
+        Some(EdgeMeasures {
+            flow: accumulator.crossings as f32 / self.sampled_time * 3600.,
+            density,
+            space_mean_speed,
+            mean_queue_length: accumulator.queue_time / self.sampled_time,
+            max_queue_length: accumulator.max_queue_length,
+        })
+    }
+}
+
+// What was on an edge during the last tick
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EdgeSample {
+    pub vehicle_count: usize,
+    // Average speed of the vehicles on the edge, 0 if the edge is empty
+    pub mean_speed: f32,
+    // Average of each vehicle's speed relative to the speed it wants to drive
+    // at, 0 if the edge is empty
+    pub mean_speed_ratio: f32,
+    pub queue_length: usize,
+}
+
+// Samples every edge each tick and aggregates the samples into time bins
+#[cfg_attr(feature = "bevy", derive(Resource))]
+pub struct EdgeMetrics {
+    bin_duration: f32,
+    // Completed bins in time order
+    pub bins: Vec<EdgeMetricsBin>,
+    current_bin: EdgeMetricsBin,
+    // Observations made during the current tick
+    pending_samples: HashMap<(usize, usize), EdgeSample>,
+    pending_crossings: HashMap<(usize, usize), usize>,
+    // The samples from the last completed tick
+    pub latest_samples: HashMap<(usize, usize), EdgeSample>,
+}
+
+impl Default for EdgeMetrics {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(60))
+    }
+}
+
+impl EdgeMetrics {
+    pub fn new(bin_duration: Duration) -> Self {
+        EdgeMetrics {
+            bin_duration: bin_duration.as_secs_f32(),
+            bins: Vec::new(),
+            current_bin: EdgeMetricsBin::new(0.),
+            pending_samples: HashMap::new(),
+            pending_crossings: HashMap::new(),
+            latest_samples: HashMap::new(),
+        }
+    }
+
+    // Discards all measurements, keeping the bin duration
+    pub fn clear(&mut self) {
+        *self = Self::new(Duration::from_secs_f32(self.bin_duration));
+    }
+
+    // Records a vehicle reaching the end of an edge
+    pub fn record_crossing(&mut self, edge: (usize, usize)) {
+        *self.pending_crossings.entry(edge).or_default() += 1;
+    }
+
+    // Records a vehicle on an edge during the current tick, along with the
+    // speed it would drive at if nothing was in its way
+    pub fn record_vehicle(
+        &mut self,
+        edge: (usize, usize),
+        speed: f32,
+        desired_speed: f32,
+        is_stopped: bool,
+    ) {
+        let sample = self.pending_samples.entry(edge).or_default();
+        sample.mean_speed += speed;
+        if desired_speed > 0. {
+            sample.mean_speed_ratio += speed / desired_speed;
+        }
+        sample.vehicle_count += 1;
+        if is_stopped {
+            sample.queue_length += 1;
+        }
+    }
+
+    // Adds the observations of the current tick to the current bin. `time` is
+    // the simulation time at the end of the tick.
+    pub fn end_tick(&mut self, time: f32, delta_seconds: f32, node_graph: &NodeGraph) {
+        // Bins are aligned to the first tick, which may be part way through a run
+        if self.bins.is_empty() && self.current_bin.sampled_time == 0. {
+            self.current_bin.start_time = time - delta_seconds;
+        }
+
+        // Start a new bin once the current one is full
+        while time - self.current_bin.start_time > self.bin_duration {
+            let start_time = self.current_bin.start_time + self.bin_duration;
+            let bin = std::mem::replace(&mut self.current_bin, EdgeMetricsBin::new(start_time));
+            self.bins.push(bin);
+        }
+
+        self.current_bin.sampled_time += delta_seconds;
+        self.latest_samples.clear();
+        for edge in node_graph.edges.iter() {
+            let mut sample = self.pending_samples.remove(edge).unwrap_or_default();
+            let accumulator = self.current_bin.edges.entry(*edge).or_default();
+            accumulator.crossings += self.pending_crossings.remove(edge).unwrap_or_default();
+            accumulator.occupancy_time += sample.vehicle_count as f32 * delta_seconds;
+            accumulator.speed_time += sample.mean_speed * delta_seconds;
+            accumulator.queue_time += sample.queue_length as f32 * delta_seconds;
+            accumulator.max_queue_length = accumulator.max_queue_length.max(sample.queue_length);
+
+            // Speeds were summed while recording, turn them into an average
+            if sample.vehicle_count > 0 {
+                sample.mean_speed /= sample.vehicle_count as f32;
+                sample.mean_speed_ratio /= sample.vehicle_count as f32;
+            }
+            self.latest_samples.insert(*edge, sample);
+        }
+        self.pending_samples.clear();
+        self.pending_crossings.clear();
+    }
+
+    // Gets the completed bins followed by the partially filled current bin
+    pub fn all_bins(&self) -> impl Iterator<Item = &EdgeMetricsBin> {
+        self.bins
+            .iter()
+            .chain(Some(&self.current_bin).filter(|bin| bin.sampled_time > 0.))
+    }
+
+    // Writes one row per edge per bin, ordered by bin then edge
+    pub fn write_csv(&self, writer: &mut impl Write, node_graph: &NodeGraph) -> io::Result<()> {
+        writeln!(
+            writer,
+            "bin_start,bin_end,source_node,dest_node,flow,density,space_mean_speed,mean_queue_length,max_queue_length"
+        )?;
+        let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+        edges.sort();
+        for bin in self.all_bins() {
+            for edge in edges.iter() {
+                let edge_length = node_graph.nodes[edge.0]
+                    .position
+                    .distance(node_graph.nodes[edge.1].position);
+                let Some(measures) = bin.measures(*edge, edge_length) else {
+                    continue;
+                };
+                let density = measures
+                    .density
+                    .map_or(String::new(), |density| format!("{:.3}", density));
+                let space_mean_speed = measures
+                    .space_mean_speed
+                    .map_or(String::new(), |speed| format!("{:.3}", speed));
+                writeln!(
+                    writer,
+                    "{:.1},{:.1},{},{},{:.1},{},{},{:.3},{}",
+                    bin.start_time,
+                    bin.start_time + bin.sampled_time,
+                    edge.0,
+                    edge.1,
+                    measures.flow,
+                    density,
+                    space_mean_speed,
+                    measures.mean_queue_length,
+                    measures.max_queue_length
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes edge_metrics.csv into the given directory
+    pub fn save(&self, directory: impl AsRef<Path>, node_graph: &NodeGraph) -> io::Result<()> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        let mut file = fs::File::create(directory.join("edge_metrics.csv"))?;
+        self.write_csv(&mut file, node_graph)
+    }
+}
+
+// Saves the edge metrics to the working directory when the app exits
+#[cfg(feature = "bevy")]
+pub fn save_edge_metrics(
+    mut exit_events: EventReader<AppExit>,
+    edge_metrics: Res<EdgeMetrics>,
+    node_graph: Res<NodeGraph>,
+) {
+    if exit_events.read().next().is_none() {
+        return;
+    }
+
+    match edge_metrics.save(".", &node_graph) {
+        Ok(()) => info!("Saved edge metrics to edge_metrics.csv"),
+        Err(error) => error!("Failed to save edge metrics: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use glam::Vec3;
+
+    use super::*;
+    use crate::node_graph::Node;
+
+    #[test]
+    fn end_tick_aggregates_measures_into_bins() {
+        let graph = NodeGraph::create_highway_merge();
+        let mut edge_metrics = EdgeMetrics::new(Duration::from_secs(10));
+
+        // Two vehicles sit on edge (0, 1) for 10 seconds, one of them stopped
+        for tick in 1..=10 {
+            edge_metrics.record_vehicle((0, 1), 6., 8., false);
+            edge_metrics.record_vehicle((0, 1), 0., 8., true);
+            if tick % 5 == 0 {
+                edge_metrics.record_crossing((0, 1));
+            }
+            edge_metrics.end_tick(tick as f32, 1., &graph);
+        }
+        // The next tick starts a new bin
+        edge_metrics.end_tick(11., 1., &graph);
+
+        assert_eq!(edge_metrics.bins.len(), 1);
+        let measures = edge_metrics.bins[0].measures((0, 1), 12.).unwrap();
+        assert_eq!(
+            measures,
+            EdgeMeasures {
+                // 2 crossings in 10 seconds
+                flow: 720.,
+                // 2 vehicles on 12 meters
+                density: Some(2. / 12. * 1000.),
+                space_mean_speed: Some(3.),
+                mean_queue_length: 1.,
+                max_queue_length: 1,
+            }
+        );
+
+        // Empty edges are still measured
+        let empty = edge_metrics.bins[0].measures((1, 2), 12.).unwrap();
+        assert_eq!(empty.flow, 0.);
+        assert_eq!(empty.space_mean_speed, None);
+        assert_eq!(edge_metrics.all_bins().count(), 2);
+    }
+
+    #[test]
+    fn zero_length_edges_have_no_density() {
+        // Two nodes in the same place, as left by some imports
+        let graph = NodeGraph::new(
+            vec![
+                Node {
+                    position: Vec3::ZERO,
+                },
+                Node {
+                    position: Vec3::ZERO,
+                },
+            ],
+            HashSet::from([(0, 1)]),
+        );
+        let mut edge_metrics = EdgeMetrics::new(Duration::from_secs(10));
+        edge_metrics.record_vehicle((0, 1), 0., 8., true);
+        edge_metrics.end_tick(1., 1., &graph);
+
+        let measures = edge_metrics.all_bins().next().unwrap().measures((0, 1), 0.);
+        assert_eq!(measures.unwrap().density, None);
+
+        let mut csv = Vec::new();
+        edge_metrics.write_csv(&mut csv, &graph).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(!csv.contains("NaN") && !csv.contains("inf"));
+        assert_eq!(csv.lines().nth(1), Some("0.0,1.0,0,1,0.0,,0.000,1.000,1"));
+    }
+
+    #[test]
+    fn end_tick_keeps_latest_samples() {
+        let graph = NodeGraph::create_highway_merge();
+        let mut edge_metrics = EdgeMetrics::default();
+
+        edge_metrics.record_vehicle((2, 3), 4., 8., false);
+        edge_metrics.record_vehicle((2, 3), 8., 8., false);
+        edge_metrics.end_tick(0.1, 0.1, &graph);
+        assert_eq!(
+            edge_metrics.latest_samples[&(2, 3)],
+            EdgeSample {
+                vehicle_count: 2,
+                mean_speed: 6.,
+                mean_speed_ratio: 0.75,
+                queue_length: 0,
+            }
+        );
+
+        edge_metrics.end_tick(0.2, 0.1, &graph);
+        assert_eq!(edge_metrics.latest_samples[&(2, 3)], EdgeSample::default());
+    }
+}
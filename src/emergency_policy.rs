@@ -0,0 +1,24 @@
+#[cfg(feature = "bevy")]
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+// How the rest of the traffic treats emergency vehicles. Both can be turned
+// off to measure how much they improve response times.
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmergencyPolicy {
+    // Emergency vehicles approaching a node are given it ahead of any vehicle
+    // which doesn't already hold it
+    pub preemption: bool,
+    // Vehicles pull over to let emergency vehicles behind them pass
+    pub yielding: bool,
+}
+
+impl Default for EmergencyPolicy {
+    fn default() -> Self {
+        EmergencyPolicy {
+            preemption: true,
+            yielding: true,
+        }
+    }
+}
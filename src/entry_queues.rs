@@ -0,0 +1,248 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Write},
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+
+use crate::vehicles::Vehicle;
+
+// A vehicle waiting at a source node for room to enter the network
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueuedVehicle {
+    pub vehicle: Vehicle,
+    // The simulation time the vehicle joined the queue
+    pub queued_at: f32,
+}
+
+// How long vehicles waited to enter the network at a source node, and how
+// long the queue there grew
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntryQueueStats {
+    pub entered: usize,
+    pub total_delay: f32,
+    pub max_delay: f32,
+    pub max_length: usize,
+    // The queue length integrated over time, for the mean length
+    length_time: f32,
+}
+
+impl EntryQueueStats {
+    pub fn mean_delay(&self) -> f32 {
+        if self.entered == 0 {
+            return 0.;
+        }
+        self.total_delay / self.entered as f32
+    }
+}
+
+// Vehicles which have been spawned but can't enter the network yet because
+// the first edge of their path is blocked. Each source node has its own
+// queue, and vehicles enter from it in the order they arrived.
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default)]
+pub struct EntryQueues {
+    // Queues by source node, ordered so vehicles are always let in in the
+    // same order
+    queues: BTreeMap<usize, VecDeque<QueuedVehicle>>,
+    stats: BTreeMap<usize, EntryQueueStats>,
+    // The simulation time the queue lengths have been measured over
+    measured_time: f32,
+}
+
+impl EntryQueues {
+    // Adds a vehicle to the back of the queue at the start of its path
+    pub fn push(&mut self, vehicle: Vehicle, now: f32) {
+        let source_node = vehicle.path()[0];
+        self.queues
+            .entry(source_node)
+            .or_default()
+            .push_back(QueuedVehicle {
+                vehicle,
+                queued_at: now,
+            });
+    }
+
+    // The number of vehicles waiting at a source node
+    pub fn queue_length(&self, source_node: usize) -> usize {
+        self.queues.get(&source_node).map_or(0, VecDeque::len)
+    }
+
+    // Lets the vehicle at the front of each queue into the network if there
+    // is room for it. Only one vehicle enters per node each update, since the
+    // vehicles let in aren't on the road until the next one.
+    pub fn release(&mut self, now: f32, has_room: impl Fn(&Vehicle) -> bool) -> Vec<Vehicle> {
+        let mut released = Vec::new();
+        for (source_node, queue) in self.queues.iter_mut() {
+            if !queue
+                .front()
+                .is_some_and(|queued| has_room(&queued.vehicle))
+            {
+                continue;
+            }
+            let Some(QueuedVehicle {
+                mut vehicle,
+                queued_at,
+            }) = queue.pop_front()
+            else {
+                continue;
+            };
+            let delay = now - queued_at;
+            let stats = self.stats.entry(*source_node).or_default();
+            stats.entered += 1;
+            stats.total_delay += delay;
+            stats.max_delay = stats.max_delay.max(delay);
+            vehicle.enter_network(now);
+            released.push(vehicle);
+        }
+        released
+    }
+
+    // Accumulates the queue lengths left after vehicles have entered over an
+    // update, so vehicles which enter straight away aren't counted as queued
+    pub fn record_lengths(&mut self, delta_seconds: f32) {
+        for (source_node, queue) in self.queues.iter() {
+            let stats = self.stats.entry(*source_node).or_default();
+            stats.length_time += queue.len() as f32 * delta_seconds;
+            stats.max_length = stats.max_length.max(queue.len());
+        }
+        self.measured_time += delta_seconds;
+    }
+
+    // The time averaged queue length at a source node
+    pub fn mean_length(&self, source_node: usize) -> f32 {
+        match self.stats.get(&source_node) {
+            Some(stats) if self.measured_time > 0. => stats.length_time / self.measured_time,
+            _ => 0.,
+        }
+    }
+
+    // Every queued vehicle, front to back by source node
+    pub fn queued_vehicles(&self) -> impl Iterator<Item = &QueuedVehicle> {
+        self.queues.values().flatten()
+    }
+
+    // Removes the waiting vehicles, keeping the statistics
+    pub fn clear_vehicles(&mut self) {
+        self.queues.clear();
+    }
+
+    // Starts again with the given vehicles waiting and no statistics
+    pub fn restore(queued_vehicles: Vec<QueuedVehicle>) -> Self {
+        let mut entry_queues = EntryQueues::default();
+        for queued in queued_vehicles {
+            let source_node = queued.vehicle.path()[0];
+            entry_queues
+                .queues
+                .entry(source_node)
+                .or_default()
+                .push_back(queued);
+        }
+        entry_queues
+    }
+
+    // Writes the queue statistics of each source node as a text table
+    pub fn write_table(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{:<8} {:>8} {:>8} {:>11} {:>11} {:>11} {:>10}",
+            "source", "entered", "waiting", "mean delay", "max delay", "mean queue", "max queue"
+        )?;
+        for (source_node, stats) in self.stats.iter() {
+            writeln!(
+                writer,
+                "{:<8} {:>8} {:>8} {:>9.1} s {:>9.1} s {:>11.1} {:>10}",
+                source_node,
+                stats.entered,
+                self.queue_length(*source_node),
+                stats.mean_delay(),
+                stats.max_delay,
+                self.mean_length(*source_node),
+                stats.max_length
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        node_graph::NodeGraph,
+        simulation::{Simulation, SimulationConfig},
+        vehicle_types::VehicleType,
+    };
+
+    #[test]
+    fn queued_vehicles_enter_in_order_once_there_is_room() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut entry_queues = EntryQueues::default();
+        for id in 0..2 {
+            let vehicle = Vehicle::new(id, VehicleType::default(), vec![0, 1], 1., &mut rng);
+            entry_queues.push(vehicle, 1.);
+        }
+
+        // Nothing enters while the first edge is blocked
+        assert!(entry_queues.release(2., |_| false).is_empty());
+        entry_queues.record_lengths(1.);
+        assert_eq!(entry_queues.queue_length(0), 2);
+
+        let entered = entry_queues.release(4., |_| true);
+        entry_queues.record_lengths(1.);
+        assert_eq!(entered.len(), 1);
+        assert_eq!(entered[0].id(), 0);
+        assert_eq!(entry_queues.queue_length(0), 1);
+
+        let stats = &entry_queues.stats[&0];
+        assert_eq!(stats.entered, 1);
+        assert_eq!(stats.max_delay, 3.);
+        assert_eq!(stats.max_length, 2);
+        assert_eq!(entry_queues.mean_length(0), 1.5);
+    }
+
+    #[test]
+    fn vehicles_queue_instead_of_overlapping_at_busy_sources() {
+        let config = SimulationConfig {
+            spawn_interval: Duration::from_millis(50),
+            seed: Some(11),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(NodeGraph::create_highway_merge(), &config);
+        simulation.run_for(Duration::from_secs(20));
+
+        assert!(simulation
+            .entry_queues
+            .stats
+            .values()
+            .any(|stats| stats.max_length > 1 && stats.mean_delay() > 0.));
+
+        // Vehicles still on their first edge don't sit on top of each other
+        let vehicles: Vec<&Vehicle> = simulation
+            .vehicles
+            .iter()
+            .filter(|vehicle| vehicle.path_index() == 0)
+            .collect();
+        let node_graph = &simulation.node_graph;
+        for (index, vehicle) in vehicles.iter().enumerate() {
+            for other in vehicles[index + 1..].iter() {
+                if vehicle.get_edge() != other.get_edge() {
+                    continue;
+                }
+                let distance = vehicle
+                    .get_world_position(node_graph)
+                    .distance(other.get_world_position(node_graph));
+                let min_distance =
+                    (vehicle.vehicle_type().length + other.vehicle_type().length) / 2.;
+                assert!(distance >= min_distance, "vehicles overlap at the source");
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::HashSet,
+    f32::consts::PI,
+    fs,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use glam::Vec3;
+
+use crate::{
+    crosswalks::CrosswalkSettings,
+    edge_metrics::EdgeMetrics,
+    emergency_policy::EmergencyPolicy,
+    node_graph::{Node, NodeGraph},
+    simulation::{Simulation, SimulationConfig},
+    vehicle_types::VehicleTypes,
+};
+
+// Settings for a demand sweep
+#[derive(Clone, Debug)]
+pub struct FundamentalDiagramConfig {
+    // The spawn intervals to simulate, one run is made per interval
+    pub spawn_intervals: Vec<Duration>,
+    // Simulation time before measurements start, lets the network fill up
+    pub warm_up: Duration,
+    // Simulation time measured per run
+    pub duration: Duration,
+    // The period each flow/density pair is aggregated over
+    pub bin_duration: Duration,
+    pub time_step: Duration,
+    // Seeds every run so a diagram can be repeated, random seeds are used if
+    // there is none
+    pub seed: Option<u64>,
+    // The fleet mix of the vehicles spawned
+    pub vehicle_types: VehicleTypes,
+    pub emergency_policy: EmergencyPolicy,
+    pub crosswalk_settings: CrosswalkSettings,
+}
+
+impl Default for FundamentalDiagramConfig {
+    fn default() -> Self {
+        FundamentalDiagramConfig {
+            spawn_intervals: [2000, 1500, 1000, 750, 500, 400, 300, 200, 150, 100]
+                .map(Duration::from_millis)
+                .to_vec(),
+            warm_up: Duration::from_secs(120),
+            duration: Duration::from_secs(600),
+            bin_duration: Duration::from_secs(30),
+            time_step: Duration::from_millis(50),
+            seed: None,
+            vehicle_types: VehicleTypes::default(),
+            emergency_policy: EmergencyPolicy::default(),
+            crosswalk_settings: CrosswalkSettings::default(),
+        }
+    }
+}
+
+// A single detector measurement
+#[derive(Clone, Debug, PartialEq)]
+pub struct FundamentalDiagramPoint {
+    // The demand of the run in vehicles per hour
+    pub demand: f32,
+    pub bin_start: f32,
+    pub edge: (usize, usize),
+    // Vehicles per hour
+    pub flow: f32,
+    // Vehicles per kilometer
+    pub density: f32,
+    pub space_mean_speed: Option<f32>,
+}
+
+// Creates a one way ring road with on-ramps and off-ramps spaced evenly
+// around it. Each ramp pair attaches to the ring at ring node k, the off-ramp
+// leaves from the ring node before it so vehicles can't exit where they
+// entered. Nodes are numbered with the ring first, then on-ramp sources and
+// off-ramp destinations alternating.
+pub fn create_ring_road(ring_node_count: usize, radius: f32, ramp_count: usize) -> NodeGraph {
+    assert!(ramp_count > 0 && ramp_count <= ring_node_count);
+
+    // Positions are given as a fractional ring node index and a distance from the center
+    let ring_position = |node: f32, distance: f32| {
+        let angle = 2. * PI * node / ring_node_count as f32;
+        Vec3::new(angle.cos(), 0., -angle.sin()) * distance
+    };
+    let mut nodes: Vec<Node> = (0..ring_node_count)
+        .map(|node| Node {
+            position: ring_position(node as f32, radius),
+        })
+        .collect();
+    let mut edges: HashSet<(usize, usize)> = (0..ring_node_count)
+        .map(|node| (node, (node + 1) % ring_node_count))
+        .collect();
+
+    // Ramps sit outside the ring and join it at an angle
+    let ramp_distance = radius * 1.25;
+    for ramp in 0..ramp_count {
+        let ring_node = ramp * ring_node_count / ramp_count;
+        let previous_ring_node = (ring_node + ring_node_count - 1) % ring_node_count;
+        let on_ramp = nodes.len();
+        let off_ramp = on_ramp + 1;
+        nodes.push(Node {
+            position: ring_position(ring_node as f32 - 0.4, ramp_distance),
+        });
+        nodes.push(Node {
+            position: ring_position(previous_ring_node as f32 - 0.6, ramp_distance),
+        });
+        edges.insert((on_ramp, ring_node));
+        edges.insert((previous_ring_node, off_ramp));
+    }
+    NodeGraph::new(nodes, edges)
+}
+
+// Simulates the network once per spawn interval and collects flow/density
+// pairs from every detector edge after the warm up period
+pub fn run_fundamental_diagram(
+    node_graph: impl Fn() -> NodeGraph,
+    detector_edges: &[(usize, usize)],
+    config: &FundamentalDiagramConfig,
+) -> Vec<FundamentalDiagramPoint> {
+    let mut points = Vec::new();
+    for spawn_interval in config.spawn_intervals.iter() {
+        let simulation_config = SimulationConfig {
+            spawn_interval: *spawn_interval,
+            time_step: config.time_step,
+            metrics_bin_duration: config.bin_duration,
+            seed: config.seed,
+            vehicle_types: config.vehicle_types.clone(),
+            emergency_policy: config.emergency_policy.clone(),
+            crosswalk_settings: config.crosswalk_settings.clone(),
+        };
+        let mut simulation = Simulation::new(node_graph(), &simulation_config);
+        simulation.run_for(config.warm_up);
+        // Discard anything measured while the network was filling up
+        simulation.edge_metrics = EdgeMetrics::new(config.bin_duration);
+        simulation.run_for(config.duration);
+
+        let demand = 3600. / spawn_interval.as_secs_f32();
+        let graph = &simulation.node_graph;
+        for bin in simulation.edge_metrics.bins.iter() {
+            for edge in detector_edges {
+                let edge_length = graph.nodes[edge.0]
+                    .position
+                    .distance(graph.nodes[edge.1].position);
+                let Some(measures) = bin.measures(*edge, edge_length) else {
+                    continue;
+                };
+                // Edges without length have no density to plot
+                let Some(density) = measures.density else {
+                    continue;
+                };
+                points.push(FundamentalDiagramPoint {
+                    demand,
+                    bin_start: bin.start_time,
+                    edge: *edge,
+                    flow: measures.flow,
+                    density,
+                    space_mean_speed: measures.space_mean_speed,
+                });
+            }
+        }
+    }
+    points
+}
+
+pub fn write_csv(points: &[FundamentalDiagramPoint], writer: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        writer,
+        "demand,bin_start,source_node,dest_node,flow,density,space_mean_speed"
+    )?;
+    for point in points {
+        let space_mean_speed = point
+            .space_mean_speed
+            .map_or(String::new(), |speed| format!("{:.3}", speed));
+        writeln!(
+            writer,
+            "{:.1},{:.1},{},{},{:.1},{:.3},{}",
+            point.demand,
+            point.bin_start,
+            point.edge.0,
+            point.edge.1,
+            point.flow,
+            point.density,
+            space_mean_speed
+        )?;
+    }
+    Ok(())
+}
+
+// Sweeps demand on the default ring road using every ring edge as a
+// detector, then writes the points to the given file
+pub fn run_ring_road_experiment(
+    config: &FundamentalDiagramConfig,
+    output_path: impl AsRef<Path>,
+) -> io::Result<Vec<FundamentalDiagramPoint>> {
+    let ring_node_count = 24;
+    let ring_road = || create_ring_road(ring_node_count, 40., 4);
+    let detector_edges: Vec<(usize, usize)> = (0..ring_node_count)
+        .map(|node| (node, (node + 1) % ring_node_count))
+        .collect();
+
+    let points = run_fundamental_diagram(ring_road, &detector_edges, config);
+    write_csv(&points, &mut fs::File::create(output_path)?)?;
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_ring_road_routes_around_the_ring() {
+        let graph = create_ring_road(8, 20., 2);
+        assert_eq!(graph.source_nodes, HashSet::from([8, 10]));
+        assert_eq!(graph.dest_nodes, HashSet::from([9, 11]));
+        assert_eq!(graph.shortest_path_map[&(8, 11)], vec![8, 0, 1, 2, 3, 11]);
+        assert_eq!(
+            graph.shortest_path_map[&(8, 9)],
+            vec![8, 0, 1, 2, 3, 4, 5, 6, 7, 9]
+        );
+        assert!(graph.validate().is_valid());
+    }
+
+    #[test]
+    fn run_fundamental_diagram_collects_points_per_demand() {
+        let config = FundamentalDiagramConfig {
+            spawn_intervals: vec![Duration::from_millis(1000), Duration::from_millis(250)],
+            warm_up: Duration::from_secs(20),
+            duration: Duration::from_secs(40),
+            bin_duration: Duration::from_secs(10),
+            time_step: Duration::from_millis(50),
+            seed: Some(4),
+            ..FundamentalDiagramConfig::default()
+        };
+        let ring_road = || create_ring_road(8, 20., 2);
+        let points = run_fundamental_diagram(ring_road, &[(1, 2), (5, 6)], &config);
+        // Seeded sweeps can be repeated
+        assert_eq!(
+            run_fundamental_diagram(ring_road, &[(1, 2), (5, 6)], &config),
+            points
+        );
+
+        // Three complete bins per run, the last bin is still being filled
+        assert_eq!(points.len(), 2 * 3 * 2);
+        assert!(points.iter().all(|point| point.density >= 0.));
+        let total_flow = |demand: f32| -> f32 {
+            points
+                .iter()
+                .filter(|point| point.demand == demand)
+                .map(|point| point.flow)
+                .sum()
+        };
+        assert!(total_flow(3600.) > 0.);
+        assert!(total_flow(14400.) > total_flow(3600.));
+    }
+}
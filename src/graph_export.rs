@@ -0,0 +1,176 @@
+use std::fmt::Write;
+
+use crate::node_graph::NodeGraph;
+
+// Classifies a node the same way the renderer colors it
+fn node_kind(node_graph: &NodeGraph, node: usize) -> &'static str {
+    if node_graph.source_nodes.contains(&node) {
+        "source"
+    } else if node_graph.dest_nodes.contains(&node) {
+        "dest"
+    } else {
+        "internal"
+    }
+}
+
+// Gets the edges of the graph in a stable order so exports can be diffed
+fn sorted_edges(node_graph: &NodeGraph) -> Vec<(usize, usize)> {
+    let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+    edges.sort();
+    edges
+}
+
+// Gets the position of a node on the ground plane with y pointing along the
+// forward vector -z. Subtracting from zero avoids writing out negative zeros.
+fn planar_position(node_graph: &NodeGraph, node: usize) -> (f32, f32) {
+    let position = node_graph.nodes[node].position;
+    (position.x, 0. - position.z)
+}
+
+fn highlighted_path(
+    node_graph: &NodeGraph,
+    highlighted_path_index: Option<(usize, usize)>,
+) -> Option<&Vec<usize>> {
+    node_graph.shortest_path_map.get(&highlighted_path_index?)
+}
+
+// Converts the node graph into a GraphViz DOT digraph.
+// Node positions are pinned so that layouts using neato match the simulation,
+// and the edges of the highlighted path (if any) are drawn in bold magenta.
+pub fn write_dot(node_graph: &NodeGraph, highlighted_path_index: Option<(usize, usize)>) -> String {
+    let highlighted_path = highlighted_path(node_graph, highlighted_path_index);
+
+    let mut dot = String::new();
+    writeln!(dot, "digraph network {{").unwrap();
+    writeln!(
+        dot,
+        "    node [shape=circle, style=filled, fontcolor=white];"
+    )
+    .unwrap();
+    for index in 0..node_graph.nodes.len() {
+        let kind = node_kind(node_graph, index);
+        let color = match kind {
+            "source" => "green",
+            "dest" => "red",
+            _ => "blue",
+        };
+        let (x, y) = planar_position(node_graph, index);
+        writeln!(
+            dot,
+            "    {} [kind={}, fillcolor={}, pos=\"{},{}!\"];",
+            index, kind, color, x, y
+        )
+        .unwrap();
+    }
+    for (source, dest) in sorted_edges(node_graph) {
+        let is_highlighted =
+            highlighted_path.is_some_and(|path| NodeGraph::is_edge_in_path(source, dest, path));
+        if is_highlighted {
+            writeln!(
+                dot,
+                "    {} -> {} [color=magenta, penwidth=3];",
+                source, dest
+            )
+            .unwrap();
+        } else {
+            writeln!(dot, "    {} -> {};", source, dest).unwrap();
+        }
+    }
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+// Converts the node graph into a GeoJSON feature collection.
+// Nodes become points and edges become line strings. GeoJSON coordinates are
+// WGS84 longitude and latitude, so only networks which know where they lie on
+// the earth, such as OpenStreetMap imports, can be written. Returns None for
+// any other network.
+pub fn write_geojson(
+    node_graph: &NodeGraph,
+    highlighted_path_index: Option<(usize, usize)>,
+) -> Option<String> {
+    let projection = node_graph.projection?;
+    let highlighted_path = highlighted_path(node_graph, highlighted_path_index);
+    let coordinates = |node: usize| {
+        let (lat, lon) = projection.unproject(node_graph.nodes[node].position);
+        format!("[{:.7}, {:.7}]", lon, lat)
+    };
+
+    let mut features = Vec::new();
+    for index in 0..node_graph.nodes.len() {
+        features.push(format!(
+            r#"{{"type": "Feature", "geometry": {{"type": "Point", "coordinates": {}}}, "properties": {{"index": {}, "kind": "{}"}}}}"#,
+            coordinates(index),
+            index,
+            node_kind(node_graph, index)
+        ));
+    }
+    for (source, dest) in sorted_edges(node_graph) {
+        let is_highlighted =
+            highlighted_path.is_some_and(|path| NodeGraph::is_edge_in_path(source, dest, path));
+        let attributes = node_graph.edge_attributes.get(&(source, dest));
+        let speed_limit = attributes
+            .and_then(|attributes| attributes.speed_limit)
+            .map_or("null".to_string(), |speed_limit| speed_limit.to_string());
+        let lanes = attributes
+            .and_then(|attributes| attributes.lanes)
+            .map_or("null".to_string(), |lanes| lanes.to_string());
+        features.push(format!(
+            r#"{{"type": "Feature", "geometry": {{"type": "LineString", "coordinates": [{}, {}]}}, "properties": {{"source": {}, "dest": {}, "highlighted": {}, "speed_limit": {}, "lanes": {}}}}}"#,
+            coordinates(source),
+            coordinates(dest),
+            source,
+            dest,
+            is_highlighted,
+            speed_limit,
+            lanes
+        ));
+    }
+
+    Some(format!(
+        "{{\"type\": \"FeatureCollection\", \"features\": [\n  {}\n]}}\n",
+        features.join(",\n  ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osm_import::LocalProjection;
+
+    #[test]
+    fn write_dot_classifies_nodes_and_highlights_path() {
+        let graph = NodeGraph::create_t_junction();
+        let dot = write_dot(&graph, Some((1, 2)));
+
+        assert!(dot.starts_with("digraph network {"));
+        assert!(dot.contains("    1 [kind=source, fillcolor=green, pos=\"1,-10!\"];"));
+        assert!(dot.contains("    0 [kind=dest, fillcolor=red, pos=\"-1,-10!\"];"));
+        assert!(dot.contains("    6 [kind=internal, fillcolor=blue, pos=\"-1,-1!\"];"));
+        // The path 1 -> 7 -> 8 -> 2 is highlighted, other edges are not
+        assert!(dot.contains("    1 -> 7 [color=magenta, penwidth=3];"));
+        assert!(dot.contains("    7 -> 8 [color=magenta, penwidth=3];"));
+        assert!(dot.contains("    8 -> 2 [color=magenta, penwidth=3];"));
+        assert!(dot.contains("    7 -> 5;"));
+        assert_eq!(dot.matches("->").count(), graph.edges.len());
+    }
+
+    #[test]
+    fn write_geojson_contains_every_node_and_edge() {
+        let mut graph = NodeGraph::create_highway_merge();
+        // Networks without a place on the earth can't be written as GeoJSON
+        assert_eq!(write_geojson(&graph, None), None);
+
+        graph.projection = Some(LocalProjection::new(0., 0.));
+        let geojson = write_geojson(&graph, None).unwrap();
+        assert_eq!(geojson.matches(r#""type": "Point""#).count(), 8);
+        assert_eq!(geojson.matches(r#""type": "LineString""#).count(), 7);
+        // Coordinates are longitude then latitude. Node 5 is 16 m west and
+        // 6 m south of the origin, which at the equator is 0.0001439 and
+        // 0.0000540 degrees.
+        assert!(geojson.contains(
+            r#"{"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[-0.0001439, -0.0000540], [-0.0000540, 0.0000000]]}, "properties": {"source": 5, "dest": 2, "highlighted": false, "speed_limit": null, "lanes": null}}"#
+        ));
+        assert!(!geojson.contains(r#""highlighted": true"#));
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::{self, Write},
+};
+
+use crate::{node_graph::NodeGraph, trip_log::TripRecord};
+
+type Edge = (usize, usize);
+
+// HCM level of service for unsignalized intersections, graded by the average
+// control delay per vehicle
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LevelOfService {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl LevelOfService {
+    pub fn from_delay(delay_seconds: f32) -> Self {
+        if delay_seconds <= 10. {
+            LevelOfService::A
+        } else if delay_seconds <= 15. {
+            LevelOfService::B
+        } else if delay_seconds <= 25. {
+            LevelOfService::C
+        } else if delay_seconds <= 35. {
+            LevelOfService::D
+        } else if delay_seconds <= 50. {
+            LevelOfService::E
+        } else {
+            LevelOfService::F
+        }
+    }
+}
+
+impl fmt::Display for LevelOfService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded so the letter can be aligned in tables
+        f.pad(&format!("{:?}", self))
+    }
+}
+
+// The average control delay of a group of vehicles
+#[derive(Clone, Debug, PartialEq)]
+pub struct DelaySummary {
+    pub vehicle_count: usize,
+    // The vehicles counted which hadn't passed through the junction by the
+    // end of the run, with their delay so far
+    pub unfinished_count: usize,
+    pub mean_delay: f32,
+    pub level_of_service: LevelOfService,
+}
+
+impl DelaySummary {
+    fn from_delays(delays: &[VehicleDelay]) -> Self {
+        let mean_delay =
+            delays.iter().map(|delay| delay.seconds).sum::<f32>() / delays.len() as f32;
+        DelaySummary {
+            vehicle_count: delays.len(),
+            unfinished_count: delays.iter().filter(|delay| !delay.is_finished).count(),
+            mean_delay,
+            level_of_service: LevelOfService::from_delay(mean_delay),
+        }
+    }
+}
+
+// The delay of one vehicle through a junction
+#[derive(Clone, Copy, Debug)]
+struct VehicleDelay {
+    seconds: f32,
+    is_finished: bool,
+}
+
+// Control delay at a junction. Approaches are the edges leading into the
+// junction and movements are approach/exit edge pairs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JunctionDelay {
+    // The nodes making up the junction, sorted by index
+    pub nodes: Vec<usize>,
+    pub overall: DelaySummary,
+    pub approaches: BTreeMap<Edge, DelaySummary>,
+    pub movements: BTreeMap<(Edge, Edge), DelaySummary>,
+}
+
+// Finds the nodes where routes merge or split
+pub fn junction_nodes(node_graph: &NodeGraph) -> HashSet<usize> {
+    let mut in_degrees: HashMap<usize, usize> = HashMap::new();
+    let mut out_degrees: HashMap<usize, usize> = HashMap::new();
+    for (source, dest) in node_graph.edges.iter() {
+        *out_degrees.entry(*source).or_default() += 1;
+        *in_degrees.entry(*dest).or_default() += 1;
+    }
+    (0..node_graph.nodes.len())
+        .filter(|node| {
+            !node_graph.source_nodes.contains(node) && !node_graph.dest_nodes.contains(node)
+        })
+        .filter(|node| in_degrees.get(node) > Some(&1) || out_degrees.get(node) > Some(&1))
+        .collect()
+}
+
+// Finds the junctions of the graph. Connected junction nodes are grouped into
+// a single junction, e.g. nodes 8-11 of the default intersection. Junctions
+// are ordered by their lowest node.
+pub fn find_junctions(node_graph: &NodeGraph) -> Vec<Vec<usize>> {
+    let junction_nodes = junction_nodes(node_graph);
+
+    // Group junction nodes connected in either direction
+    let mut neighbours: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (source, dest) in node_graph.edges.iter() {
+        if junction_nodes.contains(source) && junction_nodes.contains(dest) {
+            neighbours.entry(*source).or_default().push(*dest);
+            neighbours.entry(*dest).or_default().push(*source);
+        }
+    }
+    let mut sorted_junction_nodes: Vec<usize> = junction_nodes.iter().copied().collect();
+    sorted_junction_nodes.sort();
+    let mut visited = HashSet::new();
+    let mut junctions = Vec::new();
+    for node in sorted_junction_nodes {
+        if !visited.insert(node) {
+            continue;
+        }
+        let mut junction = vec![node];
+        let mut stack = vec![node];
+        while let Some(current) = stack.pop() {
+            for neighbour in neighbours.get(&current).into_iter().flatten() {
+                if visited.insert(*neighbour) {
+                    junction.push(*neighbour);
+                    stack.push(*neighbour);
+                }
+            }
+        }
+        junction.sort();
+        junctions.push(junction);
+    }
+    junctions
+}
+
+// Calculates the control delay of every trip through each junction. A
+// vehicle's delay is the time it took from the start of the approach edge to
+// the end of the exit edge, minus the time it would have taken driving at its
+// free flow speed. Unfinished trips are the vehicles still on the network or
+// waiting to enter it, recorded up to the end of the run. Those on or queued
+// for an approach count with their delay so far, so a junction which stops
+// letting vehicles through isn't graded only by the few that made it.
+pub fn calculate_intersection_delay(
+    node_graph: &NodeGraph,
+    records: &[TripRecord],
+    unfinished_trips: &[TripRecord],
+) -> Vec<JunctionDelay> {
+    let junctions = find_junctions(node_graph);
+    let junction_by_node: HashMap<usize, usize> = junctions
+        .iter()
+        .enumerate()
+        .flat_map(|(junction, nodes)| nodes.iter().map(move |node| (*node, junction)))
+        .collect();
+
+    let edge_length = |route: &[usize], node_index: usize| {
+        let source = &node_graph.nodes[route[node_index]];
+        let dest = &node_graph.nodes[route[node_index + 1]];
+        source.position.distance(dest.position)
+    };
+    let mut movement_delays: Vec<BTreeMap<(Edge, Edge), Vec<VehicleDelay>>> =
+        vec![BTreeMap::new(); junctions.len()];
+    let trips = records
+        .iter()
+        .map(|record| (record, true))
+        .chain(unfinished_trips.iter().map(|record| (record, false)));
+    for (record, is_finished) in trips {
+        // Trips logged before node times were tracked can't be measured
+        let reached_nodes = record.node_times.len();
+        if (is_finished && reached_nodes != record.route.len())
+            || reached_nodes == 0
+            || reached_nodes > record.route.len()
+            || record.free_flow_speed <= 0.
+        {
+            continue;
+        }
+
+        let mut index = 1;
+        while index + 1 < record.route.len() {
+            let Some(junction) = junction_by_node.get(&record.route[index]).copied() else {
+                index += 1;
+                continue;
+            };
+
+            // Find the last node of the route inside this junction
+            let entry_index = index;
+            while index + 2 < record.route.len()
+                && junction_by_node.get(&record.route[index + 1]) == Some(&junction)
+            {
+                index += 1;
+            }
+            let exit_index = index;
+
+            let first = entry_index - 1;
+            let last = exit_index + 1;
+            // Junctions the vehicle hasn't reached the approach of yet
+            if first >= reached_nodes {
+                break;
+            }
+            let (travel_time, distance, has_passed) = if last < reached_nodes {
+                let distance: f32 = (first..last)
+                    .map(|node_index| edge_length(&record.route, node_index))
+                    .sum();
+                let travel_time = record.node_times[last] - record.node_times[first];
+                (travel_time, distance, true)
+            } else {
+                // Still on its way through, measured up to where it is now
+                let distance_to_approach: f32 = (0..first)
+                    .map(|node_index| edge_length(&record.route, node_index))
+                    .sum();
+                let travel_time = record.arrival_time - record.node_times[first];
+                (travel_time, record.distance - distance_to_approach, false)
+            };
+            let delay = (travel_time - distance.max(0.) / record.free_flow_speed).max(0.);
+
+            let approach = (record.route[first], record.route[entry_index]);
+            let exit = (record.route[exit_index], record.route[last]);
+            movement_delays[junction]
+                .entry((approach, exit))
+                .or_default()
+                .push(VehicleDelay {
+                    seconds: delay,
+                    is_finished: has_passed,
+                });
+            index += 1;
+        }
+    }
+
+    junctions
+        .into_iter()
+        .zip(movement_delays)
+        .filter(|(_, movements)| !movements.is_empty())
+        .map(|(nodes, movements)| {
+            let mut approach_delays: BTreeMap<Edge, Vec<VehicleDelay>> = BTreeMap::new();
+            for ((approach, _), delays) in movements.iter() {
+                approach_delays.entry(*approach).or_default().extend(delays);
+            }
+            let all_delays: Vec<VehicleDelay> = movements.values().flatten().copied().collect();
+            JunctionDelay {
+                nodes,
+                overall: DelaySummary::from_delays(&all_delays),
+                approaches: approach_delays
+                    .into_iter()
+                    .map(|(approach, delays)| (approach, DelaySummary::from_delays(&delays)))
+                    .collect(),
+                movements: movements
+                    .into_iter()
+                    .map(|(movement, delays)| (movement, DelaySummary::from_delays(&delays)))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+// Writes a plain text table of the delay at each junction, with a row per
+// approach followed by a row per movement. Unfinished vehicles are included
+// in the vehicle counts.
+pub fn write_table(junction_delays: &[JunctionDelay], writer: &mut impl Write) -> io::Result<()> {
+    let write_row = |writer: &mut dyn Write, label: &str, summary: &DelaySummary| {
+        writeln!(
+            writer,
+            "  {:<24}{:>9}{:>12}{:>12.1}{:>6}",
+            label,
+            summary.vehicle_count,
+            summary.unfinished_count,
+            summary.mean_delay,
+            summary.level_of_service
+        )
+    };
+
+    if junction_delays.is_empty() {
+        writeln!(writer, "No vehicles reached a junction")?;
+    }
+    for junction_delay in junction_delays {
+        let nodes: Vec<String> = junction_delay.nodes.iter().map(usize::to_string).collect();
+        writeln!(writer, "Junction {}", nodes.join(" "))?;
+        writeln!(
+            writer,
+            "  {:<24}{:>9}{:>12}{:>12}{:>6}",
+            "", "vehicles", "unfinished", "delay (s)", "LOS"
+        )?;
+        write_row(writer, "All", &junction_delay.overall)?;
+        for (approach, summary) in junction_delay.approaches.iter() {
+            let label = format!("From {}->{}", approach.0, approach.1);
+            write_row(writer, &label, summary)?;
+        }
+        for ((approach, exit), summary) in junction_delay.movements.iter() {
+            let label = format!("{}->{} to {}->{}", approach.0, approach.1, exit.0, exit.1);
+            write_row(writer, &label, summary)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trip(route: Vec<usize>, node_times: Vec<f32>) -> TripRecord {
+        TripRecord {
+            vehicle_id: 0,
+            source_node: route[0],
+            dest_node: route[route.len() - 1],
+            spawn_time: node_times[0],
+            arrival_time: node_times[node_times.len() - 1],
+            route,
+            distance: 0.,
+            stopped_time: 0.,
+            stop_count: 0,
+            node_times,
+            free_flow_speed: 6.,
+            vehicle_type: "car".to_string(),
+        }
+    }
+
+    #[test]
+    fn find_junctions_groups_connected_junction_nodes() {
+        assert_eq!(
+            find_junctions(&NodeGraph::create()),
+            vec![vec![8, 9, 10, 11]]
+        );
+        assert_eq!(
+            find_junctions(&NodeGraph::create_t_junction()),
+            vec![vec![6, 7, 8, 9]]
+        );
+        // The mainline nodes between the ramps aren't junctions
+        assert_eq!(
+            find_junctions(&NodeGraph::create_highway_merge()),
+            vec![vec![2], vec![4]]
+        );
+    }
+
+    #[test]
+    fn calculate_intersection_delay_compares_to_free_flow() {
+        let graph = NodeGraph::create_highway_merge();
+        // Mainline edges are 12 meters long, so each takes 2 seconds at free
+        // flow. The ramp edges take ramp_time.
+        let ramp_time = 136f32.sqrt() / 6.;
+        let records = [
+            // No delay at the merge, 6 seconds of delay at the diverge
+            trip(vec![0, 1, 2, 3, 4, 6], vec![0., 2., 4., 6., 8., 16.]),
+            // 20 seconds of delay at the merge, 2 at the diverge
+            trip(
+                vec![0, 1, 2, 3, 4, 7],
+                vec![0., 2., 14., 26., 28., 30. + ramp_time],
+            ),
+            // 3 seconds of delay at the merge. Vehicles are timed by update
+            // so this one appears faster than free flow at the diverge.
+            trip(
+                vec![5, 2, 3, 4, 7],
+                vec![
+                    0.,
+                    ramp_time,
+                    ramp_time + 5.,
+                    ramp_time + 7.,
+                    2. * ramp_time + 6.5,
+                ],
+            ),
+        ];
+        let junction_delays = calculate_intersection_delay(&graph, &records, &[]);
+        assert_eq!(junction_delays.len(), 2);
+        let assert_delay = |summary: &DelaySummary, expected_delay: f32| {
+            assert!((summary.mean_delay - expected_delay).abs() < 1e-4);
+        };
+
+        let merge = &junction_delays[0];
+        assert_eq!(merge.nodes, vec![2]);
+        assert_eq!(merge.overall.vehicle_count, 3);
+        assert_eq!(
+            merge.approaches[&(1, 2)],
+            DelaySummary {
+                vehicle_count: 2,
+                unfinished_count: 0,
+                mean_delay: 10.,
+                level_of_service: LevelOfService::A,
+            }
+        );
+        assert_delay(&merge.approaches[&(5, 2)], 3.);
+        assert_eq!(
+            merge.movements[&((1, 2), (2, 3))].level_of_service,
+            LevelOfService::A
+        );
+
+        let diverge = &junction_delays[1];
+        assert_eq!(diverge.nodes, vec![4]);
+        assert_delay(&diverge.movements[&((3, 4), (4, 6))], 6.);
+        // Delays are never negative
+        assert_delay(&diverge.movements[&((3, 4), (4, 7))], 1.);
+        assert_delay(&diverge.overall, 8. / 3.);
+    }
+
+    #[test]
+    fn calculate_intersection_delay_counts_vehicles_still_waiting() {
+        let graph = NodeGraph::create_highway_merge();
+        let records = [trip(vec![0, 1, 2, 3, 4, 6], vec![0., 2., 4., 6., 8., 10.])];
+        let mut unfinished_trips = [
+            // 3 meters along the approach after reaching it at 2 seconds
+            trip(vec![0, 1, 2, 3, 4, 6], vec![0., 2.]),
+            // Queued at the on-ramp since 10 seconds
+            trip(vec![5, 2, 3, 4, 7], vec![10.]),
+            // Not on an approach yet
+            trip(vec![0, 1, 2, 3, 4, 6], vec![61.]),
+        ];
+        unfinished_trips[0].distance = 15.;
+        for unfinished_trip in unfinished_trips.iter_mut() {
+            unfinished_trip.arrival_time = 62.;
+        }
+        let junction_delays = calculate_intersection_delay(&graph, &records, &unfinished_trips);
+
+        let merge = &junction_delays[0];
+        assert_eq!(merge.nodes, vec![2]);
+        assert_eq!(merge.overall.vehicle_count, 3);
+        assert_eq!(merge.overall.unfinished_count, 2);
+        assert!((merge.approaches[&(1, 2)].mean_delay - 59.5 / 2.).abs() < 1e-4);
+        assert_eq!(
+            merge.approaches[&(5, 2)],
+            DelaySummary {
+                vehicle_count: 1,
+                unfinished_count: 1,
+                mean_delay: 52.,
+                level_of_service: LevelOfService::F,
+            }
+        );
+        assert_eq!(merge.overall.level_of_service, LevelOfService::E);
+        // Only the finished trip reached the diverge
+        assert_eq!(junction_delays[1].overall.vehicle_count, 1);
+    }
+
+    #[test]
+    fn level_of_service_uses_hcm_thresholds() {
+        let grades: Vec<LevelOfService> = [0., 10., 12., 20., 30., 45., 60.]
+            .into_iter()
+            .map(LevelOfService::from_delay)
+            .collect();
+        assert_eq!(
+            grades,
+            [
+                LevelOfService::A,
+                LevelOfService::A,
+                LevelOfService::B,
+                LevelOfService::C,
+                LevelOfService::D,
+                LevelOfService::E,
+                LevelOfService::F,
+            ]
+        );
+    }
+}
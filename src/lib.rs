@@ -0,0 +1,83 @@
+// A traffic simulator with an optional Bevy viewer. The road network is a
+// NodeGraph of directed edges, vehicles drive routes through it reserving
+// the junctions they cross, and trips, edge flows and junction delays are
+// measured as they go.
+//
+// Simulations can be embedded in other programs by creating a Simulation and
+// stepping it for as long as needed:
+//
+//     let mut simulation = Simulation::new(NodeGraph::create_t_junction(), &SimulationConfig::default());
+//     simulation.run_for(Duration::from_secs(600));
+//     let trip_log = &simulation.trip_log;
+//
+// A SimulationSnapshot captures a simulation, or the app, so a warmed up
+// network can be saved and any number of runs branched from it.
+//
+// The simulation core doesn't depend on Bevy and builds with
+// --no-default-features. The default "bevy" feature adds the window: ECS
+// systems which run the simulation in the app, rendering and editing. The
+// traffic-rs binary adds the command line on top, its batch commands work
+// with or without the window.
+
+#[cfg(feature = "bevy")]
+pub mod camera_controls;
+pub mod crosswalks;
+pub mod edge_metrics;
+pub mod emergency_policy;
+pub mod entry_queues;
+pub mod fundamental_diagram;
+pub mod graph_export;
+pub mod intersection_delay;
+#[cfg(feature = "bevy")]
+pub mod network_editor;
+pub mod network_file;
+pub mod node_graph;
+#[cfg(feature = "bevy")]
+pub mod node_graph_renderer;
+pub mod node_graph_validation;
+pub mod osm_import;
+pub mod parking;
+#[cfg(feature = "bevy")]
+pub mod replay;
+#[cfg(feature = "bevy")]
+pub mod road_mesh;
+pub mod scenario;
+#[cfg(feature = "bevy")]
+pub mod selection;
+pub mod simulation;
+pub mod simulation_clock;
+pub mod simulation_rng;
+pub mod snapshot;
+pub mod sumo_network;
+#[cfg(feature = "bevy")]
+pub mod trajectory_recording;
+pub mod transit;
+pub mod trip_log;
+pub mod vehicle_id_generator;
+pub mod vehicle_spawn_limiter;
+pub mod vehicle_types;
+pub mod vehicles;
+
+pub use edge_metrics::EdgeMetrics;
+pub use entry_queues::EntryQueues;
+pub use intersection_delay::calculate_intersection_delay;
+pub use node_graph::{Node, NodeGraph};
+#[cfg(feature = "bevy")]
+pub use node_graph_validation::report_network_issues;
+pub use scenario::Scenario;
+pub use simulation::{Simulation, SimulationConfig};
+pub use snapshot::SimulationSnapshot;
+pub use trip_log::{TripLog, TripRecord};
+pub use vehicle_types::{VehicleType, VehicleTypes};
+pub use vehicles::Vehicle;
+
+// Loads a road network file, the format is chosen by the file extension
+pub fn load_network(path: &str) -> Result<NodeGraph, Box<dyn std::error::Error>> {
+    if path.ends_with(".net.xml") {
+        Ok(sumo_network::load_sumo_network(path)?)
+    } else if path.ends_with(".json") {
+        Ok(network_file::load_network_file(path)?)
+    } else {
+        Ok(osm_import::load_osm(path)?)
+    }
+}
@@ -1,51 +1,583 @@
-use std::time::Duration;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+#[cfg(feature = "bevy")]
 use bevy::prelude::*;
-use node_graph_renderer::HighlightedEdgeGizmos;
+use clap::{Args, Parser, Subcommand};
+#[cfg(feature = "bevy")]
+use traffic_rs::{
+    camera_controls, edge_metrics, entry_queues, network_editor,
+    node_graph_renderer::{self, HighlightedEdgeGizmos},
+    parking, replay, report_network_issues, road_mesh, selection, simulation_clock, snapshot,
+    trajectory_recording, transit, trip_log, vehicle_id_generator, vehicle_spawn_limiter, vehicles,
+};
+use traffic_rs::{
+    crosswalks, fundamental_diagram, graph_export, intersection_delay, node_graph,
+    scenario::Scenario,
+    simulation::{Simulation, SimulationConfig},
+    simulation_rng,
+    snapshot::SimulationSnapshot,
+    sumo_network,
+};
 
-mod node_graph;
-mod node_graph_renderer;
-mod vehicle_id_generator;
-mod vehicle_spawn_limiter;
-mod vehicles;
+// The period edge metrics are aggregated over
+#[cfg(feature = "bevy")]
+const METRICS_BIN_DURATION: Duration = Duration::from_secs(60);
+
+// A headless run is reported as locked up if no vehicle arrives over this
+// long while the entry queues grow
+const STALL_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Parser)]
+#[command(version, about = "A traffic simulator")]
+struct Cli {
+    // Without a command the demo intersection is simulated in a window
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Simulate a scenario, in a window unless --headless is given")]
+    Run(RunArgs),
+    #[command(about = "Check that a scenario's network can be simulated")]
+    Validate(ScenarioArgs),
+    #[command(
+        about = "Write a scenario's network as SUMO and GraphViz DOT files, and GeoJSON for map imports"
+    )]
+    Export {
+        #[command(flatten)]
+        scenario: ScenarioArgs,
+        #[arg(long, default_value = ".", help = "Directory to write the files to")]
+        out: PathBuf,
+    },
+    #[cfg(feature = "bevy")]
+    #[command(about = "Play back a recording made with the R key without simulating")]
+    Replay {
+        #[arg(default_value = "recording.traj", help = "Recording to play back")]
+        path: PathBuf,
+    },
+    #[command(about = "Sweep demand on a ring road and write the resulting flow/density points")]
+    FundamentalDiagram {
+        #[arg(
+            long,
+            default_value = "fundamental_diagram.csv",
+            help = "File to write the points to"
+        )]
+        out: PathBuf,
+        #[arg(long, help = "Seeds every run so the diagram can be repeated")]
+        seed: Option<u64>,
+    },
+}
+
+// Where the network and demand to simulate come from. The demo intersection
+// is used if neither is given.
+#[derive(Args)]
+struct ScenarioArgs {
+    #[arg(
+        long,
+        conflicts_with = "network",
+        help = "Scenario file naming the network, transit lines and parking areas and the demand"
+    )]
+    scenario: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Road network file (OSM, SUMO or JSON) to simulate with the default demand"
+    )]
+    network: Option<PathBuf>,
+}
+
+impl ScenarioArgs {
+    // Loads the scenario, exiting if it can't be loaded
+    fn load(&self) -> Scenario {
+        let scenario = match (&self.scenario, &self.network) {
+            (Some(path), _) => Scenario::load(path),
+            (None, Some(path)) => Scenario::from_network(path),
+            (None, None) => Ok(Scenario::default()),
+        };
+        scenario.unwrap_or_else(|error| {
+            eprintln!("Failed to load the scenario: {}", error);
+            std::process::exit(1);
+        })
+    }
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    scenario: ScenarioArgs,
+    #[arg(
+        long,
+        default_value_t = 600,
+        requires = "headless",
+        help = "Seconds of simulation time to run for"
+    )]
+    duration: u64,
+    #[arg(long, help = "Seeds the simulation so runs can be repeated")]
+    seed: Option<u64>,
+    #[arg(
+        long,
+        default_value = ".",
+        requires = "headless",
+        help = "Directory to write the results to"
+    )]
+    out: PathBuf,
+    #[arg(
+        long,
+        help = "Simulate as fast as possible without a window, then write the results"
+    )]
+    headless: bool,
+    #[arg(
+        long,
+        requires = "headless",
+        conflicts_with_all = ["scenario", "network"],
+        help = "Snapshot to carry on from instead of starting the scenario, --seed reseeds it"
+    )]
+    from_snapshot: Option<PathBuf>,
+    #[arg(
+        long,
+        requires = "headless",
+        help = "File to save a snapshot of the end of the run to, so later runs can carry on from it"
+    )]
+    save_snapshot: Option<PathBuf>,
+}
 
 fn main() {
-    let graph = node_graph::NodeGraph::create();
+    match Cli::parse().command {
+        None => run_window(Scenario::default()),
+        Some(Command::Run(args)) => {
+            if args.headless {
+                run_headless(
+                    headless_simulation(&args),
+                    Duration::from_secs(args.duration),
+                    &args.out,
+                    args.save_snapshot.as_deref(),
+                );
+            } else {
+                let mut scenario = args.scenario.load();
+                scenario.config.seed = args.seed;
+                run_window(scenario);
+            }
+        }
+        Some(Command::Validate(args)) => validate(&args.load()),
+        Some(Command::Export { scenario, out }) => export(&scenario.load().node_graph, &out),
+        #[cfg(feature = "bevy")]
+        Some(Command::Replay { path }) => {
+            match trajectory_recording::TrajectoryRecording::load(&path) {
+                Ok(recording) => run_replay(recording),
+                Err(error) => {
+                    eprintln!("Failed to load {}: {}", path.display(), error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::FundamentalDiagram { out, seed }) => {
+            let config = fundamental_diagram::FundamentalDiagramConfig {
+                seed,
+                ..fundamental_diagram::FundamentalDiagramConfig::default()
+            };
+            match fundamental_diagram::run_ring_road_experiment(&config, &out) {
+                Ok(points) => println!("Wrote {} points to {}", points.len(), out.display()),
+                Err(error) => {
+                    eprintln!("Failed to write {}: {}", out.display(), error);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
+
+// Simulates the scenario in a window with rendering and editing
+#[cfg(feature = "bevy")]
+fn run_window(scenario: Scenario) {
+    let Scenario {
+        node_graph,
+        transit_lines,
+        parking_areas,
+        config,
+    } = scenario;
+    let rng = config.seed.map_or_else(
+        simulation_rng::SimulationRng::default,
+        simulation_rng::SimulationRng::from_seed,
+    );
     let graph_renderer = node_graph_renderer::NodeGraphRenderer::default();
-    let spawn_interval = Duration::from_millis(200);
-    let spawn_limiter = vehicle_spawn_limiter::VehicleSpawnLimiter::new(spawn_interval);
+    let spawn_limiter = vehicle_spawn_limiter::VehicleSpawnLimiter::new(config.spawn_interval);
     App::new()
         .add_plugins(DefaultPlugins)
         .init_gizmo_group::<HighlightedEdgeGizmos>()
-        .add_systems(Startup, setup)
+        .add_systems(Startup, (setup, camera_controls::spawn_camera))
         .add_systems(Startup, node_graph_renderer::configure_gizmos)
-        .add_systems(Update, vehicles::spawn_vehicle)
-        .add_systems(Update, vehicles::move_vehicles)
+        .add_systems(Startup, validate_network)
+        .add_systems(Startup, node_graph_renderer::spawn_heatmap_legend)
+        .add_systems(Startup, selection::spawn_selection_panel)
+        .add_systems(PreUpdate, simulation_clock::advance_simulation_clock)
+        .add_systems(Startup, network_editor::spawn_network_editor_help)
+        .add_systems(
+            Update,
+            (
+                transit::dispatch_transit_vehicles.run_if(not(network_editor::editor_active)),
+                vehicles::spawn_vehicle.run_if(not(network_editor::editor_active)),
+                parking::depart_parked_vehicles.run_if(not(network_editor::editor_active)),
+                crosswalks::update_crosswalk_placement,
+                crosswalks::update_crosswalks.run_if(not(network_editor::editor_active)),
+            )
+                .chain()
+                .before(vehicles::move_vehicles),
+        )
+        .add_systems(
+            Update,
+            vehicles::move_vehicles.run_if(not(network_editor::editor_active)),
+        )
+        .add_systems(
+            Update,
+            (
+                crosswalks::toggle_crosswalks,
+                crosswalks::show_crosswalks,
+                parking::show_parking_areas,
+            ),
+        )
+        .add_systems(Update, vehicles::attach_vehicle_meshes)
         .add_systems(Update, node_graph_renderer::show_node_graph)
-        .insert_resource(graph)
+        .add_systems(Update, road_mesh::update_road_meshes)
+        .add_systems(
+            Update,
+            (
+                camera_controls::control_camera,
+                camera_controls::fit_camera_to_network.run_if(not(network_editor::editor_active)),
+                camera_controls::update_camera,
+            )
+                .chain(),
+        )
+        .add_systems(Update, node_graph_renderer::toggle_edge_color_mode)
+        .add_systems(
+            Update,
+            node_graph_renderer::update_edge_congestion.after(vehicles::move_vehicles),
+        )
+        .add_systems(Update, node_graph_renderer::update_heatmap_legend)
+        .add_systems(
+            Update,
+            selection::pick_with_mouse.run_if(not(network_editor::editor_active)),
+        )
+        .add_systems(Update, selection::update_vehicle_highlight)
+        .add_systems(
+            Update,
+            selection::update_selection_panel.after(vehicles::move_vehicles),
+        )
+        .add_systems(Update, export_network)
+        .add_systems(Update, (snapshot::save_snapshot, snapshot::load_snapshot))
+        .add_systems(Update, trajectory_recording::toggle_trajectory_recording)
+        .add_systems(
+            Update,
+            (
+                network_editor::toggle_network_editor,
+                (
+                    network_editor::edit_network,
+                    network_editor::show_network_editor,
+                    network_editor::save_edited_network,
+                )
+                    .run_if(network_editor::editor_active),
+                network_editor::update_network_editor_help,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            trajectory_recording::record_trajectories.after(vehicles::move_vehicles),
+        )
+        .add_systems(Last, trip_log::save_trip_log)
+        .add_systems(Last, transit::save_transit_log)
+        .add_systems(Last, parking::save_parking_log)
+        .add_systems(Last, edge_metrics::save_edge_metrics)
+        .add_systems(Last, trajectory_recording::save_trajectory_recording)
+        .insert_resource(node_graph)
+        .insert_resource(simulation_clock::SimulationClock::default())
+        .insert_resource(rng)
         .insert_resource(graph_renderer)
         .insert_resource(spawn_limiter)
         .insert_resource(vehicle_id_generator::VehicleIdGenerator::default())
+        .insert_resource(entry_queues::EntryQueues::default())
+        .insert_resource(config.vehicle_types)
+        .insert_resource(config.emergency_policy)
+        .insert_resource(config.crosswalk_settings)
+        .insert_resource(crosswalks::CrosswalkPlacement::default())
+        .insert_resource(trip_log::TripLog::default())
+        .insert_resource(transit_lines)
+        .insert_resource(parking_areas)
+        .insert_resource(parking::ParkingLog::default())
+        .insert_resource(transit::TransitLog::default())
+        .insert_resource(edge_metrics::EdgeMetrics::new(METRICS_BIN_DURATION))
+        .insert_resource(trajectory_recording::TrajectoryRecorder::default())
+        .insert_resource(network_editor::NetworkEditor::default())
         .run();
 }
 
-#[derive(Component)]
-struct Ground;
+// Builds without Bevy have no window, so only headless runs are possible
+#[cfg(not(feature = "bevy"))]
+fn run_window(_scenario: Scenario) {
+    eprintln!("Built without the bevy feature, only headless runs are available");
+    std::process::exit(1);
+}
 
+// Shows a recording through the normal renderer, none of the simulation
+// systems are run
+#[cfg(feature = "bevy")]
+fn run_replay(recording: trajectory_recording::TrajectoryRecording) {
+    let graph = recording.to_node_graph();
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .init_gizmo_group::<HighlightedEdgeGizmos>()
+        .add_systems(Startup, (setup, camera_controls::spawn_camera))
+        .add_systems(Startup, node_graph_renderer::configure_gizmos)
+        .add_systems(Startup, replay::spawn_replay_status)
+        .add_systems(Update, node_graph_renderer::show_node_graph)
+        .add_systems(Update, road_mesh::update_road_meshes)
+        .add_systems(
+            Update,
+            (
+                camera_controls::control_camera,
+                camera_controls::fit_camera_to_network,
+                camera_controls::update_camera,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                replay::control_replay,
+                replay::advance_replay,
+                replay::show_replay_vehicles,
+                replay::update_replay_status,
+            )
+                .chain(),
+        )
+        .insert_resource(graph)
+        .insert_resource(node_graph_renderer::NodeGraphRenderer::default())
+        .insert_resource(replay::ReplayState::new(recording))
+        .run();
+}
+
+// Creates the simulation for a headless run, from the scenario or carrying on
+// from a snapshot. Exits if either can't be loaded.
+fn headless_simulation(args: &RunArgs) -> Simulation {
+    let Some(path) = &args.from_snapshot else {
+        let mut scenario = args.scenario.load();
+        scenario.config.seed = args.seed;
+        for issue in scenario.node_graph.validate().issues.iter() {
+            eprintln!("Network issue: {}", issue);
+        }
+        return scenario.into_simulation();
+    };
+
+    let simulation = SimulationSnapshot::load(path)
+        .and_then(|snapshot| snapshot.into_simulation(&SimulationConfig::default()));
+    let mut simulation = simulation.unwrap_or_else(|error| {
+        eprintln!("Failed to load {}: {}", path.display(), error);
+        std::process::exit(1);
+    });
+    // Branches of the same snapshot only differ if they are given new seeds
+    if let Some(seed) = args.seed {
+        simulation.rng = simulation_rng::SimulationRng::from_seed(seed);
+    }
+    simulation
+}
+
+// Simulates without a window for the given time, writes the trip log, edge
+// metrics and any transit and parking logs to the output directory and
+// prints the delay at each junction, then saves a snapshot of the end of the
+// run if asked to. Exits with an error if any of the results couldn't be
+// saved or the network locked up.
+fn run_headless(
+    mut simulation: Simulation,
+    duration: Duration,
+    out: &Path,
+    snapshot_path: Option<&Path>,
+) {
+    let has_transit = !simulation.transit_lines.lines.is_empty();
+    let has_parking = !simulation.parking_areas.areas.is_empty();
+    let stalled_at = simulation.run_watching_for_stall(duration, STALL_WINDOW);
+    if let Some(stalled_at) = stalled_at {
+        eprintln!(
+            "Warning: no vehicles arrived for {} seconds from {:.0} s while the entry queues grew, the network has locked up",
+            STALL_WINDOW.as_secs(),
+            stalled_at
+        );
+    }
+
+    let node_graph = &simulation.node_graph;
+    let trip_log = &simulation.trip_log;
+    let mut save_failed = false;
+    if let Err(error) = trip_log.save(out) {
+        eprintln!("Failed to save trip log: {}", error);
+        save_failed = true;
+    }
+    if let Err(error) = simulation.edge_metrics.save(out, node_graph) {
+        eprintln!("Failed to save edge metrics: {}", error);
+        save_failed = true;
+    }
+    println!(
+        "Simulated {} seconds, {} trips completed",
+        duration.as_secs(),
+        trip_log.records.len()
+    );
+
+    let junction_delays = intersection_delay::calculate_intersection_delay(
+        node_graph,
+        &trip_log.records,
+        &simulation.unfinished_trips(),
+    );
+    if let Err(error) = intersection_delay::write_table(&junction_delays, &mut std::io::stdout()) {
+        eprintln!("Failed to write the delay table: {}", error);
+    }
+    println!();
+    if let Err(error) = trip_log.write_vehicle_type_table(&mut std::io::stdout()) {
+        eprintln!("Failed to write the vehicle type table: {}", error);
+    }
+    println!();
+    if let Err(error) = simulation.entry_queues.write_table(&mut std::io::stdout()) {
+        eprintln!("Failed to write the entry queue table: {}", error);
+    }
+
+    let mut crosswalks: Vec<&crosswalks::Crosswalk> = simulation.crosswalks.iter().collect();
+    if !crosswalks.is_empty() {
+        crosswalks.sort_by_key(|crosswalk| crosswalk.edge);
+        println!();
+        if let Err(error) = crosswalks::write_table(crosswalks, &mut std::io::stdout()) {
+            eprintln!("Failed to write the crosswalk table: {}", error);
+        }
+    }
+
+    if has_transit {
+        let transit_log = &simulation.transit_log;
+        if let Err(error) = transit_log.save(out) {
+            eprintln!("Failed to save transit log: {}", error);
+            save_failed = true;
+        }
+        println!();
+        if let Err(error) = transit_log.write_punctuality_table(&mut std::io::stdout()) {
+            eprintln!("Failed to write the punctuality table: {}", error);
+        }
+    }
+
+    if has_parking {
+        let parking_log = &simulation.parking_log;
+        if let Err(error) = parking_log.save(out) {
+            eprintln!("Failed to save parking log: {}", error);
+            save_failed = true;
+        }
+        println!();
+        if let Err(error) =
+            parking_log.write_table(&simulation.parking_areas, &mut std::io::stdout())
+        {
+            eprintln!("Failed to write the parking table: {}", error);
+        }
+    }
+
+    if let Some(snapshot_path) = snapshot_path {
+        match SimulationSnapshot::capture(&simulation).save(snapshot_path) {
+            Ok(()) => println!("\nSaved a snapshot to {}", snapshot_path.display()),
+            Err(error) => {
+                eprintln!("Failed to save snapshot: {}", error);
+                save_failed = true;
+            }
+        }
+    }
+
+    if save_failed || stalled_at.is_some() {
+        std::process::exit(1);
+    }
+}
+
+// Prints the size of the scenario's network and any issues with it, exiting
+// with an error if there are issues. Transit lines and parking areas are
+// checked against the network when the scenario is loaded.
+fn validate(scenario: &Scenario) {
+    let node_graph = &scenario.node_graph;
+    let report = node_graph.validate();
+    println!(
+        "Network has {} nodes, {} edges and {} strongly connected components ({} with cycles)",
+        node_graph.nodes.len(),
+        node_graph.edges.len(),
+        report.strongly_connected_components.len(),
+        report.cyclic_components().count()
+    );
+    println!(
+        "Scenario has {} transit lines and {} parking areas",
+        scenario.transit_lines.lines.len(),
+        scenario.parking_areas.areas.len()
+    );
+    for issue in report.issues.iter() {
+        println!("Network issue: {}", issue);
+    }
+    if !report.is_valid() {
+        eprintln!("Network has {} issues", report.issues.len());
+        std::process::exit(1);
+    }
+}
+
+// Writes the network into the given directory, exiting if it can't be
+// written
+fn export(node_graph: &node_graph::NodeGraph, out: &Path) {
+    if let Err(error) = std::fs::create_dir_all(out) {
+        eprintln!("Failed to create {}: {}", out.display(), error);
+        std::process::exit(1);
+    }
+    for (file_name, contents) in network_exports(node_graph, None) {
+        let path = out.join(file_name);
+        match std::fs::write(&path, contents) {
+            Ok(()) => println!("Exported network to {}", path.display()),
+            Err(error) => {
+                eprintln!("Failed to export network to {}: {}", path.display(), error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// The files a network is exported as. The GraphViz DOT graph and GeoJSON
+// include the highlighted path if there is one. GeoJSON is only written for
+// networks imported from map data, since others have no place on the earth.
+fn network_exports(
+    node_graph: &node_graph::NodeGraph,
+    highlighted_path_index: Option<(usize, usize)>,
+) -> Vec<(&'static str, String)> {
+    let mut exports = vec![
+        (
+            "network.net.xml",
+            sumo_network::write_sumo_network(node_graph),
+        ),
+        (
+            "network.dot",
+            graph_export::write_dot(node_graph, highlighted_path_index),
+        ),
+    ];
+    if let Some(geojson) = graph_export::write_geojson(node_graph, highlighted_path_index) {
+        exports.push(("network.geojson", geojson));
+    }
+    exports
+}
+
+#[cfg(feature = "bevy")]
+fn validate_network(node_graph: Res<node_graph::NodeGraph>) {
+    report_network_issues(&node_graph);
+}
+
+#[cfg(feature = "bevy")]
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    // plane
+    // plane, scaled to cover the network by fit_camera_to_network
     commands.spawn((
         PbrBundle {
-            mesh: meshes.add(Plane3d::default().mesh().size(25., 25.)),
+            mesh: meshes.add(Plane3d::default().mesh().size(1., 1.)),
             material: materials.add(Color::srgb(0.3, 0.5, 0.3)),
             transform: Transform::from_xyz(0., -1., 0.),
             ..default()
         },
-        Ground,
+        camera_controls::Ground,
     ));
 
     // light
@@ -53,10 +585,25 @@ fn setup(
         transform: Transform::from_translation(Vec3::ONE).looking_at(Vec3::ZERO, Vec3::Y),
         ..default()
     });
+}
 
-    // camera
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(0., 25., 15.).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    });
+// Writes the current network to the working directory when X is pressed,
+// including the highlighted path
+#[cfg(feature = "bevy")]
+fn export_network(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    node_graph: Res<node_graph::NodeGraph>,
+    node_graph_renderer: Res<node_graph_renderer::NodeGraphRenderer>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyX) {
+        return;
+    }
+
+    let exports = network_exports(&node_graph, node_graph_renderer.highlighted_path_index);
+    for (path, contents) in exports {
+        match std::fs::write(path, contents) {
+            Ok(()) => info!("Exported network to {}", path),
+            Err(error) => error!("Failed to export network to {}: {}", path, error),
+        }
+    }
 }
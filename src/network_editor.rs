@@ -0,0 +1,330 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    edge_metrics::EdgeMetrics,
+    entry_queues::EntryQueues,
+    network_file,
+    node_graph::{Node, NodeGraph},
+    node_graph_renderer::NodeGraphRenderer,
+    node_graph_validation,
+    selection::{self, Pick},
+    vehicles::Vehicle,
+};
+
+// Where the edited network is saved
+const NETWORK_FILE_PATH: &str = "network.json";
+
+// The state of the network editor. While it is active the simulation is
+// paused and the mouse edits the network instead of selecting things.
+#[derive(Resource, Default)]
+pub struct NetworkEditor {
+    pub active: bool,
+    // The node being moved with the left mouse button
+    dragged_node: Option<usize>,
+    // The first node of an edge being drawn with the right mouse button
+    edge_start: Option<usize>,
+}
+
+pub fn editor_active(network_editor: Res<NetworkEditor>) -> bool {
+    network_editor.active
+}
+
+// Adds a node at the given position, returning the new graph and the index of
+// the node
+pub fn with_node_added(node_graph: &NodeGraph, position: Vec3) -> (NodeGraph, usize) {
+    let mut nodes = node_graph.nodes.clone();
+    nodes.push(Node { position });
+    let node = nodes.len() - 1;
+    (
+        rebuild(node_graph, nodes, node_graph.edges.clone(), |edge| {
+            Some(edge)
+        }),
+        node,
+    )
+}
+
+// Adds the edge if it doesn't exist, otherwise removes it
+pub fn with_edge_toggled(node_graph: &NodeGraph, edge: (usize, usize)) -> NodeGraph {
+    let mut edges = node_graph.edges.clone();
+    if !edges.remove(&edge) {
+        edges.insert(edge);
+    }
+    rebuild(node_graph, node_graph.nodes.clone(), edges, |edge| {
+        Some(edge)
+    })
+}
+
+// Removes a node and its edges. Nodes after it move down an index.
+pub fn with_node_removed(node_graph: &NodeGraph, node: usize) -> NodeGraph {
+    let mut nodes = node_graph.nodes.clone();
+    nodes.remove(node);
+    let reindex = |(source, dest): (usize, usize)| {
+        if source == node || dest == node {
+            return None;
+        }
+        let shift = |index: usize| if index > node { index - 1 } else { index };
+        Some((shift(source), shift(dest)))
+    };
+    let edges = node_graph
+        .edges
+        .iter()
+        .copied()
+        .filter_map(reindex)
+        .collect();
+    rebuild(node_graph, nodes, edges, reindex)
+}
+
+// Builds a new graph so sources, destinations and routes are recalculated.
+// Edge attributes are carried over through the given edge mapping.
+fn rebuild(
+    node_graph: &NodeGraph,
+    nodes: Vec<Node>,
+    edges: HashSet<(usize, usize)>,
+    map_edge: impl Fn((usize, usize)) -> Option<(usize, usize)>,
+) -> NodeGraph {
+    let edge_attributes: HashMap<_, _> = node_graph
+        .edge_attributes
+        .iter()
+        .filter_map(|(edge, attributes)| Some((map_edge(*edge)?, attributes.clone())))
+        .filter(|(edge, _)| edges.contains(edge))
+        .collect();
+    let mut new_graph = NodeGraph::new(nodes, edges);
+    new_graph.edge_attributes = edge_attributes;
+    new_graph.projection = node_graph.projection;
+    new_graph
+}
+
+// Switches edit mode on and off when E is pressed. Vehicles are removed
+// when editing starts and measurements restart when it ends.
+#[allow(clippy::too_many_arguments)]
+pub fn toggle_network_editor(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut network_editor: ResMut<NetworkEditor>,
+    vehicle_query: Query<Entity, With<Vehicle>>,
+    mut node_graph: ResMut<NodeGraph>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+    mut edge_metrics: ResMut<EdgeMetrics>,
+    mut entry_queues: ResMut<EntryQueues>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyE) {
+        return;
+    }
+
+    network_editor.active = !network_editor.active;
+    network_editor.dragged_node = None;
+    network_editor.edge_start = None;
+    if network_editor.active {
+        for entity in &vehicle_query {
+            commands.entity(entity).despawn();
+        }
+        node_graph.node_reservation_map.clear();
+        entry_queues.clear_vehicles();
+        node_graph_renderer.clear_selection();
+    } else {
+        edge_metrics.clear();
+        *entry_queues = EntryQueues::default();
+        node_graph_renderer.edge_congestion.clear();
+        node_graph_validation::report_network_issues(&node_graph);
+    }
+}
+
+// In edit mode, left clicking empty ground places a node and dragging a node
+// moves it. Right clicking two nodes adds an edge between them, or removes
+// it if it already exists. Delete removes the node under the cursor.
+pub fn edit_network(
+    mouse: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut network_editor: ResMut<NetworkEditor>,
+    mut node_graph: ResMut<NodeGraph>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+) {
+    if mouse.just_released(MouseButton::Left) {
+        network_editor.dragged_node = None;
+    }
+    let (Ok(window), Ok(camera)) = (window_query.get_single(), camera_query.get_single()) else {
+        return;
+    };
+    let Some(point) = selection::cursor_ground_point(window, camera) else {
+        return;
+    };
+    let hovered_node = match selection::pick(point, std::iter::empty(), &node_graph) {
+        Some(Pick::Node(node)) => Some(node),
+        _ => None,
+    };
+
+    // Node positions don't affect routes, so dragging doesn't need a rebuild
+    if let Some(node) = network_editor.dragged_node {
+        node_graph.nodes[node].position = point;
+        return;
+    }
+
+    if mouse.just_pressed(MouseButton::Left) {
+        match hovered_node {
+            Some(node) => network_editor.dragged_node = Some(node),
+            None => {
+                let (new_graph, node) = with_node_added(&node_graph, point);
+                *node_graph = new_graph;
+                network_editor.dragged_node = Some(node);
+            }
+        }
+    } else if mouse.just_pressed(MouseButton::Right) {
+        match (network_editor.edge_start, hovered_node) {
+            (Some(start), Some(end)) if start != end => {
+                *node_graph = with_edge_toggled(&node_graph, (start, end));
+                network_editor.edge_start = None;
+            }
+            (None, Some(start)) => network_editor.edge_start = Some(start),
+            _ => network_editor.edge_start = None,
+        }
+    } else if keyboard.any_just_pressed([KeyCode::Delete, KeyCode::Backspace]) {
+        if let Some(node) = hovered_node {
+            *node_graph = with_node_removed(&node_graph, node);
+            network_editor.edge_start = None;
+        }
+    }
+
+    // Selections may refer to nodes which have moved index
+    if node_graph.is_changed() {
+        node_graph_renderer.clear_selection();
+    }
+}
+
+// Draws the edge being created from its first node to the cursor
+pub fn show_network_editor(
+    network_editor: Res<NetworkEditor>,
+    node_graph: Res<NodeGraph>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut gizmos: Gizmos,
+) {
+    let Some(start) = network_editor.edge_start else {
+        return;
+    };
+    let (Ok(window), Ok(camera)) = (window_query.get_single(), camera_query.get_single()) else {
+        return;
+    };
+    let Some(point) = selection::cursor_ground_point(window, camera) else {
+        return;
+    };
+    gizmos.arrow(
+        node_graph.nodes[start].position,
+        point,
+        Color::srgb(1., 1., 0.),
+    );
+}
+
+// Saves the network to the working directory when ctrl+S is pressed
+pub fn save_edited_network(keyboard: Res<ButtonInput<KeyCode>>, node_graph: Res<NodeGraph>) {
+    let ctrl = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !ctrl || !keyboard.just_pressed(KeyCode::KeyS) {
+        return;
+    }
+
+    match std::fs::write(
+        NETWORK_FILE_PATH,
+        network_file::write_network_file(&node_graph),
+    ) {
+        Ok(()) => info!("Saved network to {}", NETWORK_FILE_PATH),
+        Err(error) => error!("Failed to save network to {}: {}", NETWORK_FILE_PATH, error),
+    }
+}
+
+#[derive(Component)]
+pub struct NetworkEditorHelp;
+
+pub fn spawn_network_editor_help(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "Edit mode (E to finish)\nLeft click: place or drag a node\nRight click two nodes: add or remove an edge\nDelete: remove the node under the cursor\nCtrl+S: save to network.json",
+            TextStyle {
+                font_size: 16.,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.),
+            left: Val::Px(10.),
+            ..default()
+        })
+        .with_background_color(Color::srgba(0., 0., 0., 0.6)),
+        NetworkEditorHelp,
+    ));
+}
+
+pub fn update_network_editor_help(
+    network_editor: Res<NetworkEditor>,
+    mut help_query: Query<&mut Visibility, With<NetworkEditorHelp>>,
+) {
+    if !network_editor.is_changed() {
+        return;
+    }
+    for mut visibility in &mut help_query {
+        *visibility = if network_editor.active {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_graph::EdgeAttributes;
+
+    #[test]
+    fn editing_refreshes_routes() {
+        let graph = NodeGraph::create_highway_merge();
+
+        // A new node is isolated until it is connected
+        let (graph, node) = with_node_added(&graph, Vec3::new(40., 0., 0.));
+        assert_eq!(node, 8);
+        assert!(graph.source_nodes.contains(&8) && graph.dest_nodes.contains(&8));
+
+        // Extending the mainline makes 6 an internal node
+        let graph = with_edge_toggled(&graph, (6, 8));
+        assert!(!graph.dest_nodes.contains(&6));
+        assert_eq!(graph.shortest_path_map[&(0, 8)], vec![0, 1, 2, 3, 4, 6, 8]);
+        assert!(!graph.shortest_path_map.contains_key(&(0, 6)));
+
+        // Toggling again removes the edge
+        let graph = with_edge_toggled(&graph, (6, 8));
+        assert!(graph.dest_nodes.contains(&6));
+        assert!(!graph.edges.contains(&(6, 8)));
+    }
+
+    #[test]
+    fn with_node_removed_reindexes_edges() {
+        let mut graph = NodeGraph::create_highway_merge();
+        graph
+            .edge_attributes
+            .insert((4, 7), EdgeAttributes::default());
+        graph
+            .edge_attributes
+            .insert((0, 1), EdgeAttributes::default());
+
+        // Removing the on-ramp moves the later nodes down an index
+        let graph = with_node_removed(&graph, 5);
+        assert_eq!(graph.nodes.len(), 7);
+        assert_eq!(
+            graph.edges,
+            HashSet::from([(0, 1), (1, 2), (2, 3), (3, 4), (4, 5), (4, 6)])
+        );
+        assert_eq!(graph.source_nodes, HashSet::from([0]));
+        assert_eq!(
+            graph
+                .edge_attributes
+                .keys()
+                .copied()
+                .collect::<HashSet<_>>(),
+            HashSet::from([(0, 1), (4, 6)])
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashSet, fmt, fs, io, path::Path};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node_graph::{EdgeAttributes, Node, NodeGraph},
+    osm_import::LocalProjection,
+};
+
+#[derive(Debug)]
+pub enum NetworkFileError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // An edge references a node which doesn't exist
+    Invalid(String),
+}
+
+impl fmt::Display for NetworkFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkFileError::Io(error) => write!(f, "failed to read network file: {}", error),
+            NetworkFileError::Json(error) => write!(f, "failed to parse network file: {}", error),
+            NetworkFileError::Invalid(message) => write!(f, "invalid network file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for NetworkFileError {}
+
+impl From<io::Error> for NetworkFileError {
+    fn from(error: io::Error) -> Self {
+        NetworkFileError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for NetworkFileError {
+    fn from(error: serde_json::Error) -> Self {
+        NetworkFileError::Json(error)
+    }
+}
+
+// The native network format, a JSON document holding node positions and
+// directed edges. Sources, destinations and routes are derived when loaded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkFile {
+    pub nodes: Vec<[f32; 3]>,
+    pub edges: Vec<(usize, usize)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edge_attributes: Vec<((usize, usize), EdgeAttributes)>,
+    // Where the network lies on the earth, if it came from map data
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projection: Option<LocalProjection>,
+}
+
+impl NetworkFile {
+    pub fn from_node_graph(node_graph: &NodeGraph) -> Self {
+        let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+        edges.sort();
+        let mut edge_attributes: Vec<((usize, usize), EdgeAttributes)> = node_graph
+            .edge_attributes
+            .iter()
+            .map(|(edge, attributes)| (*edge, attributes.clone()))
+            .collect();
+        edge_attributes.sort_by_key(|(edge, _)| *edge);
+        NetworkFile {
+            nodes: node_graph
+                .nodes
+                .iter()
+                .map(|node| node.position.to_array())
+                .collect(),
+            edges,
+            edge_attributes,
+            projection: node_graph.projection,
+        }
+    }
+
+    pub fn to_node_graph(&self) -> Result<NodeGraph, NetworkFileError> {
+        let node_count = self.nodes.len();
+        let edges = self
+            .edges
+            .iter()
+            .chain(self.edge_attributes.iter().map(|(edge, _)| edge));
+        for (source, dest) in edges {
+            if *source >= node_count || *dest >= node_count {
+                return Err(NetworkFileError::Invalid(format!(
+                    "edge ({}, {}) references a missing node",
+                    source, dest
+                )));
+            }
+        }
+
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|position| Node {
+                position: Vec3::from_array(*position),
+            })
+            .collect();
+        let edges: HashSet<(usize, usize)> = self.edges.iter().copied().collect();
+        let mut node_graph = NodeGraph::new(nodes, edges);
+        node_graph.edge_attributes = self.edge_attributes.iter().cloned().collect();
+        node_graph.projection = self.projection;
+        Ok(node_graph)
+    }
+}
+
+pub fn load_network_file(path: impl AsRef<Path>) -> Result<NodeGraph, NetworkFileError> {
+    let text = fs::read_to_string(path)?;
+    parse_network_file(&text)
+}
+
+pub fn parse_network_file(text: &str) -> Result<NodeGraph, NetworkFileError> {
+    let network_file: NetworkFile = serde_json::from_str(text)?;
+    network_file.to_node_graph()
+}
+
+pub fn write_network_file(node_graph: &NodeGraph) -> String {
+    serde_json::to_string_pretty(&NetworkFile::from_node_graph(node_graph))
+        .expect("Network files should always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_file_round_trips() {
+        let mut graph = NodeGraph::create_t_junction();
+        graph.edge_attributes.insert(
+            (1, 7),
+            EdgeAttributes {
+                speed_limit: Some(8.),
+                lanes: Some(2),
+            },
+        );
+        graph.projection = Some(LocalProjection::new(51.5, -0.1));
+
+        let loaded = parse_network_file(&write_network_file(&graph)).unwrap();
+        assert_eq!(loaded.edges, graph.edges);
+        assert_eq!(loaded.source_nodes, graph.source_nodes);
+        assert_eq!(loaded.shortest_path_map, graph.shortest_path_map);
+        assert_eq!(loaded.edge_attributes, graph.edge_attributes);
+        assert_eq!(loaded.projection, graph.projection);
+        let positions = |graph: &NodeGraph| -> Vec<Vec3> {
+            graph.nodes.iter().map(|node| node.position).collect()
+        };
+        assert_eq!(positions(&loaded), positions(&graph));
+    }
+
+    #[test]
+    fn parse_network_file_rejects_missing_nodes() {
+        let text = r#"{ "nodes": [[0, 0, 0], [1, 0, 0]], "edges": [[0, 1], [1, 2]] }"#;
+        assert!(matches!(
+            parse_network_file(text),
+            Err(NetworkFileError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_network_file("{ \"nodes\": 3 }"),
+            Err(NetworkFileError::Json(_))
+        ));
+    }
+}
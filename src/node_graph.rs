@@ -1,19 +1,40 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    f32::consts::PI,
     usize,
 };
 
-use bevy::prelude::*;
+#[cfg(feature = "bevy")]
+use bevy::prelude::Resource;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::osm_import::LocalProjection;
+
+// The width of a single lane in world units
+pub const LANE_WIDTH: f32 = 1.4;
 
 #[derive(Clone)]
 pub struct Node {
     pub position: Vec3,
 }
 
-#[derive(Resource)]
+// Optional properties of an edge, typically carried over from imported map data
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EdgeAttributes {
+    // The legal speed limit along the edge in world units per second
+    pub speed_limit: Option<f32>,
+    // The number of lanes travelling in the direction of the edge
+    pub lanes: Option<u32>,
+}
+
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone)]
 pub struct NodeGraph {
     pub nodes: Vec<Node>,
     pub edges: HashSet<(usize, usize)>,
+    // Properties of edges, edges without an entry use the simulation defaults
+    pub edge_attributes: HashMap<(usize, usize), EdgeAttributes>,
     // Source nodes are nodes that have no other nodes pointing to them
     pub source_nodes: HashSet<usize>,
     // Destination nodes are nodes that don't have any nodes leading from them
@@ -24,6 +45,9 @@ pub struct NodeGraph {
     pub shortest_path_map: HashMap<(usize, usize), Vec<usize>>,
     // Stores which vehicle has a given node reserved
     pub node_reservation_map: HashMap<usize, usize>,
+    // Where the network lies on the earth, for networks imported from map
+    // data
+    pub projection: Option<LocalProjection>,
 }
 
 impl NodeGraph {
@@ -86,6 +110,143 @@ impl NodeGraph {
         Self::new(nodes, edges)
     }
 
+    // Creates a single lane roundabout with the given number of arms. Arms are
+    // spaced evenly around the ring and traffic circulates counterclockwise
+    // when viewed from above. Each arm i is made of four nodes:
+    //   4i     source, the start of the inbound lane
+    //   4i + 1 destination, the end of the outbound lane
+    //   4i + 2 entry, where the inbound lane joins the ring
+    //   4i + 3 exit, where the outbound lane leaves the ring
+    // The ring visits the nodes in the order
+    //   entry(0) -> exit(1) -> entry(1) -> exit(2) -> ... -> exit(0) -> entry(0)
+    // so a vehicle entering on arm i passes the exit of arm i + 1 first.
+    pub fn create_roundabout(arm_count: usize) -> Self {
+        assert!(arm_count > 0, "A roundabout needs at least one arm");
+
+        let ring_radius = 5.;
+        let arm_length = 12.;
+        let lane_offset = 1.;
+        // Entries and exits are spaced evenly so the ring is a regular polygon
+        let ring_node_spacing = PI / arm_count as f32;
+
+        let mut nodes = Vec::with_capacity(arm_count * 4);
+        for arm in 0..arm_count {
+            // Angles increase counterclockwise when looking down the y axis
+            let arm_angle = 2. * PI * arm as f32 / arm_count as f32;
+            let direction = Vec3::new(arm_angle.cos(), 0., -arm_angle.sin());
+            let counterclockwise = Vec3::new(direction.z, 0., -direction.x);
+            let ring_position = |angle: f32| Vec3::new(angle.cos(), 0., -angle.sin()) * ring_radius;
+            nodes.extend(
+                [
+                    direction * arm_length + counterclockwise * lane_offset,
+                    direction * arm_length - counterclockwise * lane_offset,
+                    ring_position(arm_angle + ring_node_spacing / 2.),
+                    ring_position(arm_angle - ring_node_spacing / 2.),
+                ]
+                .map(|position| Node { position }),
+            );
+        }
+
+        let mut edges = HashSet::new();
+        for arm in 0..arm_count {
+            let next_arm = (arm + 1) % arm_count;
+            // Arm to and from the ring
+            edges.insert((arm * 4, arm * 4 + 2));
+            edges.insert((arm * 4 + 3, arm * 4 + 1));
+            // Around the ring to the next arm
+            edges.insert((arm * 4 + 2, next_arm * 4 + 3));
+            edges.insert((next_arm * 4 + 3, next_arm * 4 + 2));
+        }
+        Self::new(nodes, edges)
+    }
+
+    // Creates a one way highway segment with an on-ramp merging into it
+    // followed by an off-ramp diverging from it.
+    //
+    //                   5                     7
+    //                    \                   ^
+    //                     V                 /
+    //    0----->1-------->2------>3------->4------->6
+    //
+    pub fn create_highway_merge() -> Self {
+        let node_positions = [
+            // Mainline
+            Vec3::new(-30., 0., 0.),
+            Vec3::new(-18., 0., 0.),
+            Vec3::new(-6., 0., 0.),
+            Vec3::new(6., 0., 0.),
+            Vec3::new(18., 0., 0.),
+            // On-ramp
+            Vec3::new(-16., 0., 6.),
+            // Mainline end
+            Vec3::new(30., 0., 0.),
+            // Off-ramp
+            Vec3::new(28., 0., 6.),
+        ];
+        let nodes = node_positions.map(|position| Node { position }).to_vec();
+        let edges = HashSet::from([
+            // Mainline
+            (0, 1),
+            (1, 2),
+            (2, 3),
+            (3, 4),
+            (4, 6),
+            // On-ramp merge
+            (5, 2),
+            // Off-ramp diverge
+            (4, 7),
+        ]);
+        Self::new(nodes, edges)
+    }
+
+    // Creates a three way junction with the following structure
+    //
+    //    2<-----8<-----9<-----4
+    //           ^     /
+    //            \   /
+    //              X
+    //            /   \
+    //           V     \
+    //    3----->6----->7----->5
+    //           |      ^
+    //           V      |
+    //           0      1
+    pub fn create_t_junction() -> Self {
+        let node_positions = [
+            // Bottom
+            Vec3::new(-1., 0., 10.),
+            Vec3::new(1., 0., 10.),
+            // Left
+            Vec3::new(-10., 0., -1.),
+            Vec3::new(-10., 0., 1.),
+            // Right
+            Vec3::new(10., 0., -1.),
+            Vec3::new(10., 0., 1.),
+            // Intersection
+            Vec3::new(-1., 0., 1.),
+            Vec3::new(1., 0., 1.),
+            Vec3::new(-1., 0., -1.),
+            Vec3::new(1., 0., -1.),
+        ];
+        let nodes = node_positions.map(|position| Node { position }).to_vec();
+        let edges = HashSet::from([
+            // Sources to the intersection
+            (1, 7),
+            (3, 6),
+            (4, 9),
+            // Intersection out to destinations
+            (6, 0),
+            (7, 5),
+            (8, 2),
+            // Intersection to intersection
+            (6, 7),
+            (7, 8),
+            (9, 8),
+            (9, 6),
+        ]);
+        Self::new(nodes, edges)
+    }
+
     pub fn new(nodes: Vec<Node>, edges: HashSet<(usize, usize)>) -> Self {
         // Automatically classify nodes as source, or destination nodes based
         // on edge directions.
@@ -101,14 +262,48 @@ impl NodeGraph {
         NodeGraph {
             nodes,
             edges,
+            edge_attributes: HashMap::new(),
             source_nodes,
             dest_nodes,
             node_map,
             shortest_path_map,
             node_reservation_map: HashMap::new(),
+            projection: None,
         }
     }
 
+    // The speed limit of an edge, None if vehicles may drive as fast as they
+    // like
+    pub fn speed_limit(&self, edge: (usize, usize)) -> Option<f32> {
+        self.edge_attributes
+            .get(&edge)
+            .and_then(|attributes| attributes.speed_limit)
+    }
+
+    // The number of lanes of an edge, edges without a lane count have one
+    pub fn lane_count(&self, edge: (usize, usize)) -> u32 {
+        self.edge_attributes
+            .get(&edge)
+            .and_then(|attributes| attributes.lanes)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    // Finds the path with the fewest edges between any two nodes, not just
+    // from a source to a destination. A node's path to itself is just the
+    // node.
+    pub fn find_path(&self, source_node: usize, dest_node: usize) -> Option<Vec<usize>> {
+        if source_node == dest_node {
+            return Some(vec![source_node]);
+        }
+        calculate_shortest_path(
+            source_node,
+            dest_node,
+            &self.node_map,
+            &calculate_reverse_node_map(&self.node_map),
+        )
+    }
+
     pub fn is_edge_in_path(source_node: usize, dest_node: usize, path: &Vec<usize>) -> bool {
         let Some(source_index) = path.iter().position(|x| x == &source_node) else {
             return false;
@@ -132,6 +327,10 @@ fn calculate_shortest_path_map(
 
     for source_node in source_nodes {
         for dest_node in dest_nodes {
+            // Isolated nodes are both sources and destinations, they have no path
+            if source_node == dest_node {
+                continue;
+            }
             if let Some(shortest_path) =
                 calculate_shortest_path(*source_node, *dest_node, node_map, &reverse_node_map)
             {
@@ -182,11 +381,12 @@ fn calculate_shortest_path(
             .get(&node)
             .expect("Node not contained in reverse node map");
 
-        // Find the next node by sorting the available connections by their value in the distance map
+        // Find the next node by sorting the available connections by their value in the distance map.
+        // Ties go to the lowest node index so paths don't depend on hash ordering.
         node = *connections
             .iter()
             .filter(|x| distance_map.contains_key(x))
-            .min_by_key(|x| distance_map.get(x))
+            .min_by_key(|x| (distance_map.get(x), **x))
             .expect("Error calculating next node");
 
         shortest_path.push(node);
@@ -274,4 +474,94 @@ mod tests {
             );
         }
     }
+
+    fn assert_shortest_paths(graph: &NodeGraph, expected_values: Vec<(usize, usize, Vec<usize>)>) {
+        for (source_node, dest_node, expected_path) in expected_values {
+            let shortest_path = graph
+                .shortest_path_map
+                .get(&(source_node, dest_node))
+                .unwrap();
+            assert_eq!(
+                expected_path, *shortest_path,
+                "Input of ({}, {}) produced an unexpected shortest path",
+                source_node, dest_node
+            );
+        }
+    }
+
+    #[test]
+    fn create_roundabout_produces_expected_paths() {
+        let graph = NodeGraph::create_roundabout(4);
+        assert_eq!(graph.source_nodes, HashSet::from([0, 4, 8, 12]));
+        assert_eq!(graph.dest_nodes, HashSet::from([1, 5, 9, 13]));
+
+        let expected_values = vec![
+            (0, 5, vec![0, 2, 7, 5]),
+            (0, 9, vec![0, 2, 7, 6, 11, 9]),
+            (0, 13, vec![0, 2, 7, 6, 11, 10, 15, 13]),
+            // A full lap of the ring is a u-turn
+            (0, 1, vec![0, 2, 7, 6, 11, 10, 15, 14, 3, 1]),
+            (12, 1, vec![12, 14, 3, 1]),
+            (12, 5, vec![12, 14, 3, 2, 7, 5]),
+            (8, 5, vec![8, 10, 15, 14, 3, 2, 7, 5]),
+        ];
+        assert_shortest_paths(&graph, expected_values);
+    }
+
+    #[test]
+    fn create_roundabout_supports_any_arm_count() {
+        let graph = NodeGraph::create_roundabout(3);
+        assert_eq!(graph.nodes.len(), 12);
+        assert_eq!(graph.shortest_path_map.len(), 9);
+
+        let expected_values = vec![
+            (0, 5, vec![0, 2, 7, 5]),
+            (4, 9, vec![4, 6, 11, 9]),
+            (8, 1, vec![8, 10, 3, 1]),
+            (8, 5, vec![8, 10, 3, 2, 7, 5]),
+        ];
+        assert_shortest_paths(&graph, expected_values);
+    }
+
+    #[test]
+    fn create_highway_merge_produces_expected_paths() {
+        let graph = NodeGraph::create_highway_merge();
+        assert_eq!(graph.source_nodes, HashSet::from([0, 5]));
+        assert_eq!(graph.dest_nodes, HashSet::from([6, 7]));
+
+        let expected_values = vec![
+            (0, 6, vec![0, 1, 2, 3, 4, 6]),
+            (0, 7, vec![0, 1, 2, 3, 4, 7]),
+            (5, 6, vec![5, 2, 3, 4, 6]),
+            (5, 7, vec![5, 2, 3, 4, 7]),
+        ];
+        assert_shortest_paths(&graph, expected_values);
+    }
+
+    #[test]
+    fn find_path_connects_internal_nodes() {
+        let graph = NodeGraph::create_highway_merge();
+        assert_eq!(graph.find_path(1, 4), Some(vec![1, 2, 3, 4]));
+        assert_eq!(graph.find_path(3, 3), Some(vec![3]));
+        assert_eq!(graph.find_path(3, 1), None);
+    }
+
+    #[test]
+    fn create_t_junction_produces_expected_paths() {
+        // commented out test cases are for uturn scenarios which don't seem valid
+        let expected_values = vec![
+            (1, 5, vec![1, 7, 5]),
+            (1, 2, vec![1, 7, 8, 2]),
+            (3, 0, vec![3, 6, 0]),
+            (3, 5, vec![3, 6, 7, 5]),
+            // (3, 2, vec![3, 6, 7, 8, 2]),
+            (4, 2, vec![4, 9, 8, 2]),
+            (4, 0, vec![4, 9, 6, 0]),
+            // (4, 5, vec![4, 9, 6, 7, 5]),
+        ];
+        let graph = NodeGraph::create_t_junction();
+        assert_eq!(graph.source_nodes, HashSet::from([1, 3, 4]));
+        assert_eq!(graph.dest_nodes, HashSet::from([0, 2, 5]));
+        assert_shortest_paths(&graph, expected_values);
+    }
 }
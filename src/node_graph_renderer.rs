@@ -1,6 +1,48 @@
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 
-use crate::node_graph::NodeGraph;
+use crate::{edge_metrics::EdgeMetrics, node_graph::NodeGraph};
+
+// The density at which vehicles are queued bumper to bumper, in vehicles per km
+const JAM_DENSITY: f32 = 800.;
+// How quickly the heatmap follows changes in traffic, per second
+const CONGESTION_SMOOTHING_RATE: f32 = 2.;
+
+// The metric used to color edges which aren't part of the highlighted path
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EdgeColorMode {
+    #[default]
+    Plain,
+    // Ratio between the speed of vehicles on the edge and the speed they
+    // would drive at if nothing was in their way
+    SpeedRatio,
+    // Vehicles per kilometer relative to jam density
+    Density,
+    // Stopped vehicles relative to how many vehicles fit on the edge
+    QueueLength,
+}
+
+impl EdgeColorMode {
+    fn next(self) -> Self {
+        match self {
+            EdgeColorMode::Plain => EdgeColorMode::SpeedRatio,
+            EdgeColorMode::SpeedRatio => EdgeColorMode::Density,
+            EdgeColorMode::Density => EdgeColorMode::QueueLength,
+            EdgeColorMode::QueueLength => EdgeColorMode::Plain,
+        }
+    }
+
+    // The legend title followed by the labels for no and full congestion
+    fn legend_labels(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            EdgeColorMode::Plain => ("", "", ""),
+            EdgeColorMode::SpeedRatio => ("Speed / desired speed", "1.0", "0.0"),
+            EdgeColorMode::Density => ("Density (veh/km)", "0", "800"),
+            EdgeColorMode::QueueLength => ("Queue (% of edge)", "0%", "100%"),
+        }
+    }
+}
 
 #[derive(Resource, Default)]
 pub struct NodeGraphRenderer {
@@ -8,6 +50,27 @@ pub struct NodeGraphRenderer {
     pub highlighted_vehicle_id: Option<usize>,
     // The index of the path in shortest_path_map which is highlighted on the screen
     pub highlighted_path_index: Option<(usize, usize)>,
+    // Set when the highlighted vehicle was picked with the mouse, newly
+    // spawned vehicles are only highlighted automatically when this is unset
+    pub vehicle_selected_by_user: bool,
+    // The node picked with the mouse
+    pub selected_node: Option<usize>,
+    // The metric edges are colored by
+    pub edge_color_mode: EdgeColorMode,
+    // Smoothed congestion of each edge for the current color mode, from 0 (free
+    // flowing) to 1 (fully congested)
+    pub edge_congestion: HashMap<(usize, usize), f32>,
+}
+
+impl NodeGraphRenderer {
+    // Clears the highlighted vehicle and selected node, used when the
+    // network is replaced
+    pub fn clear_selection(&mut self) {
+        self.highlighted_vehicle_id = None;
+        self.highlighted_path_index = None;
+        self.vehicle_selected_by_user = false;
+        self.selected_node = None;
+    }
 }
 
 #[derive(Default, Reflect, GizmoConfigGroup)]
@@ -35,6 +98,14 @@ pub fn show_node_graph(
             Color::srgb(0.1, 0.1, 0.9)
         };
         gizmos.sphere(node.position, Quat::IDENTITY, node_radius, color);
+        if node_graph_renderer.selected_node == Some(i) {
+            gizmos.sphere(
+                node.position,
+                Quat::IDENTITY,
+                node_radius * 1.5,
+                Color::srgb(1., 1., 0.),
+            );
+        }
     }
 
     let highlighted_path: Option<&Vec<usize>> = match node_graph_renderer.highlighted_path_index {
@@ -56,6 +127,199 @@ pub fn show_node_graph(
                 continue;
             }
         }
-        gizmos.arrow(arrow_start, arrow_end, Color::srgb(1., 1., 1.));
+        let color = match node_graph_renderer.edge_congestion.get(&(*source, *dest)) {
+            Some(congestion) if node_graph_renderer.edge_color_mode != EdgeColorMode::Plain => {
+                congestion_color(*congestion)
+            }
+            _ => Color::srgb(1., 1., 1.),
+        };
+        gizmos.arrow(arrow_start, arrow_end, color);
+    }
+}
+
+// Maps congestion from 0 to 1 onto a green, yellow, red color ramp
+fn congestion_color(congestion: f32) -> Color {
+    let green = LinearRgba::rgb(0.1, 0.8, 0.1);
+    let yellow = LinearRgba::rgb(0.9, 0.9, 0.1);
+    let red = LinearRgba::rgb(0.9, 0.1, 0.1);
+    let congestion = congestion.clamp(0., 1.);
+    if congestion < 0.5 {
+        green.mix(&yellow, congestion * 2.).into()
+    } else {
+        yellow.mix(&red, (congestion - 0.5) * 2.).into()
+    }
+}
+
+// Cycles through the edge color modes when M is pressed
+pub fn toggle_edge_color_mode(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+) {
+    if keyboard.just_pressed(KeyCode::KeyM) {
+        node_graph_renderer.edge_color_mode = node_graph_renderer.edge_color_mode.next();
+        node_graph_renderer.edge_congestion.clear();
+    }
+}
+
+// Updates the congestion of each edge from the latest edge metric samples
+pub fn update_edge_congestion(
+    node_graph: Res<NodeGraph>,
+    edge_metrics: Res<EdgeMetrics>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+    time: Res<Time>,
+) {
+    let edge_color_mode = node_graph_renderer.edge_color_mode;
+    if edge_color_mode == EdgeColorMode::Plain {
+        return;
+    }
+
+    // Samples change every tick as vehicles move, so ease towards them
+    let smoothing = (CONGESTION_SMOOTHING_RATE * time.delta_seconds()).min(1.);
+    for (edge, sample) in edge_metrics.latest_samples.iter() {
+        let (Some(source), Some(dest)) =
+            (node_graph.nodes.get(edge.0), node_graph.nodes.get(edge.1))
+        else {
+            continue;
+        };
+        let edge_length = source.position.distance(dest.position);
+        let target_congestion = match edge_color_mode {
+            EdgeColorMode::Plain => 0.,
+            EdgeColorMode::SpeedRatio => {
+                if sample.vehicle_count == 0 {
+                    0.
+                } else {
+                    1. - sample.mean_speed_ratio
+                }
+            }
+            EdgeColorMode::Density => {
+                sample.vehicle_count as f32 / edge_length * 1000. / JAM_DENSITY
+            }
+            EdgeColorMode::QueueLength => {
+                let capacity = edge_length * JAM_DENSITY / 1000.;
+                sample.queue_length as f32 / capacity
+            }
+        };
+        let congestion = node_graph_renderer
+            .edge_congestion
+            .entry(*edge)
+            .or_insert(target_congestion);
+        *congestion += (target_congestion.clamp(0., 1.) - *congestion) * smoothing;
+    }
+}
+
+#[derive(Component)]
+pub struct HeatmapLegend;
+
+#[derive(Component)]
+pub enum HeatmapLegendText {
+    Title,
+    Low,
+    High,
+}
+
+pub fn spawn_heatmap_legend(mut commands: Commands) {
+    let text_style = TextStyle {
+        font_size: 16.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(10.),
+                    right: Val::Px(10.),
+                    padding: UiRect::all(Val::Px(8.)),
+                    row_gap: Val::Px(4.),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                background_color: Color::srgba(0., 0., 0., 0.6).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            HeatmapLegend,
+        ))
+        .with_children(|legend| {
+            legend.spawn((
+                TextBundle::from_section("", text_style.clone()),
+                HeatmapLegendText::Title,
+            ));
+            // A row of swatches sampled along the color ramp
+            legend
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|ramp| {
+                    let swatch_count = 10;
+                    for i in 0..swatch_count {
+                        ramp.spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Px(16.),
+                                height: Val::Px(12.),
+                                ..default()
+                            },
+                            background_color: congestion_color(
+                                i as f32 / (swatch_count - 1) as f32,
+                            )
+                            .into(),
+                            ..default()
+                        });
+                    }
+                });
+            legend
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        justify_content: JustifyContent::SpaceBetween,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|labels| {
+                    labels.spawn((
+                        TextBundle::from_section("", text_style.clone()),
+                        HeatmapLegendText::Low,
+                    ));
+                    labels.spawn((
+                        TextBundle::from_section("", text_style.clone()),
+                        HeatmapLegendText::High,
+                    ));
+                });
+        });
+}
+
+// Shows the legend for the current edge color mode, or hides it for plain edges
+pub fn update_heatmap_legend(
+    node_graph_renderer: Res<NodeGraphRenderer>,
+    mut legend_query: Query<&mut Visibility, With<HeatmapLegend>>,
+    mut text_query: Query<(&mut Text, &HeatmapLegendText)>,
+) {
+    if !node_graph_renderer.is_changed() {
+        return;
+    }
+
+    let edge_color_mode = node_graph_renderer.edge_color_mode;
+    for mut visibility in &mut legend_query {
+        *visibility = if edge_color_mode == EdgeColorMode::Plain {
+            Visibility::Hidden
+        } else {
+            Visibility::Visible
+        };
+    }
+
+    let (title, low, high) = edge_color_mode.legend_labels();
+    for (mut text, legend_text) in &mut text_query {
+        text.sections[0].value = match legend_text {
+            HeatmapLegendText::Title => title,
+            HeatmapLegendText::Low => low,
+            HeatmapLegendText::High => high,
+        }
+        .to_string();
     }
 }
@@ -0,0 +1,408 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::log::{info, warn};
+
+use crate::node_graph::NodeGraph;
+
+// A problem with a node graph which stops it from being simulated correctly
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationIssue {
+    // An edge references a node index which doesn't exist
+    EdgeOutOfRange {
+        source: usize,
+        dest: usize,
+    },
+    // An edge starts and ends at the same position, vehicles can't drive it
+    ZeroLengthEdge {
+        source: usize,
+        dest: usize,
+    },
+    // Two edges connect the same positions in the same direction
+    DuplicateEdge {
+        first: (usize, usize),
+        second: (usize, usize),
+    },
+    // A node without any edges, it is classified as both a source and a destination
+    IsolatedNode {
+        node: usize,
+    },
+    // A destination node which can't be reached from any source
+    UnreachableDest {
+        node: usize,
+    },
+    // A source node which can't reach any destination
+    SourceWithoutRoute {
+        node: usize,
+    },
+    // No source can reach any destination, so no vehicles can be spawned
+    NoRoutes,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::EdgeOutOfRange { source, dest } => {
+                write!(f, "edge ({}, {}) references a missing node", source, dest)
+            }
+            ValidationIssue::ZeroLengthEdge { source, dest } => {
+                write!(f, "edge ({}, {}) has zero length", source, dest)
+            }
+            ValidationIssue::DuplicateEdge { first, second } => write!(
+                f,
+                "edges ({}, {}) and ({}, {}) overlap",
+                first.0, first.1, second.0, second.1
+            ),
+            ValidationIssue::IsolatedNode { node } => {
+                write!(f, "node {} is not connected to any edges", node)
+            }
+            ValidationIssue::UnreachableDest { node } => {
+                write!(f, "destination {} can't be reached from any source", node)
+            }
+            ValidationIssue::SourceWithoutRoute { node } => {
+                write!(f, "source {} has no route to any destination", node)
+            }
+            ValidationIssue::NoRoutes => write!(f, "there are no routes through the network"),
+        }
+    }
+}
+
+// The result of validating a node graph
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+    // The strongly connected components of the graph, each sorted by node index.
+    // Sources and destinations are always components of their own.
+    pub strongly_connected_components: Vec<Vec<usize>>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    // Gets the components containing more than one node, these are the
+    // parts of the network where vehicles can drive in loops
+    pub fn cyclic_components(&self) -> impl Iterator<Item = &Vec<usize>> {
+        self.strongly_connected_components
+            .iter()
+            .filter(|component| component.len() > 1)
+    }
+}
+
+impl NodeGraph {
+    // Checks the graph for problems which would stop vehicles from being
+    // spawned or driving correctly. Issues are reported in a stable order.
+    pub fn validate(&self) -> ValidationReport {
+        let mut issues = Vec::new();
+        let node_count = self.nodes.len();
+
+        let mut edges: Vec<(usize, usize)> = self.edges.iter().copied().collect();
+        edges.sort();
+
+        let mut connected_nodes = HashSet::new();
+        let mut edges_by_position: HashMap<[u32; 6], (usize, usize)> = HashMap::new();
+        for (source, dest) in edges.iter().copied() {
+            if source >= node_count || dest >= node_count {
+                issues.push(ValidationIssue::EdgeOutOfRange { source, dest });
+                continue;
+            }
+            connected_nodes.insert(source);
+            connected_nodes.insert(dest);
+
+            let source_position = self.nodes[source].position;
+            let dest_position = self.nodes[dest].position;
+            if source_position == dest_position {
+                issues.push(ValidationIssue::ZeroLengthEdge { source, dest });
+            }
+
+            let key = [source_position, dest_position]
+                .map(|position| position.to_array().map(f32::to_bits))
+                .concat()
+                .try_into()
+                .unwrap();
+            if let Some(first) = edges_by_position.insert(key, (source, dest)) {
+                issues.push(ValidationIssue::DuplicateEdge {
+                    first,
+                    second: (source, dest),
+                });
+            }
+        }
+
+        for node in 0..node_count {
+            if !connected_nodes.contains(&node) {
+                issues.push(ValidationIssue::IsolatedNode { node });
+            }
+        }
+
+        // Isolated nodes are both sources and destinations, they've already been reported
+        let mut dest_nodes: Vec<usize> = self
+            .dest_nodes
+            .iter()
+            .copied()
+            .filter(|node| connected_nodes.contains(node))
+            .collect();
+        dest_nodes.sort();
+        for node in dest_nodes {
+            let reachable = self
+                .shortest_path_map
+                .keys()
+                .any(|(_, dest_node)| *dest_node == node);
+            if !reachable {
+                issues.push(ValidationIssue::UnreachableDest { node });
+            }
+        }
+
+        let mut source_nodes: Vec<usize> = self
+            .source_nodes
+            .iter()
+            .copied()
+            .filter(|node| connected_nodes.contains(node))
+            .collect();
+        source_nodes.sort();
+        for node in source_nodes {
+            let has_route = self
+                .shortest_path_map
+                .keys()
+                .any(|(source_node, _)| *source_node == node);
+            if !has_route {
+                issues.push(ValidationIssue::SourceWithoutRoute { node });
+            }
+        }
+
+        if self.shortest_path_map.is_empty() {
+            issues.push(ValidationIssue::NoRoutes);
+        }
+
+        ValidationReport {
+            issues,
+            strongly_connected_components: calculate_strongly_connected_components(
+                node_count,
+                &self.node_map,
+            ),
+        }
+    }
+}
+
+// Finds the strongly connected components of the graph using Tarjan's
+// algorithm. Implemented iteratively so large imported networks don't
+// overflow the stack.
+fn calculate_strongly_connected_components(
+    node_count: usize,
+    node_map: &HashMap<usize, HashSet<usize>>,
+) -> Vec<Vec<usize>> {
+    // Sort connections so the traversal is deterministic
+    let connections: Vec<Vec<usize>> = (0..node_count)
+        .map(|node| {
+            let mut connections: Vec<usize> = node_map
+                .get(&node)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|connection| *connection < node_count)
+                .collect();
+            connections.sort();
+            connections
+        })
+        .collect();
+
+    let mut index_counter = 0;
+    let mut indices: Vec<Option<usize>> = vec![None; node_count];
+    let mut low_links = vec![0; node_count];
+    let mut on_stack = vec![false; node_count];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+
+    for root in 0..node_count {
+        if indices[root].is_some() {
+            continue;
+        }
+
+        // Each frame is a node and the position of the next connection to visit
+        let mut call_stack = vec![(root, 0)];
+        indices[root] = Some(index_counter);
+        low_links[root] = index_counter;
+        index_counter += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((node, connection_index)) = call_stack.pop() {
+            if let Some(connection) = connections[node].get(connection_index).copied() {
+                call_stack.push((node, connection_index + 1));
+                match indices[connection] {
+                    None => {
+                        indices[connection] = Some(index_counter);
+                        low_links[connection] = index_counter;
+                        index_counter += 1;
+                        stack.push(connection);
+                        on_stack[connection] = true;
+                        call_stack.push((connection, 0));
+                    }
+                    Some(connection_order) if on_stack[connection] => {
+                        low_links[node] = low_links[node].min(connection_order);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            // All connections have been visited, propagate the low link to the parent
+            if let Some((parent, _)) = call_stack.last() {
+                low_links[*parent] = low_links[*parent].min(low_links[node]);
+            }
+
+            if Some(low_links[node]) == indices[node] {
+                let mut component = Vec::new();
+                loop {
+                    let member = stack.pop().expect("Component root should be on the stack");
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.sort();
+                components.push(component);
+            }
+        }
+    }
+
+    components.sort();
+    components
+}
+
+// Logs any problems with the network which would affect the simulation
+#[cfg(feature = "bevy")]
+pub fn report_network_issues(node_graph: &NodeGraph) {
+    let report = node_graph.validate();
+    if !report.is_valid() {
+        warn!("Network has {} issues", report.issues.len());
+    }
+    for issue in report.issues.iter() {
+        warn!("Network issue: {}", issue);
+    }
+    info!(
+        "Network has {} nodes, {} edges and {} strongly connected components ({} with cycles)",
+        node_graph.nodes.len(),
+        node_graph.edges.len(),
+        report.strongly_connected_components.len(),
+        report.cyclic_components().count()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::node_graph::Node;
+
+    fn nodes(positions: &[(f32, f32)]) -> Vec<Node> {
+        positions
+            .iter()
+            .map(|(x, z)| Node {
+                position: Vec3::new(*x, 0., *z),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn validate_accepts_templates() {
+        for graph in [
+            NodeGraph::create(),
+            NodeGraph::create_t_junction(),
+            NodeGraph::create_roundabout(4),
+            NodeGraph::create_highway_merge(),
+        ] {
+            let report = graph.validate();
+            assert_eq!(report.issues, vec![]);
+        }
+    }
+
+    #[test]
+    fn validate_reports_strongly_connected_components() {
+        let report = NodeGraph::create().validate();
+
+        // The four intersection nodes form a single component, every other
+        // node is a component of its own
+        let cyclic_components: Vec<&Vec<usize>> = report.cyclic_components().collect();
+        assert_eq!(cyclic_components, vec![&vec![8, 9, 10, 11]]);
+        assert_eq!(report.strongly_connected_components.len(), 9);
+    }
+
+    #[test]
+    fn validate_reports_broken_networks() {
+        // 0 -> 1 -> 2 leads to a missing node so 2 isn't a destination,
+        // 3 <-> 4 is a loop with no way in or out, 5 is isolated, 6 -> 7 has
+        // zero length and 8 -> 9 overlaps 1 -> 2.
+        let nodes = nodes(&[
+            (0., 0.),
+            (1., 0.),
+            (2., 0.),
+            (0., 5.),
+            (1., 5.),
+            (9., 9.),
+            (3., 3.),
+            (3., 3.),
+            (1., 0.),
+            (2., 0.),
+        ]);
+        let edges = HashSet::from([(0, 1), (1, 2), (3, 4), (4, 3), (6, 7), (8, 9), (2, 42)]);
+        let report = NodeGraph::new(nodes, edges).validate();
+
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.issues,
+            vec![
+                ValidationIssue::EdgeOutOfRange {
+                    source: 2,
+                    dest: 42
+                },
+                ValidationIssue::ZeroLengthEdge { source: 6, dest: 7 },
+                ValidationIssue::DuplicateEdge {
+                    first: (1, 2),
+                    second: (8, 9),
+                },
+                ValidationIssue::IsolatedNode { node: 5 },
+                ValidationIssue::SourceWithoutRoute { node: 0 },
+            ]
+        );
+        assert_eq!(report.cyclic_components().count(), 1);
+    }
+
+    #[test]
+    fn validate_reports_unreachable_destinations() {
+        // 0 -> 1 and 2 <- 3 -> 4 are valid routes, but 5 is only reachable
+        // from the loop 6 <-> 7
+        let nodes = nodes(&[
+            (0., 0.),
+            (1., 0.),
+            (0., 1.),
+            (1., 1.),
+            (2., 1.),
+            (0., 2.),
+            (1., 2.),
+            (2., 2.),
+        ]);
+        let edges = HashSet::from([(0, 1), (3, 2), (3, 4), (6, 7), (7, 6), (6, 5)]);
+        let report = NodeGraph::new(nodes, edges).validate();
+
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::UnreachableDest { node: 5 }]
+        );
+    }
+
+    #[test]
+    fn validate_reports_networks_without_routes() {
+        let nodes = nodes(&[(0., 0.), (1., 0.)]);
+        let edges = HashSet::from([(0, 1), (1, 0)]);
+        let report = NodeGraph::new(nodes, edges).validate();
+
+        assert_eq!(report.issues, vec![ValidationIssue::NoRoutes]);
+        assert_eq!(report.strongly_connected_components, vec![vec![0, 1]]);
+    }
+}
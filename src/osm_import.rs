@@ -0,0 +1,492 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs, io,
+    path::Path,
+};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::node_graph::{EdgeAttributes, Node, NodeGraph};
+
+// Mean radius of the earth in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// A directed edge between two osm node ids
+type OsmEdge = (i64, i64);
+
+// Values of the highway tag for ways that carry vehicle traffic
+const ROAD_TYPES: [&str; 16] = [
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+    "road",
+    "busway",
+];
+
+#[derive(Debug)]
+pub enum OsmImportError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    MissingAttribute {
+        element: &'static str,
+        attribute: &'static str,
+    },
+    InvalidAttribute {
+        element: &'static str,
+        attribute: &'static str,
+        value: String,
+    },
+    NoRoads,
+}
+
+impl fmt::Display for OsmImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsmImportError::Io(error) => write!(f, "failed to read osm file: {}", error),
+            OsmImportError::Xml(error) => write!(f, "failed to parse osm xml: {}", error),
+            OsmImportError::MissingAttribute { element, attribute } => {
+                write!(f, "<{}> is missing the '{}' attribute", element, attribute)
+            }
+            OsmImportError::InvalidAttribute {
+                element,
+                attribute,
+                value,
+            } => write!(
+                f,
+                "<{}> has an invalid '{}' attribute: '{}'",
+                element, attribute, value
+            ),
+            OsmImportError::NoRoads => write!(f, "the osm data does not contain any roads"),
+        }
+    }
+}
+
+impl std::error::Error for OsmImportError {}
+
+impl From<io::Error> for OsmImportError {
+    fn from(error: io::Error) -> Self {
+        OsmImportError::Io(error)
+    }
+}
+
+impl From<roxmltree::Error> for OsmImportError {
+    fn from(error: roxmltree::Error) -> Self {
+        OsmImportError::Xml(error)
+    }
+}
+
+// Reads an OpenStreetMap xml extract from disk and converts it into a node graph
+pub fn load_osm(path: impl AsRef<Path>) -> Result<NodeGraph, OsmImportError> {
+    let text = fs::read_to_string(path)?;
+    parse_osm(&text)
+}
+
+// Converts an OpenStreetMap xml document into a node graph.
+// Only ways tagged as roads are imported. Positions are projected onto a plane
+// in meters centered on the imported roads, with north pointing along -z.
+// Nodes which only continue a road without joining other roads are collapsed
+// into a single edge.
+pub fn parse_osm(text: &str) -> Result<NodeGraph, OsmImportError> {
+    let document = roxmltree::Document::parse(text)?;
+
+    // Collect the coordinates of every node, roads reference them by id
+    let mut coordinates: HashMap<i64, (f64, f64)> = HashMap::new();
+    for element in document.root_element().children() {
+        if !element.has_tag_name("node") {
+            continue;
+        }
+        let id = parse_attribute(&element, "node", "id")?;
+        let lat = parse_attribute(&element, "node", "lat")?;
+        let lon = parse_attribute(&element, "node", "lon")?;
+        coordinates.insert(id, (lat, lon));
+    }
+
+    // Build directed edges between osm node ids from the road ways
+    let mut edges: HashMap<OsmEdge, EdgeAttributes> = HashMap::new();
+    for element in document.root_element().children() {
+        if !element.has_tag_name("way") {
+            continue;
+        }
+        let tags: HashMap<&str, &str> = element
+            .children()
+            .filter(|child| child.has_tag_name("tag"))
+            .filter_map(|child| Some((child.attribute("k")?, child.attribute("v")?)))
+            .collect();
+        let Some(road_type) = tags.get("highway") else {
+            continue;
+        };
+        if !ROAD_TYPES.contains(road_type) {
+            continue;
+        }
+
+        let mut node_refs = Vec::new();
+        for child in element.children().filter(|child| child.has_tag_name("nd")) {
+            node_refs.push(parse_attribute::<i64>(&child, "nd", "ref")?);
+        }
+
+        let direction = road_direction(&tags);
+        let forward_attributes = edge_attributes(&tags, "forward", direction);
+        let backward_attributes = edge_attributes(&tags, "backward", direction);
+        for segment in node_refs.windows(2) {
+            let (from, to) = (segment[0], segment[1]);
+            // Extracts often cut roads at their boundary, so skip any
+            // segments which leave the extract
+            if from == to || !coordinates.contains_key(&from) || !coordinates.contains_key(&to) {
+                continue;
+            }
+            if direction != RoadDirection::Backward {
+                edges.insert((from, to), forward_attributes.clone());
+            }
+            if direction != RoadDirection::Forward {
+                edges.insert((to, from), backward_attributes.clone());
+            }
+        }
+    }
+
+    if edges.is_empty() {
+        return Err(OsmImportError::NoRoads);
+    }
+
+    collapse_degree_two_chains(&mut edges);
+
+    // Project the remaining nodes around the center of their bounding box.
+    // Nodes are ordered by id so that imports are deterministic.
+    let mut node_indices: BTreeMap<i64, usize> = BTreeMap::new();
+    for (from, to) in edges.keys() {
+        node_indices.insert(*from, 0);
+        node_indices.insert(*to, 0);
+    }
+    let (mut min_lat, mut max_lat) = (f64::MAX, f64::MIN);
+    let (mut min_lon, mut max_lon) = (f64::MAX, f64::MIN);
+    for id in node_indices.keys() {
+        let (lat, lon) = coordinates[id];
+        min_lat = min_lat.min(lat);
+        max_lat = max_lat.max(lat);
+        min_lon = min_lon.min(lon);
+        max_lon = max_lon.max(lon);
+    }
+    let projection = LocalProjection::new((min_lat + max_lat) / 2., (min_lon + max_lon) / 2.);
+
+    let mut nodes = Vec::with_capacity(node_indices.len());
+    for (index, (id, node_index)) in node_indices.iter_mut().enumerate() {
+        *node_index = index;
+        let (lat, lon) = coordinates[id];
+        nodes.push(Node {
+            position: projection.project(lat, lon),
+        });
+    }
+
+    let graph_edges = edges
+        .keys()
+        .map(|(from, to)| (node_indices[from], node_indices[to]))
+        .collect();
+    let mut graph = NodeGraph::new(nodes, graph_edges);
+    graph.edge_attributes = edges
+        .into_iter()
+        .filter(|(_, attributes)| *attributes != EdgeAttributes::default())
+        .map(|((from, to), attributes)| ((node_indices[&from], node_indices[&to]), attributes))
+        .collect();
+    graph.projection = Some(projection);
+    Ok(graph)
+}
+
+// An equirectangular projection of latitude and longitude onto a local
+// plane in meters. Accurate enough for city sized extracts.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocalProjection {
+    origin_lat: f64,
+    origin_lon: f64,
+}
+
+impl LocalProjection {
+    pub fn new(origin_lat: f64, origin_lon: f64) -> Self {
+        LocalProjection {
+            origin_lat,
+            origin_lon,
+        }
+    }
+
+    pub fn project(&self, lat: f64, lon: f64) -> Vec3 {
+        let x = EARTH_RADIUS
+            * (lon - self.origin_lon).to_radians()
+            * self.origin_lat.to_radians().cos();
+        let north = EARTH_RADIUS * (lat - self.origin_lat).to_radians();
+        // North points along the forward vector, which is -z
+        Vec3::new(x as f32, 0., -north as f32)
+    }
+
+    // Converts a position on the plane back to latitude and longitude
+    pub fn unproject(&self, position: Vec3) -> (f64, f64) {
+        let lat = self.origin_lat + (-position.z as f64 / EARTH_RADIUS).to_degrees();
+        let lon = self.origin_lon
+            + (position.x as f64 / (EARTH_RADIUS * self.origin_lat.to_radians().cos()))
+                .to_degrees();
+        (lat, lon)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RoadDirection {
+    Both,
+    Forward,
+    Backward,
+}
+
+fn road_direction(tags: &HashMap<&str, &str>) -> RoadDirection {
+    match tags.get("oneway") {
+        Some(&"yes") | Some(&"true") | Some(&"1") => return RoadDirection::Forward,
+        Some(&"-1") | Some(&"reverse") => return RoadDirection::Backward,
+        Some(&"no") | Some(&"false") | Some(&"0") => return RoadDirection::Both,
+        _ => {}
+    }
+    // Motorways and roundabouts are implicitly one way
+    let implied_oneway = matches!(tags.get("highway"), Some(&"motorway"))
+        || matches!(
+            tags.get("junction"),
+            Some(&"roundabout") | Some(&"circular")
+        );
+    if implied_oneway {
+        RoadDirection::Forward
+    } else {
+        RoadDirection::Both
+    }
+}
+
+// Reads the speed limit and lane count for one direction of a way.
+// `side` is either "forward" or "backward" relative to the way's node order.
+fn edge_attributes(
+    tags: &HashMap<&str, &str>,
+    side: &str,
+    direction: RoadDirection,
+) -> EdgeAttributes {
+    let speed_limit = tags
+        .get(format!("maxspeed:{}", side).as_str())
+        .or(tags.get("maxspeed"))
+        .and_then(|value| parse_max_speed(value));
+
+    let lanes = match tags.get(format!("lanes:{}", side).as_str()) {
+        Some(lanes) => lanes.parse().ok(),
+        None => {
+            let total_lanes: Option<u32> = tags.get("lanes").and_then(|lanes| lanes.parse().ok());
+            // Lanes of a two way road are shared between both directions
+            match direction {
+                RoadDirection::Both => total_lanes.map(|lanes| (lanes / 2).max(1)),
+                _ => total_lanes,
+            }
+        }
+    };
+
+    EdgeAttributes { speed_limit, lanes }
+}
+
+// Parses an osm maxspeed value into meters per second.
+// Values without units are in km/h, values such as "none" or "signals" are ignored.
+fn parse_max_speed(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let (number, meters_per_second_per_unit) = if let Some(number) = value.strip_suffix("mph") {
+        (number, 0.44704)
+    } else if let Some(number) = value.strip_suffix("knots") {
+        (number, 0.514444)
+    } else if let Some(number) = value.strip_suffix("km/h") {
+        (number, 1. / 3.6)
+    } else if let Some(number) = value.strip_suffix("kmh") {
+        (number, 1. / 3.6)
+    } else {
+        (value, 1. / 3.6)
+    };
+    let speed: f32 = number.trim().parse().ok()?;
+    Some(speed * meters_per_second_per_unit)
+}
+
+// Removes nodes which only connect two edges of the same road, joining the
+// edges on either side of them. A node is removed when it is the middle of a
+// one way chain (a -> n -> c) or a two way chain (a <-> n <-> c) and the
+// edges on both sides have the same attributes.
+fn collapse_degree_two_chains(edges: &mut HashMap<OsmEdge, EdgeAttributes>) {
+    let mut successors: HashMap<i64, HashSet<i64>> = HashMap::new();
+    let mut predecessors: HashMap<i64, HashSet<i64>> = HashMap::new();
+    for (from, to) in edges.keys() {
+        successors.entry(*from).or_default().insert(*to);
+        predecessors.entry(*to).or_default().insert(*from);
+    }
+
+    let mut candidates: Vec<i64> = successors.keys().copied().collect();
+    candidates.sort();
+    for node in candidates {
+        let (Some(outgoing), Some(incoming)) = (successors.get(&node), predecessors.get(&node))
+        else {
+            continue;
+        };
+
+        // Work out which edges would replace the ones through this node
+        let replacements: Vec<(OsmEdge, OsmEdge, OsmEdge)> =
+            if outgoing.len() == 1 && incoming.len() == 1 {
+                let from = *incoming.iter().next().unwrap();
+                let to = *outgoing.iter().next().unwrap();
+                if from == to {
+                    continue;
+                }
+                vec![((from, node), (node, to), (from, to))]
+            } else if outgoing.len() == 2 && incoming == outgoing {
+                let mut neighbours = outgoing.iter().copied();
+                let a = neighbours.next().unwrap();
+                let c = neighbours.next().unwrap();
+                vec![
+                    ((a, node), (node, c), (a, c)),
+                    ((c, node), (node, a), (c, a)),
+                ]
+            } else {
+                continue;
+            };
+
+        let collapsible = replacements
+            .iter()
+            .all(|(incoming_edge, outgoing_edge, joined)| {
+                edges[incoming_edge] == edges[outgoing_edge] && !edges.contains_key(joined)
+            });
+        if !collapsible {
+            continue;
+        }
+
+        for (incoming_edge, outgoing_edge, joined) in replacements {
+            let attributes = edges.remove(&incoming_edge).unwrap();
+            edges.remove(&outgoing_edge);
+            edges.insert(joined, attributes);
+
+            let (from, to) = joined;
+            successors.get_mut(&from).unwrap().remove(&node);
+            successors.get_mut(&from).unwrap().insert(to);
+            predecessors.get_mut(&to).unwrap().remove(&node);
+            predecessors.get_mut(&to).unwrap().insert(from);
+        }
+        successors.remove(&node);
+        predecessors.remove(&node);
+    }
+}
+
+fn parse_attribute<T: std::str::FromStr>(
+    element: &roxmltree::Node,
+    element_name: &'static str,
+    attribute: &'static str,
+) -> Result<T, OsmImportError> {
+    let value = element
+        .attribute(attribute)
+        .ok_or(OsmImportError::MissingAttribute {
+            element: element_name,
+            attribute,
+        })?;
+    value.parse().map_err(|_| OsmImportError::InvalidAttribute {
+        element: element_name,
+        attribute,
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A two way residential street crossed by a one way primary road.
+    //
+    //              4
+    //              |
+    //              V
+    //    1 <-> 2 <-> 3 <-> 6
+    //              |
+    //              V
+    //              5
+    const OSM_EXTRACT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="51.5000" lon="-0.1020"/>
+  <node id="2" lat="51.5000" lon="-0.1010"/>
+  <node id="3" lat="51.5000" lon="-0.1000"/>
+  <node id="4" lat="51.5010" lon="-0.1000"/>
+  <node id="5" lat="51.4990" lon="-0.1000"/>
+  <node id="6" lat="51.5000" lon="-0.0990"/>
+  <node id="7" lat="51.5020" lon="-0.1020"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="6"/>
+    <tag k="highway" v="residential"/>
+    <tag k="maxspeed" v="30 mph"/>
+  </way>
+  <way id="11">
+    <nd ref="4"/>
+    <nd ref="3"/>
+    <nd ref="5"/>
+    <tag k="highway" v="primary"/>
+    <tag k="oneway" v="yes"/>
+    <tag k="lanes" v="2"/>
+    <tag k="maxspeed" v="50"/>
+  </way>
+  <way id="12">
+    <nd ref="1"/>
+    <nd ref="7"/>
+    <tag k="highway" v="footway"/>
+  </way>
+</osm>"#;
+
+    #[test]
+    fn parse_osm_builds_directed_edges_and_collapses_chains() {
+        let graph = parse_osm(OSM_EXTRACT).unwrap();
+
+        // Node 2 is collapsed and node 7 is only used by a footway.
+        // The remaining osm nodes 1, 3, 4, 5, 6 are indexed in id order.
+        assert_eq!(graph.nodes.len(), 5);
+        let expected_edges = HashSet::from([(0, 1), (1, 0), (1, 4), (4, 1), (2, 1), (1, 3)]);
+        assert_eq!(graph.edges, expected_edges);
+        assert_eq!(graph.source_nodes, HashSet::from([2]));
+        assert_eq!(graph.dest_nodes, HashSet::from([3]));
+    }
+
+    #[test]
+    fn parse_osm_carries_speed_and_lane_tags() {
+        let graph = parse_osm(OSM_EXTRACT).unwrap();
+
+        let residential = &graph.edge_attributes[&(0, 1)];
+        assert!((residential.speed_limit.unwrap() - 13.4112).abs() < 0.001);
+        assert_eq!(residential.lanes, None);
+
+        let primary = &graph.edge_attributes[&(2, 1)];
+        assert!((primary.speed_limit.unwrap() - 13.8889).abs() < 0.001);
+        assert_eq!(primary.lanes, Some(2));
+    }
+
+    #[test]
+    fn parse_osm_projects_to_meters() {
+        let graph = parse_osm(OSM_EXTRACT).unwrap();
+
+        // 0.002 degrees of latitude between nodes 4 and 5 is roughly 222 meters
+        let north_south = graph.nodes[2].position.distance(graph.nodes[3].position);
+        assert!((north_south - 222.4).abs() < 0.5, "{}", north_south);
+        // North is along -z
+        assert!(graph.nodes[2].position.z < graph.nodes[3].position.z);
+
+        // The projection is kept so positions can be converted back
+        let (lat, lon) = graph.projection.unwrap().unproject(graph.nodes[2].position);
+        assert!((lat - 51.501).abs() < 1e-6, "{}", lat);
+        assert!((lon - -0.1).abs() < 1e-6, "{}", lon);
+    }
+
+    #[test]
+    fn parse_max_speed_handles_units() {
+        assert_eq!(parse_max_speed("36"), Some(10.));
+        assert_eq!(parse_max_speed("36 km/h"), Some(10.));
+        assert!((parse_max_speed("10 mph").unwrap() - 4.4704).abs() < 0.0001);
+        assert_eq!(parse_max_speed("none"), None);
+    }
+}
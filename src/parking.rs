@@ -0,0 +1,751 @@
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use crate::simulation_rng::SimulationRng;
+use crate::{
+    node_graph::NodeGraph,
+    simulation_clock::SimulationClock,
+    vehicle_id_generator::VehicleIdGenerator,
+    vehicle_types::{VehicleType, VehicleTypes},
+    vehicles::{self, Vehicle},
+};
+
+// Vehicles give up looking for a space after finding this many areas full
+const MAX_AREAS_TRIED: usize = 4;
+// The length of each space drawn along the curb
+#[cfg(feature = "bevy")]
+const SPACE_LENGTH: f32 = 0.7;
+
+#[derive(Debug)]
+pub enum ParkingError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // An area doesn't fit the network it is used with
+    Invalid(String),
+}
+
+impl fmt::Display for ParkingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParkingError::Io(error) => write!(f, "failed to read parking areas: {}", error),
+            ParkingError::Json(error) => write!(f, "failed to parse parking areas: {}", error),
+            ParkingError::Invalid(message) => write!(f, "invalid parking area: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ParkingError {}
+
+impl From<io::Error> for ParkingError {
+    fn from(error: io::Error) -> Self {
+        ParkingError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ParkingError {
+    fn from(error: serde_json::Error) -> Self {
+        ParkingError::Json(error)
+    }
+}
+
+// Spaces along the curb of an edge, entered and left at a single point
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingArea {
+    pub name: String,
+    pub edge: (usize, usize),
+    // How far along the edge vehicles pull in and out, from 0 at the start to
+    // 1 at the end
+    #[serde(default = "default_area_position")]
+    pub position: f32,
+    pub capacity: usize,
+    // Spaces taken, including by vehicles still pulling in
+    #[serde(default)]
+    pub occupied: usize,
+    // Vehicles which parked here
+    #[serde(default)]
+    pub arrivals: usize,
+    // Vehicles which found the area full when they reached it
+    #[serde(default)]
+    pub turned_away: usize,
+    // The types of the vehicles which parked here, so they leave as the same
+    // type. Spaces occupied from the start have no entry.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parked_types: Vec<VehicleType>,
+}
+
+fn default_area_position() -> f32 {
+    0.5
+}
+
+impl ParkingArea {
+    pub fn has_space(&self) -> bool {
+        self.occupied < self.capacity
+    }
+
+    // Takes a space for a vehicle of the given type
+    pub fn park(&mut self, vehicle_type: VehicleType) {
+        self.occupied += 1;
+        self.arrivals += 1;
+        self.parked_types.push(vehicle_type);
+    }
+
+    // Picks the space of the next vehicle to leave and its type. Vehicles in
+    // spaces occupied from the start are given a type which could have
+    // driven there.
+    fn next_to_leave(
+        &self,
+        vehicle_types: &VehicleTypes,
+        rng: &mut impl Rng,
+    ) -> (usize, VehicleType) {
+        let space = rng.gen_range(0..self.occupied);
+        let vehicle_type = match self.parked_types.get(space) {
+            Some(vehicle_type) => vehicle_type.clone(),
+            None => vehicle_types.choose_parked(rng),
+        };
+        (space, vehicle_type)
+    }
+
+    // Frees a space picked by next_to_leave
+    fn leave(&mut self, space: usize) {
+        self.occupied -= 1;
+        if space < self.parked_types.len() {
+            self.parked_types.swap_remove(space);
+        }
+    }
+
+    fn validate(&self, node_graph: &NodeGraph) -> Result<(), ParkingError> {
+        let invalid =
+            |message: &str| Err(ParkingError::Invalid(format!("{}: {}", self.name, message)));
+        if !node_graph.edges.contains(&self.edge) {
+            return invalid("its edge isn't in the network");
+        }
+        if !(0. ..1.).contains(&self.position) {
+            return invalid("it is off its edge");
+        }
+        if self.occupied > self.capacity {
+            return invalid("more spaces are occupied than it has");
+        }
+        if self.parked_types.len() > self.occupied {
+            return invalid("more vehicles are parked than spaces are occupied");
+        }
+        Ok(())
+    }
+}
+
+// How vehicles use the parking areas
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingSettings {
+    // The share of vehicles entering the network that drive to a parking
+    // area, and of vehicles leaving a space that park somewhere else
+    pub parking_share: f32,
+    // How long vehicles stay parked on average, in seconds
+    pub mean_parking_duration: f32,
+    // Seconds spent pulling into or out of a space, blocking the lane
+    pub maneuver_time: f32,
+}
+
+impl Default for ParkingSettings {
+    fn default() -> Self {
+        ParkingSettings {
+            parking_share: 0.3,
+            mean_parking_duration: 300.,
+            maneuver_time: 3.,
+        }
+    }
+}
+
+// The parking areas of the network
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParkingAreas {
+    pub areas: Vec<ParkingArea>,
+    #[serde(default)]
+    pub settings: ParkingSettings,
+}
+
+impl ParkingAreas {
+    // Checks every area fits the network
+    pub fn validate(&self, node_graph: &NodeGraph) -> Result<(), ParkingError> {
+        self.areas
+            .iter()
+            .try_for_each(|area| area.validate(node_graph))
+    }
+
+    // Picks where a vehicle which found an area full looks next: the closest
+    // area with a free space, or failing that the closest other area, circling
+    // until a space frees up. Returns the area and the route to it from the
+    // given node, which runs to the end of the area's edge.
+    pub fn next_area(
+        &self,
+        node_graph: &NodeGraph,
+        from_node: usize,
+        full_area: usize,
+    ) -> Option<(usize, Vec<usize>)> {
+        self.areas
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != full_area || self.areas.len() == 1)
+            .filter_map(|(index, area)| {
+                Some((index, area, route_to_area(node_graph, from_node, area)?))
+            })
+            .min_by_key(|(index, area, route)| (!area.has_space(), route.len(), *index))
+            .map(|(index, _, route)| (index, route))
+    }
+
+    // Parked vehicles leave at random, pulling out of their space and driving
+    // to a destination node or, for some, another parking area. Vehicles only
+    // pull out when the lane beside their area is clear of the given vehicles
+    // and the others leaving, otherwise they stay parked. Returns the vehicles
+    // leaving.
+    pub fn depart(
+        &mut self,
+        node_graph: &NodeGraph,
+        vehicles: &[&Vehicle],
+        vehicle_types: &VehicleTypes,
+        vehicle_id_generator: &mut VehicleIdGenerator,
+        rng: &mut impl Rng,
+        clock: &SimulationClock,
+    ) -> Vec<Vehicle> {
+        let settings = self.settings.clone();
+        let departure_chance = clock.delta_seconds() / settings.mean_parking_duration.max(1.);
+        let mut dest_nodes: Vec<usize> = node_graph.dest_nodes.iter().copied().collect();
+        dest_nodes.sort();
+
+        let mut leaving: Vec<Vehicle> = Vec::new();
+        for area_index in 0..self.areas.len() {
+            let area = &self.areas[area_index];
+            if !node_graph.edges.contains(&area.edge) {
+                continue;
+            }
+            let departures = (0..area.occupied)
+                .filter(|_| rng.gen::<f32>() < departure_chance)
+                .count();
+            for _ in 0..departures {
+                let area = &self.areas[area_index];
+                let (start_node, end_node) = area.edge;
+                let target = if rng.gen::<f32>() < settings.parking_share {
+                    let other_areas: Vec<usize> = (0..self.areas.len())
+                        .filter(|index| *index != area_index)
+                        .collect();
+                    other_areas.choose(rng).and_then(|target_index| {
+                        let target_area = &self.areas[*target_index];
+                        let route = route_to_area(node_graph, end_node, target_area)?;
+                        Some((*target_index, route))
+                    })
+                } else {
+                    None
+                };
+                let route = match target {
+                    Some((_, ref route)) => Some(route.clone()),
+                    None => dest_nodes
+                        .choose(rng)
+                        .and_then(|dest_node| node_graph.find_path(end_node, *dest_node)),
+                };
+                // Vehicles with nowhere to go stay parked
+                let Some(route) = route else {
+                    continue;
+                };
+                let (space, vehicle_type) = area.next_to_leave(vehicle_types, rng);
+                let has_room = vehicles::has_room_at(
+                    vehicles.iter().copied().chain(leaving.iter()),
+                    node_graph,
+                    area.edge,
+                    area.position,
+                    vehicle_type.length,
+                );
+                if !has_room {
+                    continue;
+                }
+
+                let mut path = vec![start_node];
+                path.extend(route);
+                let position = area.position;
+                let parking_trip = ParkingTrip::leaving(
+                    settings.maneuver_time,
+                    target.map(|(index, _)| (index, &self.areas[index])),
+                );
+                let vehicle = Vehicle::new(
+                    vehicle_id_generator.get_id(),
+                    vehicle_type,
+                    path,
+                    clock.elapsed_seconds(),
+                    rng,
+                )
+                .with_parking_trip(parking_trip, position);
+                leaving.push(vehicle);
+                self.areas[area_index].leave(space);
+            }
+        }
+        leaving
+    }
+}
+
+// The route from a node to the end of a parking area's edge
+pub fn route_to_area(
+    node_graph: &NodeGraph,
+    from_node: usize,
+    area: &ParkingArea,
+) -> Option<Vec<usize>> {
+    let mut route = node_graph.find_path(from_node, area.edge.0)?;
+    route.push(area.edge.1);
+    Some(route)
+}
+
+// Loads parking areas from a JSON file, checking they fit the network
+pub fn load_parking_areas(
+    path: impl AsRef<Path>,
+    node_graph: &NodeGraph,
+) -> Result<ParkingAreas, ParkingError> {
+    let parking_areas: ParkingAreas = serde_json::from_str(&fs::read_to_string(path)?)?;
+    parking_areas.validate(node_graph)?;
+    Ok(parking_areas)
+}
+
+// A vehicle's progress into or out of a parking space, and its search for one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingTrip {
+    // The area the vehicle is driving to and where it pulls in on the last
+    // edge of its path, None once it has parked, given up or isn't parking
+    pub target: Option<(usize, f32)>,
+    // Whether the vehicle has reached its target and is waiting to be given a
+    // space or turned away
+    pub reached_target: bool,
+    // Seconds left pulling into or out of a space
+    pub maneuver_remaining: f32,
+    // Whether the vehicle is pulling in, and leaves the network once done
+    pub is_parking: bool,
+    // The simulation time and distance driven when the vehicle first found
+    // its area full
+    pub search_start: Option<(f32, f32)>,
+    pub areas_tried: usize,
+}
+
+impl ParkingTrip {
+    // A vehicle driving to park in the given area
+    pub fn to_area(area_index: usize, area: &ParkingArea) -> Self {
+        ParkingTrip {
+            target: Some((area_index, area.position)),
+            reached_target: false,
+            maneuver_remaining: 0.,
+            is_parking: false,
+            search_start: None,
+            areas_tried: 0,
+        }
+    }
+
+    // A vehicle pulling out of a space, optionally to park somewhere else
+    pub fn leaving(maneuver_time: f32, target: Option<(usize, &ParkingArea)>) -> Self {
+        ParkingTrip {
+            target: target.map(|(area_index, area)| (area_index, area.position)),
+            reached_target: false,
+            maneuver_remaining: maneuver_time,
+            is_parking: false,
+            search_start: None,
+            areas_tried: 0,
+        }
+    }
+
+    pub fn is_maneuvering(&self) -> bool {
+        self.maneuver_remaining > 0.
+    }
+
+    // Whether the vehicle has finished pulling into a space
+    pub fn is_parked(&self) -> bool {
+        self.is_parking && !self.is_maneuvering()
+    }
+
+    // Where the vehicle has to stop on the edge at the given path index, if
+    // it pulls in on that edge
+    pub fn target_position(&self, path_index: usize, path_length: usize) -> Option<f32> {
+        let (_, position) = self.target?;
+        (path_index + 2 == path_length && !self.reached_target && !self.is_maneuvering())
+            .then_some(position)
+    }
+
+    // Counts down the time pulling in or out, returning whether the vehicle
+    // has to keep blocking the lane
+    pub fn maneuver(&mut self, delta_seconds: f32) -> bool {
+        if !self.is_maneuvering() {
+            return false;
+        }
+        self.maneuver_remaining -= delta_seconds;
+        self.is_maneuvering()
+    }
+
+    // Takes a space in the target area
+    pub fn park(&mut self, maneuver_time: f32) {
+        self.target = None;
+        self.reached_target = false;
+        self.is_parking = true;
+        // Pulling in takes at least one update so it is seen blocking the lane
+        self.maneuver_remaining = maneuver_time.max(f32::EPSILON);
+    }
+
+    // Looks for a space in another area after finding the target full
+    pub fn search(&mut self, target: Option<(usize, f32)>, time: f32, distance: f32) {
+        self.search_start.get_or_insert((time, distance));
+        self.areas_tried += 1;
+        self.reached_target = false;
+        self.target = target.filter(|_| self.areas_tried < MAX_AREAS_TRIED);
+    }
+}
+
+// How long a vehicle looked for a space and whether it found one
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ParkingSearch {
+    pub vehicle_id: usize,
+    // The area parked in, None if the vehicle gave up
+    pub area: Option<String>,
+    pub areas_tried: usize,
+    // Time and distance spent cruising after finding the first area full
+    pub cruising_time: f32,
+    pub cruising_distance: f32,
+}
+
+// Stores how every vehicle which drove to a parking area got on
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParkingLog {
+    pub searches: Vec<ParkingSearch>,
+}
+
+impl ParkingLog {
+    // Writes one row per vehicle which drove to a parking area
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "vehicle_id,area,areas_tried,cruising_time,cruising_distance"
+        )?;
+        for search in self.searches.iter() {
+            writeln!(
+                writer,
+                "{},{},{},{:.3},{:.3}",
+                search.vehicle_id,
+                search.area.as_deref().unwrap_or(""),
+                search.areas_tried,
+                search.cruising_time,
+                search.cruising_distance
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes the occupancy of each area followed by how long vehicles spent
+    // cruising for a space
+    pub fn write_table(
+        &self,
+        parking_areas: &ParkingAreas,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{:<16} {:>8} {:>8} {:>8} {:>11}",
+            "area", "capacity", "occupied", "arrivals", "turned away"
+        )?;
+        for area in parking_areas.areas.iter() {
+            writeln!(
+                writer,
+                "{:<16} {:>8} {:>8} {:>8} {:>11}",
+                area.name, area.capacity, area.occupied, area.arrivals, area.turned_away
+            )?;
+        }
+        let searches = self.searches.len().max(1) as f32;
+        let gave_up = self
+            .searches
+            .iter()
+            .filter(|search| search.area.is_none())
+            .count();
+        let cruising_time: f32 = self
+            .searches
+            .iter()
+            .map(|search| search.cruising_time)
+            .sum();
+        writeln!(
+            writer,
+            "{} vehicles looked for parking, {} gave up, mean cruising time {:.1} s",
+            self.searches.len(),
+            gave_up,
+            cruising_time / searches
+        )
+    }
+
+    // Writes parking_searches.csv into the given directory
+    pub fn save(&self, directory: impl AsRef<Path>) -> io::Result<()> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        self.write_csv(&mut fs::File::create(
+            directory.join("parking_searches.csv"),
+        )?)
+    }
+}
+
+// Spawns the vehicles leaving parking areas
+#[cfg(feature = "bevy")]
+#[allow(clippy::too_many_arguments)]
+pub fn depart_parked_vehicles(
+    mut commands: Commands,
+    node_graph: Res<NodeGraph>,
+    mut parking_areas: ResMut<ParkingAreas>,
+    vehicle_types: Res<VehicleTypes>,
+    mut vehicle_id_generator: ResMut<VehicleIdGenerator>,
+    mut rng: ResMut<SimulationRng>,
+    clock: Res<SimulationClock>,
+    vehicle_query: Query<&Vehicle>,
+) {
+    let vehicles: Vec<&Vehicle> = vehicle_query.iter().collect();
+    let leaving = parking_areas.depart(
+        &node_graph,
+        &vehicles,
+        &vehicle_types,
+        &mut vehicle_id_generator,
+        &mut rng.0,
+        &clock,
+    );
+    for vehicle in leaving {
+        let position = vehicle.get_world_position(&node_graph);
+        commands.spawn((
+            SpatialBundle::from_transform(Transform::from_translation(position)),
+            vehicle,
+        ));
+    }
+}
+
+// Draws each space along the right curb at the area's entrance, green when
+// free and grey when taken
+#[cfg(feature = "bevy")]
+pub fn show_parking_areas(
+    mut gizmos: Gizmos,
+    node_graph: Res<NodeGraph>,
+    parking_areas: Res<ParkingAreas>,
+) {
+    for area in parking_areas.areas.iter() {
+        let (Some(start), Some(end)) = (
+            node_graph.nodes.get(area.edge.0),
+            node_graph.nodes.get(area.edge.1),
+        ) else {
+            continue;
+        };
+        let forward = (end.position - start.position).normalize_or_zero();
+        let right = forward.cross(Vec3::Y).normalize_or_zero();
+        let curb_offset =
+            node_graph.lane_count(area.edge) as f32 * crate::node_graph::LANE_WIDTH / 2. + 0.25;
+        let center = start.position.lerp(end.position, area.position) + right * curb_offset;
+        for space in 0..area.capacity {
+            let along = (space as f32 - (area.capacity as f32 - 1.) / 2.) * SPACE_LENGTH;
+            let color = if space < area.occupied {
+                Color::srgb(0.5, 0.5, 0.55)
+            } else {
+                Color::srgb(0.2, 0.8, 0.3)
+            };
+            gizmos.cuboid(
+                Transform::from_translation(center + forward * along)
+                    .looking_to(forward, Vec3::Y)
+                    .with_scale(Vec3::new(0.4, 0.05, SPACE_LENGTH * 0.9)),
+                color,
+            );
+        }
+    }
+}
+
+// Saves the parking searches to the working directory when the app exits
+#[cfg(feature = "bevy")]
+pub fn save_parking_log(mut exit_events: EventReader<AppExit>, parking_log: Res<ParkingLog>) {
+    if exit_events.read().next().is_none() || parking_log.searches.is_empty() {
+        return;
+    }
+
+    match parking_log.save(".") {
+        Ok(()) => info!(
+            "Saved {} parking searches to parking_searches.csv",
+            parking_log.searches.len()
+        ),
+        Err(error) => error!("Failed to save parking log: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        simulation::{Simulation, SimulationConfig},
+        vehicle_types::FleetShare,
+    };
+
+    fn area(name: &str, edge: (usize, usize), capacity: usize, occupied: usize) -> ParkingArea {
+        ParkingArea {
+            name: name.to_string(),
+            edge,
+            position: 0.5,
+            capacity,
+            occupied,
+            arrivals: 0,
+            turned_away: 0,
+            parked_types: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn next_area_prefers_the_closest_area_with_space() {
+        let graph = NodeGraph::create_highway_merge();
+        let mut parking_areas = ParkingAreas {
+            areas: vec![
+                area("full", (1, 2), 2, 2),
+                area("near", (3, 4), 2, 2),
+                area("far", (4, 6), 2, 0),
+            ],
+            ..Default::default()
+        };
+
+        // The closest area is full so the vehicle drives on to the far one
+        assert_eq!(
+            parking_areas.next_area(&graph, 2, 0),
+            Some((2, vec![2, 3, 4, 6]))
+        );
+
+        // When every area is full the vehicle circles through the closest
+        parking_areas.areas[2].occupied = 2;
+        assert_eq!(
+            parking_areas.next_area(&graph, 2, 0),
+            Some((1, vec![2, 3, 4]))
+        );
+
+        // Areas behind the vehicle can't be reached on this network
+        assert_eq!(parking_areas.next_area(&graph, 6, 0), None);
+    }
+
+    #[test]
+    fn parked_vehicles_leave_as_the_type_they_arrived_as() {
+        let graph = NodeGraph::create_highway_merge();
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let mut clock = SimulationClock::default();
+        clock.tick(Duration::from_secs(1));
+        // A fleet of mostly buses and emergency vehicles, neither of which park
+        let vehicle_types = VehicleTypes {
+            fleet: [
+                VehicleType::bus(),
+                VehicleType::emergency(),
+                VehicleType::car(),
+            ]
+            .into_iter()
+            .zip([10., 10., 1.])
+            .map(|(vehicle_type, share)| FleetShare {
+                vehicle_type,
+                share,
+            })
+            .collect(),
+        };
+        let mut parked = area("parked", (1, 2), 4, 0);
+        parked.park(VehicleType::truck());
+        let mut parking_areas = ParkingAreas {
+            areas: vec![parked, area("prefilled", (3, 4), 4, 1)],
+            settings: ParkingSettings {
+                parking_share: 0.,
+                // Every vehicle leaves after a single one second tick
+                mean_parking_duration: 1.,
+                maneuver_time: 2.,
+            },
+        };
+
+        let leaving = parking_areas.depart(
+            &graph,
+            &[],
+            &vehicle_types,
+            &mut VehicleIdGenerator::default(),
+            &mut rng,
+            &clock,
+        );
+        let leaving_types: Vec<&str> = leaving
+            .iter()
+            .map(|vehicle| vehicle.vehicle_type().name.as_str())
+            .collect();
+        assert_eq!(leaving_types, ["truck", "car"]);
+        assert!(parking_areas
+            .areas
+            .iter()
+            .all(|area| area.occupied == 0 && area.parked_types.is_empty()));
+    }
+
+    #[test]
+    fn parked_vehicles_wait_for_the_lane_to_clear() {
+        let graph = NodeGraph::create_highway_merge();
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let mut clock = SimulationClock::default();
+        clock.tick(Duration::from_secs(1));
+        let vehicle_types = VehicleTypes::default();
+        let passing = Vehicle::new(9, VehicleType::car(), vec![1, 2, 3], 0., &mut rng)
+            .with_parking_trip(ParkingTrip::leaving(0., None), 0.5);
+        let mut parking_areas = ParkingAreas {
+            areas: vec![area("curb", (1, 2), 4, 2)],
+            settings: ParkingSettings {
+                parking_share: 0.,
+                mean_parking_duration: 1.,
+                maneuver_time: 2.,
+            },
+        };
+        let mut depart = |vehicles: &[&Vehicle], parking_areas: &mut ParkingAreas| {
+            parking_areas.depart(
+                &graph,
+                vehicles,
+                &vehicle_types,
+                &mut VehicleIdGenerator::default(),
+                &mut rng,
+                &clock,
+            )
+        };
+
+        // A vehicle driving past the area keeps everyone parked
+        assert!(depart(&[&passing], &mut parking_areas).is_empty());
+        assert_eq!(parking_areas.areas[0].occupied, 2);
+
+        // Once it is clear only one vehicle pulls out at a time
+        let leaving = depart(&[], &mut parking_areas);
+        assert_eq!(leaving.len(), 1);
+        assert_eq!(parking_areas.areas[0].occupied, 1);
+    }
+
+    #[test]
+    fn vehicles_park_and_cruise_when_areas_are_full() {
+        let config = SimulationConfig {
+            seed: Some(5),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(NodeGraph::create_highway_merge(), &config);
+        simulation.parking_areas = ParkingAreas {
+            areas: vec![area("small", (1, 2), 2, 0), area("large", (3, 4), 20, 0)],
+            settings: ParkingSettings {
+                parking_share: 1.,
+                mean_parking_duration: 10_000.,
+                maneuver_time: 2.,
+            },
+        };
+        simulation.run_for(Duration::from_secs(60));
+
+        let parking_areas = &simulation.parking_areas;
+        let parking_log = &simulation.parking_log;
+        let (small, large) = (&parking_areas.areas[0], &parking_areas.areas[1]);
+        assert_eq!(small.occupied, 2);
+        assert!(small.turned_away > 0);
+        assert!(large.arrivals > 0);
+        assert_eq!(small.arrivals + large.arrivals, parking_log.searches.len());
+
+        // Vehicles turned away from the small area found space further on
+        assert!(parking_log
+            .searches
+            .iter()
+            .any(|search| search.areas_tried == 2 && search.cruising_distance > 0.));
+    }
+}
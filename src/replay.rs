@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    trajectory_recording::{TrajectoryFrame, TrajectoryRecording},
+    vehicle_types::VehicleType,
+    vehicles,
+};
+
+// How far the replay jumps when scrubbing with shift held, in seconds
+const SCRUB_JUMP: f32 = 5.;
+const MIN_PLAYBACK_SPEED: f32 = 0.125;
+const MAX_PLAYBACK_SPEED: f32 = 16.;
+
+// Plays back a recording without running the simulation
+#[derive(Resource)]
+pub struct ReplayState {
+    recording: TrajectoryRecording,
+    // The replay time in seconds and the frame shown for it
+    time: f32,
+    frame_index: usize,
+    pub playing: bool,
+    pub playback_speed: f32,
+}
+
+impl ReplayState {
+    pub fn new(recording: TrajectoryRecording) -> Self {
+        let time = recording.frames.first().map_or(0., |frame| frame.time);
+        ReplayState {
+            recording,
+            time,
+            frame_index: 0,
+            playing: true,
+            playback_speed: 1.,
+        }
+    }
+
+    pub fn current_frame(&self) -> Option<&TrajectoryFrame> {
+        self.recording.frames.get(self.frame_index)
+    }
+
+    // Moves to the last frame at or before the given time
+    pub fn seek(&mut self, time: f32) {
+        let start = self.recording.frames.first().map_or(0., |frame| frame.time);
+        self.time = time.clamp(start, self.recording.duration());
+        self.frame_index = self
+            .recording
+            .frames
+            .partition_point(|frame| frame.time <= self.time)
+            .saturating_sub(1);
+    }
+
+    // Moves by a number of frames and pauses so the frame can be inspected
+    pub fn step(&mut self, frames: isize) {
+        self.playing = false;
+        let Some(last_index) = self.recording.frames.len().checked_sub(1) else {
+            return;
+        };
+        self.frame_index = self
+            .frame_index
+            .saturating_add_signed(frames)
+            .min(last_index);
+        self.time = self.recording.frames[self.frame_index].time;
+    }
+
+    // Advances playback by the given real time
+    fn advance(&mut self, delta_seconds: f32) {
+        if !self.playing {
+            return;
+        }
+        self.seek(self.time + delta_seconds * self.playback_speed);
+        if self.time >= self.recording.duration() {
+            self.playing = false;
+        }
+    }
+}
+
+// Marks the entities drawing recorded vehicles
+#[derive(Component)]
+pub struct ReplayVehicle {
+    vehicle_id: u32,
+}
+
+#[derive(Component)]
+pub struct ReplayStatusText;
+
+// Space plays or pauses, the arrow keys step a frame at a time (or jump
+// several seconds with shift held), up and down change the playback speed
+// and home restarts the replay
+pub fn control_replay(keyboard: Res<ButtonInput<KeyCode>>, mut replay_state: ResMut<ReplayState>) {
+    let jump = keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if keyboard.just_pressed(KeyCode::Space) {
+        if replay_state.time >= replay_state.recording.duration() {
+            replay_state.seek(0.);
+        }
+        replay_state.playing = !replay_state.playing;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        if jump {
+            let time = replay_state.time + SCRUB_JUMP;
+            replay_state.seek(time);
+        } else {
+            replay_state.step(1);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        if jump {
+            let time = replay_state.time - SCRUB_JUMP;
+            replay_state.seek(time);
+        } else {
+            replay_state.step(-1);
+        }
+    }
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        replay_state.playback_speed = (replay_state.playback_speed * 2.).min(MAX_PLAYBACK_SPEED);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        replay_state.playback_speed = (replay_state.playback_speed / 2.).max(MIN_PLAYBACK_SPEED);
+    }
+    if keyboard.just_pressed(KeyCode::Home) {
+        replay_state.seek(0.);
+    }
+}
+
+pub fn advance_replay(time: Res<Time>, mut replay_state: ResMut<ReplayState>) {
+    if replay_state.playing {
+        replay_state.advance(time.delta_seconds());
+    }
+}
+
+// Moves the vehicle entities to their positions in the current frame,
+// spawning and despawning them as vehicles enter and leave the recording
+pub fn show_replay_vehicles(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    replay_state: Res<ReplayState>,
+    mut vehicle_query: Query<(Entity, &ReplayVehicle, &mut Transform)>,
+) {
+    if !replay_state.is_changed() {
+        return;
+    }
+    let Some(frame) = replay_state.current_frame() else {
+        return;
+    };
+
+    let vehicle_frames: HashMap<u32, Transform> = frame
+        .vehicles
+        .iter()
+        .map(|vehicle| {
+            let transform = Transform::from_translation(vehicle.position)
+                .with_rotation(Quat::from_rotation_y(vehicle.heading));
+            (vehicle.vehicle_id, transform)
+        })
+        .collect();
+
+    let mut shown_vehicles = Vec::new();
+    for (entity, replay_vehicle, mut transform) in &mut vehicle_query {
+        match vehicle_frames.get(&replay_vehicle.vehicle_id) {
+            Some(vehicle_transform) => {
+                *transform = *vehicle_transform;
+                shown_vehicles.push(replay_vehicle.vehicle_id);
+            }
+            None => commands.entity(entity).despawn(),
+        }
+    }
+
+    for (vehicle_id, transform) in vehicle_frames {
+        if shown_vehicles.contains(&vehicle_id) {
+            continue;
+        }
+        // Recordings don't store vehicle types so every vehicle is shown as a car
+        let (mesh, material) =
+            vehicles::vehicle_mesh(&mut meshes, &mut materials, &VehicleType::car(), false);
+        commands.spawn((
+            PbrBundle {
+                mesh,
+                material,
+                transform,
+                ..default()
+            },
+            ReplayVehicle { vehicle_id },
+        ));
+    }
+}
+
+pub fn spawn_replay_status(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 16.,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.),
+            left: Val::Px(10.),
+            ..default()
+        }),
+        ReplayStatusText,
+    ));
+}
+
+// Shows the replay time, frame and playback state
+pub fn update_replay_status(
+    replay_state: Res<ReplayState>,
+    mut text_query: Query<&mut Text, With<ReplayStatusText>>,
+) {
+    if !replay_state.is_changed() {
+        return;
+    }
+
+    let frame_count = replay_state.recording.frames.len();
+    let state = if replay_state.playing {
+        "playing"
+    } else {
+        "paused"
+    };
+    let status = format!(
+        "{:.2} s / {:.2} s   frame {} / {}   {} x{}\nSpace play/pause, Left/Right step (Shift to jump), Up/Down speed, Home restart",
+        replay_state.time,
+        replay_state.recording.duration(),
+        (replay_state.frame_index + 1).min(frame_count),
+        frame_count,
+        state,
+        replay_state.playback_speed
+    );
+    for mut text in &mut text_query {
+        text.sections[0].value = status.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_graph::NodeGraph;
+
+    fn replay_state() -> ReplayState {
+        let mut recording = TrajectoryRecording::new(&NodeGraph::create());
+        for tick in 1..=10 {
+            recording.frames.push(TrajectoryFrame {
+                time: tick as f32 * 0.5,
+                vehicles: Vec::new(),
+            });
+        }
+        ReplayState::new(recording)
+    }
+
+    #[test]
+    fn seek_shows_the_last_frame_before_the_time() {
+        let mut replay_state = replay_state();
+        assert_eq!(replay_state.frame_index, 0);
+
+        replay_state.seek(1.7);
+        assert_eq!(replay_state.frame_index, 2);
+        replay_state.seek(-3.);
+        assert_eq!(replay_state.frame_index, 0);
+        replay_state.seek(100.);
+        assert_eq!(replay_state.frame_index, 9);
+        assert_eq!(replay_state.time, 5.);
+    }
+
+    #[test]
+    fn step_pauses_and_stays_in_range() {
+        let mut replay_state = replay_state();
+        replay_state.step(3);
+        assert!(!replay_state.playing);
+        assert_eq!(replay_state.frame_index, 3);
+        assert_eq!(replay_state.time, 2.);
+        replay_state.step(-10);
+        assert_eq!(replay_state.frame_index, 0);
+        replay_state.step(20);
+        assert_eq!(replay_state.frame_index, 9);
+    }
+
+    #[test]
+    fn advance_stops_at_the_end() {
+        let mut replay_state = replay_state();
+        replay_state.playback_speed = 2.;
+        replay_state.advance(1.);
+        assert_eq!(replay_state.frame_index, 4);
+        replay_state.advance(10.);
+        assert_eq!(replay_state.frame_index, 9);
+        assert!(!replay_state.playing);
+    }
+}
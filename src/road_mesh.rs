@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+
+use crate::{
+    intersection_delay,
+    node_graph::{EdgeAttributes, NodeGraph, LANE_WIDTH},
+};
+
+// Roads sit just under the vehicles, which are centered on the node height,
+// with markings slightly above the road so they don't flicker
+const ROAD_HEIGHT: f32 = -0.12;
+const MARKING_HEIGHT: f32 = -0.11;
+const MARKING_WIDTH: f32 = 0.1;
+const DASH_LENGTH: f32 = 1.;
+const DASH_GAP: f32 = 1.;
+const STOP_LINE_DEPTH: f32 = 0.3;
+// How far junction pads extend past the widest road meeting them
+const JUNCTION_PAD_MARGIN: f32 = 0.3;
+const JUNCTION_PAD_SEGMENTS: usize = 24;
+
+// Collects flat triangles facing up
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    // Adds a quad from corners given in order around its edge, in either
+    // direction
+    fn add_quad(&mut self, corners: [Vec3; 4]) {
+        let start = self.positions.len() as u32;
+        let uvs = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+        for (corner, uv) in corners.iter().zip(uvs) {
+            self.positions.push(corner.to_array());
+            self.uvs.push(uv);
+        }
+        let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        let triangles = if normal.y >= 0. {
+            [0, 1, 2, 0, 2, 3]
+        } else {
+            [0, 2, 1, 0, 3, 2]
+        };
+        self.indices
+            .extend(triangles.iter().map(|index| start + index));
+    }
+
+    fn add_disc(&mut self, center: Vec3, radius: f32) {
+        let start = self.positions.len() as u32;
+        self.positions.push(center.to_array());
+        self.uvs.push([0.5, 0.5]);
+        for segment in 0..JUNCTION_PAD_SEGMENTS {
+            let angle = segment as f32 / JUNCTION_PAD_SEGMENTS as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            self.positions
+                .push((center + Vec3::new(cos, 0., -sin) * radius).to_array());
+            self.uvs.push([0.5 + cos / 2., 0.5 - sin / 2.]);
+        }
+        // Vertices go counterclockwise when viewed from above
+        for segment in 0..JUNCTION_PAD_SEGMENTS as u32 {
+            let next = (segment + 1) % JUNCTION_PAD_SEGMENTS as u32;
+            self.indices
+                .extend([start, start + 1 + segment, start + 1 + next]);
+        }
+    }
+
+    fn build(self) -> Mesh {
+        let normals = vec![[0., 1., 0.]; self.positions.len()];
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, self.positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs)
+        .with_inserted_indices(Indices::U32(self.indices))
+    }
+}
+
+// The road surfaces and junction pads, and the markings painted on them
+pub struct RoadMeshes {
+    pub surface: Mesh,
+    pub markings: Mesh,
+}
+
+// Builds road meshes from the edges of the graph. Each edge becomes a road as
+// wide as its lanes with dashed lines between them. Junction nodes get a
+// round pad, and edges entering a junction from outside it get a stop line
+// at the edge of the pad.
+pub fn build_road_meshes(node_graph: &NodeGraph) -> RoadMeshes {
+    let mut surface = MeshBuilder::default();
+    let mut markings = MeshBuilder::default();
+    let road_height = Vec3::Y * ROAD_HEIGHT;
+    let marking_height = Vec3::Y * MARKING_HEIGHT;
+
+    let junction_nodes = intersection_delay::junction_nodes(node_graph);
+    let mut pad_radii: HashMap<usize, f32> = HashMap::new();
+    for edge in node_graph.edges.iter() {
+        let half_width = node_graph.lane_count(*edge) as f32 * LANE_WIDTH / 2.;
+        for node in [edge.0, edge.1] {
+            if junction_nodes.contains(&node) {
+                let radius = pad_radii.entry(node).or_default();
+                *radius = radius.max(half_width + JUNCTION_PAD_MARGIN);
+            }
+        }
+    }
+    let mut sorted_pads: Vec<(usize, f32)> = pad_radii
+        .iter()
+        .map(|(node, radius)| (*node, *radius))
+        .collect();
+    sorted_pads.sort_by_key(|(node, _)| *node);
+    for (node, radius) in sorted_pads {
+        surface.add_disc(node_graph.nodes[node].position + road_height, radius);
+    }
+
+    let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+    edges.sort();
+    for (source, dest) in edges {
+        let start = node_graph.nodes[source].position;
+        let end = node_graph.nodes[dest].position;
+        let length = start.xz().distance(end.xz());
+        if length <= f32::EPSILON {
+            continue;
+        }
+        let forward = (end - start).normalize();
+        let right = forward.cross(Vec3::Y).normalize();
+        let lanes = node_graph.lane_count((source, dest));
+        let half_width = lanes as f32 * LANE_WIDTH / 2.;
+
+        surface.add_quad([
+            start - right * half_width + road_height,
+            start + right * half_width + road_height,
+            end + right * half_width + road_height,
+            end - right * half_width + road_height,
+        ]);
+
+        // A strip across the road between two distances along it
+        let strip = |from: f32, to: f32, offset: f32, half_width: f32| {
+            let center = start + right * offset + marking_height;
+            [
+                center + forward * from - right * half_width,
+                center + forward * from + right * half_width,
+                center + forward * to + right * half_width,
+                center + forward * to - right * half_width,
+            ]
+        };
+
+        for divider in 1..lanes {
+            let offset = divider as f32 * LANE_WIDTH - half_width;
+            let mut dash_start = DASH_GAP / 2.;
+            while dash_start + DASH_LENGTH <= length {
+                markings.add_quad(strip(
+                    dash_start,
+                    dash_start + DASH_LENGTH,
+                    offset,
+                    MARKING_WIDTH / 2.,
+                ));
+                dash_start += DASH_LENGTH + DASH_GAP;
+            }
+        }
+
+        if junction_nodes.contains(&dest) && !junction_nodes.contains(&source) {
+            let stop_distance = length - pad_radii[&dest];
+            if stop_distance > STOP_LINE_DEPTH {
+                markings.add_quad(strip(
+                    stop_distance - STOP_LINE_DEPTH,
+                    stop_distance,
+                    0.,
+                    half_width,
+                ));
+            }
+        }
+    }
+
+    RoadMeshes {
+        surface: surface.build(),
+        markings: markings.build(),
+    }
+}
+
+// Marks the entities drawing roads
+#[derive(Component)]
+pub struct RoadMesh;
+
+// The parts of the network roads are built from, kept to tell when they need
+// rebuilding
+pub struct RoadLayout {
+    positions: Vec<Vec3>,
+    edges: HashSet<(usize, usize)>,
+    edge_attributes: HashMap<(usize, usize), EdgeAttributes>,
+}
+
+impl RoadLayout {
+    fn new(node_graph: &NodeGraph) -> Self {
+        RoadLayout {
+            positions: node_graph.nodes.iter().map(|node| node.position).collect(),
+            edges: node_graph.edges.clone(),
+            edge_attributes: node_graph.edge_attributes.clone(),
+        }
+    }
+
+    fn matches(&self, node_graph: &NodeGraph) -> bool {
+        self.positions
+            .iter()
+            .copied()
+            .eq(node_graph.nodes.iter().map(|node| node.position))
+            && self.edges == node_graph.edges
+            && self.edge_attributes == node_graph.edge_attributes
+    }
+}
+
+// Rebuilds the road meshes whenever the layout of the network changes
+pub fn update_road_meshes(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    node_graph: Res<NodeGraph>,
+    road_query: Query<Entity, With<RoadMesh>>,
+    mut last_layout: Local<Option<RoadLayout>>,
+) {
+    if last_layout
+        .as_ref()
+        .is_some_and(|layout| layout.matches(&node_graph))
+    {
+        return;
+    }
+    *last_layout = Some(RoadLayout::new(&node_graph));
+
+    for entity in &road_query {
+        commands.entity(entity).despawn();
+    }
+    let road_meshes = build_road_meshes(&node_graph);
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(road_meshes.surface),
+            material: materials.add(StandardMaterial {
+                base_color: Color::srgb(0.2, 0.2, 0.22),
+                perceptual_roughness: 0.9,
+                ..default()
+            }),
+            ..default()
+        },
+        RoadMesh,
+    ));
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(road_meshes.markings),
+            material: materials.add(StandardMaterial {
+                base_color: Color::srgb(0.95, 0.95, 0.9),
+                perceptual_roughness: 0.6,
+                ..default()
+            }),
+            ..default()
+        },
+        RoadMesh,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_graph::Node;
+
+    fn normals_face_up(mesh: &Mesh) -> bool {
+        let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
+        let positions = positions.as_float3().unwrap();
+        let Some(Indices::U32(indices)) = mesh.indices() else {
+            return false;
+        };
+        indices.chunks(3).all(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from_array(positions[triangle[i] as usize]));
+            (b - a).cross(c - a).y > 0.
+        })
+    }
+
+    #[test]
+    fn multi_lane_roads_have_lane_markings() {
+        let nodes = vec![
+            Node {
+                position: Vec3::ZERO,
+            },
+            Node {
+                position: Vec3::new(0., 0., -10.),
+            },
+        ];
+        let mut graph = NodeGraph::new(nodes, HashSet::from([(0, 1)]));
+        graph.edge_attributes.insert(
+            (0, 1),
+            EdgeAttributes {
+                speed_limit: None,
+                lanes: Some(2),
+            },
+        );
+
+        let road_meshes = build_road_meshes(&graph);
+        assert_eq!(road_meshes.surface.count_vertices(), 4);
+        // One divider made of five dashes, and no junction so no stop line
+        assert_eq!(road_meshes.markings.count_vertices(), 5 * 4);
+        assert!(normals_face_up(&road_meshes.surface));
+        assert!(normals_face_up(&road_meshes.markings));
+    }
+
+    #[test]
+    fn junction_approaches_have_stop_lines() {
+        let graph = NodeGraph::create();
+        let road_meshes = build_road_meshes(&graph);
+
+        // A pad for each of nodes 8-11 and a quad for each of the 16 edges
+        assert_eq!(
+            road_meshes.surface.count_vertices(),
+            4 * (JUNCTION_PAD_SEGMENTS + 1) + 16 * 4
+        );
+        // A stop line on each of the 4 approaches
+        assert_eq!(road_meshes.markings.count_vertices(), 4 * 4);
+        assert!(normals_face_up(&road_meshes.surface));
+        assert!(normals_face_up(&road_meshes.markings));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crosswalks::CrosswalkSettings,
+    emergency_policy::EmergencyPolicy,
+    load_network,
+    node_graph::NodeGraph,
+    parking::{self, ParkingAreas, ParkingError},
+    simulation::{Simulation, SimulationConfig},
+    transit::{self, TransitError, TransitLines},
+    vehicle_types::VehicleTypes,
+};
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // The network named by the scenario couldn't be loaded
+    Network(PathBuf, Box<dyn std::error::Error>),
+    Transit(TransitError),
+    Parking(ParkingError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(error) => write!(f, "failed to read scenario: {}", error),
+            ScenarioError::Json(error) => write!(f, "failed to parse scenario: {}", error),
+            ScenarioError::Network(path, error) => {
+                write!(f, "failed to load network {}: {}", path.display(), error)
+            }
+            ScenarioError::Transit(error) => write!(f, "{}", error),
+            ScenarioError::Parking(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(error: io::Error) -> Self {
+        ScenarioError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ScenarioError {
+    fn from(error: serde_json::Error) -> Self {
+        ScenarioError::Json(error)
+    }
+}
+
+impl From<TransitError> for ScenarioError {
+    fn from(error: TransitError) -> Self {
+        ScenarioError::Transit(error)
+    }
+}
+
+impl From<ParkingError> for ScenarioError {
+    fn from(error: ParkingError) -> Self {
+        ScenarioError::Parking(error)
+    }
+}
+
+// A scenario as written in a file: the network to simulate and the demand
+// put on it. Paths are relative to the scenario file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScenarioFile {
+    // The road network, the demo intersection is used if there is none
+    #[serde(default)]
+    pub network: Option<PathBuf>,
+    #[serde(default)]
+    pub transit: Option<PathBuf>,
+    #[serde(default)]
+    pub parking: Option<PathBuf>,
+    // Seconds between spawned vehicles
+    #[serde(default = "default_spawn_interval")]
+    pub spawn_interval: f32,
+    #[serde(default)]
+    pub vehicle_types: VehicleTypes,
+    #[serde(default)]
+    pub emergency_policy: EmergencyPolicy,
+    #[serde(default)]
+    pub crosswalks: CrosswalkSettings,
+}
+
+fn default_spawn_interval() -> f32 {
+    SimulationConfig::default().spawn_interval.as_secs_f32()
+}
+
+// A network with the demand to simulate on it, ready to be run
+pub struct Scenario {
+    pub node_graph: NodeGraph,
+    pub transit_lines: TransitLines,
+    pub parking_areas: ParkingAreas,
+    pub config: SimulationConfig,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            node_graph: NodeGraph::create(),
+            transit_lines: TransitLines::default(),
+            parking_areas: ParkingAreas::default(),
+            config: SimulationConfig::default(),
+        }
+    }
+}
+
+impl Scenario {
+    // Loads a scenario file along with the network, transit lines and parking
+    // areas it names
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let scenario_file: ScenarioFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        let node_graph = match scenario_file.network {
+            Some(network) => Self::load_network(&directory.join(network))?,
+            None => NodeGraph::create(),
+        };
+        let transit_lines = match scenario_file.transit {
+            Some(transit) => transit::load_transit_lines(directory.join(transit), &node_graph)?,
+            None => TransitLines::default(),
+        };
+        let parking_areas = match scenario_file.parking {
+            Some(parking) => parking::load_parking_areas(directory.join(parking), &node_graph)?,
+            None => ParkingAreas::default(),
+        };
+        let config = SimulationConfig {
+            spawn_interval: Duration::from_secs_f32(scenario_file.spawn_interval.max(0.)),
+            vehicle_types: scenario_file.vehicle_types,
+            emergency_policy: scenario_file.emergency_policy,
+            crosswalk_settings: scenario_file.crosswalks,
+            ..SimulationConfig::default()
+        };
+        Ok(Scenario {
+            node_graph,
+            transit_lines,
+            parking_areas,
+            config,
+        })
+    }
+
+    // A scenario with the default demand on a network loaded from a file
+    pub fn from_network(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        Ok(Scenario {
+            node_graph: Self::load_network(path.as_ref())?,
+            ..Scenario::default()
+        })
+    }
+
+    fn load_network(path: &Path) -> Result<NodeGraph, ScenarioError> {
+        load_network(&path.to_string_lossy())
+            .map_err(|error| ScenarioError::Network(path.to_path_buf(), error))
+    }
+
+    pub fn into_simulation(self) -> Simulation {
+        let mut simulation = Simulation::new(self.node_graph, &self.config);
+        simulation.transit_lines = self.transit_lines;
+        simulation.parking_areas = self.parking_areas;
+        simulation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_file;
+
+    // A directory for the files of one test, emptied before use
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join("traffic-rs-tests").join(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn load_resolves_paths_next_to_the_scenario() {
+        let directory = test_directory("scenario_paths");
+        fs::create_dir_all(directory.join("networks")).unwrap();
+        fs::write(
+            directory.join("networks/t.json"),
+            network_file::write_network_file(&NodeGraph::create_t_junction()),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("scenario.json"),
+            r#"{ "network": "networks/t.json", "spawn_interval": 0.5 }"#,
+        )
+        .unwrap();
+
+        let scenario = Scenario::load(directory.join("scenario.json")).unwrap();
+        assert_eq!(
+            scenario.node_graph.edges,
+            NodeGraph::create_t_junction().edges
+        );
+        assert_eq!(scenario.config.spawn_interval, Duration::from_millis(500));
+        assert!(scenario.transit_lines.lines.is_empty());
+
+        let mut simulation = scenario.into_simulation();
+        simulation.run_for(Duration::from_secs(30));
+        assert!(!simulation.trip_log.records.is_empty());
+    }
+
+    #[test]
+    fn load_reports_a_missing_network() {
+        let directory = test_directory("scenario_missing_network");
+        fs::write(
+            directory.join("scenario.json"),
+            r#"{ "network": "missing.json" }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Scenario::load(directory.join("scenario.json")),
+            Err(ScenarioError::Network(path, _)) if path == directory.join("missing.json")
+        ));
+    }
+}
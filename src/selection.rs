@@ -0,0 +1,329 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    entry_queues::EntryQueues, node_graph::NodeGraph, node_graph_renderer::NodeGraphRenderer,
+    vehicles::Vehicle,
+};
+
+// How close a click has to be to a vehicle or node to pick it, in world units
+const VEHICLE_PICK_RADIUS: f32 = 0.6;
+const NODE_PICK_RADIUS: f32 = 0.8;
+
+// Something in the scene picked with the mouse
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pick {
+    Vehicle(usize),
+    Node(usize),
+}
+
+// Finds what is under a point on the ground. Vehicles are preferred over
+// nodes since they are drawn on top of them.
+pub fn pick(
+    point: Vec3,
+    vehicle_positions: impl Iterator<Item = (usize, Vec3)>,
+    node_graph: &NodeGraph,
+) -> Option<Pick> {
+    let planar_distance = |position: Vec3| (position - point).xz().length();
+    let closest_vehicle = vehicle_positions
+        .map(|(vehicle_id, position)| (vehicle_id, planar_distance(position)))
+        .filter(|(_, distance)| *distance <= VEHICLE_PICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+    if let Some((vehicle_id, _)) = closest_vehicle {
+        return Some(Pick::Vehicle(vehicle_id));
+    }
+
+    node_graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(node, position)| (node, planar_distance(position.position)))
+        .filter(|(_, distance)| *distance <= NODE_PICK_RADIUS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(node, _)| Pick::Node(node))
+}
+
+// Casts the cursor onto the ground plane the network lies on. Returns None if
+// the cursor is outside the window or isn't pointing at the ground.
+pub fn cursor_ground_point(
+    window: &Window,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+) -> Option<Vec3> {
+    let cursor_position = window.cursor_position()?;
+    let ray = camera.viewport_to_world(camera_transform, cursor_position)?;
+    let distance = ray.intersect_plane(Vec3::ZERO, InfinitePlane3d::new(Vec3::Y))?;
+    Some(ray.get_point(distance))
+}
+
+// Selects the vehicle or node under the cursor when the left mouse button is
+// clicked. Clicking empty ground clears the selection.
+pub fn pick_with_mouse(
+    mouse: Res<ButtonInput<MouseButton>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
+    node_graph: Res<NodeGraph>,
+    vehicle_query: Query<(&Vehicle, &Transform)>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let (Ok(window), Ok(camera)) = (window_query.get_single(), camera_query.get_single()) else {
+        return;
+    };
+    let Some(point) = cursor_ground_point(window, camera) else {
+        return;
+    };
+
+    let vehicle_positions = vehicle_query
+        .iter()
+        .map(|(vehicle, transform)| (vehicle.id(), transform.translation));
+    match pick(point, vehicle_positions, &node_graph) {
+        Some(Pick::Vehicle(vehicle_id)) => {
+            let Some((vehicle, _)) = vehicle_query
+                .iter()
+                .find(|(vehicle, _)| vehicle.id() == vehicle_id)
+            else {
+                return;
+            };
+            let path = vehicle.path();
+            node_graph_renderer.highlighted_vehicle_id = Some(vehicle_id);
+            node_graph_renderer.highlighted_path_index = Some((path[0], path[path.len() - 1]));
+            node_graph_renderer.vehicle_selected_by_user = true;
+            node_graph_renderer.selected_node = None;
+        }
+        Some(Pick::Node(node)) => {
+            node_graph_renderer.highlighted_vehicle_id = None;
+            node_graph_renderer.highlighted_path_index = None;
+            node_graph_renderer.vehicle_selected_by_user = true;
+            node_graph_renderer.selected_node = Some(node);
+        }
+        None => node_graph_renderer.clear_selection(),
+    }
+}
+
+// Recolors vehicles when the highlighted vehicle changes. New vehicles are
+// given the right color when their mesh is attached.
+pub fn update_vehicle_highlight(
+    node_graph_renderer: Res<NodeGraphRenderer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    vehicle_query: Query<(&Vehicle, &Handle<StandardMaterial>)>,
+    mut last_highlighted_vehicle_id: Local<Option<usize>>,
+) {
+    let highlighted_vehicle_id = node_graph_renderer.highlighted_vehicle_id;
+    if *last_highlighted_vehicle_id == highlighted_vehicle_id {
+        return;
+    }
+
+    for (vehicle, material) in &vehicle_query {
+        let was_highlighted = *last_highlighted_vehicle_id == Some(vehicle.id());
+        let is_highlighted = highlighted_vehicle_id == Some(vehicle.id());
+        if was_highlighted == is_highlighted {
+            continue;
+        }
+        let Some(material) = materials.get_mut(material) else {
+            continue;
+        };
+        material.base_color = if is_highlighted {
+            Color::srgb(1., 1., 0.)
+        } else {
+            vehicle.vehicle_type().color()
+        };
+    }
+    *last_highlighted_vehicle_id = highlighted_vehicle_id;
+}
+
+#[derive(Component)]
+pub struct SelectionPanelText;
+
+pub fn spawn_selection_panel(mut commands: Commands) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                left: Val::Px(10.),
+                padding: UiRect::all(Val::Px(8.)),
+                ..default()
+            },
+            background_color: Color::srgba(0., 0., 0., 0.6).into(),
+            ..default()
+        })
+        .with_children(|panel| {
+            panel.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+                SelectionPanelText,
+            ));
+        });
+}
+
+// Describes the state of a vehicle for the selection panel
+fn describe_vehicle(vehicle: &Vehicle, node_graph: &NodeGraph) -> String {
+    let route: Vec<String> = vehicle.path().iter().map(usize::to_string).collect();
+    let leader_gap = match vehicle.leader_gap() {
+        Some(gap) => format!("{:.1} m", gap),
+        None => "none".to_string(),
+    };
+    let held_nodes: Vec<String> = vehicle
+        .path()
+        .iter()
+        .filter(|node| node_graph.node_reservation_map.get(node) == Some(&vehicle.id()))
+        .map(usize::to_string)
+        .collect();
+    let reservation = match vehicle.waiting_for_node() {
+        Some(node) => match node_graph.node_reservation_map.get(&node) {
+            Some(holder) => format!("waiting for node {} (held by vehicle {})", node, holder),
+            None => format!("waiting for node {}", node),
+        },
+        None if !held_nodes.is_empty() => format!("holds node {}", held_nodes.join(", ")),
+        None if vehicle.is_yielding() => "pulled over for an emergency vehicle".to_string(),
+        None => "none".to_string(),
+    };
+    let line = match vehicle.transit_trip() {
+        Some(transit_trip) if transit_trip.is_dwelling() => format!(
+            "\nLine {}: at stop {} of {}",
+            transit_trip.line,
+            transit_trip.next_stop + 1,
+            transit_trip.stops.len()
+        ),
+        Some(transit_trip) if transit_trip.next_stop < transit_trip.stops.len() => format!(
+            "\nLine {}: heading to stop {} of {}",
+            transit_trip.line,
+            transit_trip.next_stop + 1,
+            transit_trip.stops.len()
+        ),
+        Some(transit_trip) => format!("\nLine {}: all stops served", transit_trip.line),
+        None => String::new(),
+    };
+    let parking = match vehicle.parking_trip() {
+        Some(parking_trip) if parking_trip.is_parking => "\nParking: pulling in".to_string(),
+        Some(parking_trip) if parking_trip.is_maneuvering() => "\nParking: pulling out".to_string(),
+        Some(parking_trip) if parking_trip.target.is_some() && parking_trip.areas_tried > 0 => {
+            format!(
+                "\nParking: looking for a space, {} areas full",
+                parking_trip.areas_tried
+            )
+        }
+        Some(parking_trip) if parking_trip.target.is_some() => {
+            "\nParking: driving to a parking area".to_string()
+        }
+        _ => String::new(),
+    };
+    format!(
+        "Vehicle {} ({}){}{}\nRoute: {}\nEdge: {} of {}\nSpeed: {:.1} m/s (desired {:.1} m/s)\nLeader gap: {}\nReservation: {}",
+        vehicle.id(),
+        vehicle.vehicle_type().name,
+        line,
+        parking,
+        route.join(" > "),
+        vehicle.path_index() + 1,
+        vehicle.path().len() - 1,
+        vehicle.current_speed(),
+        vehicle.desired_speed(),
+        leader_gap,
+        reservation
+    )
+}
+
+// Describes a node for the selection panel
+fn describe_node(node: usize, node_graph: &NodeGraph, entry_queues: &EntryQueues) -> String {
+    let node_type = if node_graph.source_nodes.contains(&node) {
+        "source"
+    } else if node_graph.dest_nodes.contains(&node) {
+        "destination"
+    } else {
+        "internal"
+    };
+    let reservation = match node_graph.node_reservation_map.get(&node) {
+        Some(vehicle_id) => format!("reserved by vehicle {}", vehicle_id),
+        None => "not reserved".to_string(),
+    };
+    let mut connections: Vec<usize> = node_graph
+        .node_map
+        .get(&node)
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect();
+    connections.sort();
+    let connections = if connections.is_empty() {
+        "none".to_string()
+    } else {
+        let connections: Vec<String> = connections.iter().map(usize::to_string).collect();
+        connections.join(", ")
+    };
+    let entry_queue = if node_graph.source_nodes.contains(&node) {
+        format!("\nEntry queue: {} waiting", entry_queues.queue_length(node))
+    } else {
+        String::new()
+    };
+    format!(
+        "Node {} ({})\nLeads to: {}\nReservation: {}{}",
+        node, node_type, connections, reservation, entry_queue
+    )
+}
+
+pub fn update_selection_panel(
+    node_graph: Res<NodeGraph>,
+    node_graph_renderer: Res<NodeGraphRenderer>,
+    entry_queues: Res<EntryQueues>,
+    vehicle_query: Query<&Vehicle>,
+    mut text_query: Query<&mut Text, With<SelectionPanelText>>,
+) {
+    let vehicle = node_graph_renderer
+        .highlighted_vehicle_id
+        .and_then(|vehicle_id| {
+            vehicle_query
+                .iter()
+                .find(|vehicle| vehicle.id() == vehicle_id)
+        });
+    let node = node_graph_renderer
+        .selected_node
+        .filter(|node| *node < node_graph.nodes.len());
+    let description = match (vehicle, node) {
+        (Some(vehicle), _) => describe_vehicle(vehicle, &node_graph),
+        (None, Some(node)) => describe_node(node, &node_graph, &entry_queues),
+        (None, None) => "Click a vehicle or node to inspect it".to_string(),
+    };
+    for mut text in &mut text_query {
+        if text.sections[0].value != description {
+            text.sections[0].value = description.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_prefers_the_closest_vehicle() {
+        let graph = NodeGraph::create();
+        let vehicles = [(3, Vec3::new(-1., 0., 5.)), (4, Vec3::new(-1., 0., 5.5))];
+
+        let picked = pick(Vec3::new(-0.8, 0., 5.4), vehicles.into_iter(), &graph);
+        assert_eq!(picked, Some(Pick::Vehicle(4)));
+        // Vehicles are picked over the node they are on
+        let picked = pick(
+            Vec3::new(-1., 0., 10.),
+            [(7, Vec3::new(-1., 0., 9.8))].into_iter(),
+            &graph,
+        );
+        assert_eq!(picked, Some(Pick::Vehicle(7)));
+    }
+
+    #[test]
+    fn pick_falls_back_to_nodes() {
+        let graph = NodeGraph::create();
+        let picked = pick(Vec3::new(1.3, 0., 9.6), std::iter::empty(), &graph);
+        assert_eq!(picked, Some(Pick::Node(1)));
+        let picked = pick(Vec3::new(5., 0., 5.), std::iter::empty(), &graph);
+        assert_eq!(picked, None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::time::Duration;
+
+use crate::{
+    crosswalks::{self, Crosswalk, CrosswalkPlacement, CrosswalkSettings},
+    edge_metrics::EdgeMetrics,
+    emergency_policy::EmergencyPolicy,
+    entry_queues::EntryQueues,
+    node_graph::NodeGraph,
+    parking::{ParkingAreas, ParkingLog},
+    simulation_clock::SimulationClock,
+    simulation_rng::SimulationRng,
+    transit::{TransitLines, TransitLog},
+    trip_log::{TripLog, TripRecord},
+    vehicle_id_generator::VehicleIdGenerator,
+    vehicle_spawn_limiter::VehicleSpawnLimiter,
+    vehicle_types::VehicleTypes,
+    vehicles::{self, Vehicle},
+};
+
+// Settings for running a simulation, with or without a window
+#[derive(Clone, Debug)]
+pub struct SimulationConfig {
+    // How often a vehicle is spawned
+    pub spawn_interval: Duration,
+    // The simulation time advanced by each update when running for a
+    // duration
+    pub time_step: Duration,
+    // The period edge metrics are aggregated over
+    pub metrics_bin_duration: Duration,
+    // Seeds the simulation rng so runs can be repeated, a random seed is
+    // used if there is none
+    pub seed: Option<u64>,
+    // The vehicle types spawned and their shares of the fleet
+    pub vehicle_types: VehicleTypes,
+    // How traffic makes way for emergency vehicles
+    pub emergency_policy: EmergencyPolicy,
+    // Where crosswalks are placed and how busy they are, off by default
+    pub crosswalk_settings: CrosswalkSettings,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            spawn_interval: Duration::from_millis(200),
+            time_step: Duration::from_millis(50),
+            metrics_bin_duration: Duration::from_secs(60),
+            seed: None,
+            vehicle_types: VehicleTypes::default(),
+            emergency_policy: EmergencyPolicy::default(),
+            crosswalk_settings: CrosswalkSettings::default(),
+        }
+    }
+}
+
+// A traffic simulation which owns its network and vehicles and is advanced
+// by calling step. It runs the same vehicle model as the Bevy app, whose
+// systems call into the same functions, but needs nothing from Bevy so it
+// can be used directly in tests and batch runs. Vehicles are updated in the
+// order they entered rather than in ECS order, so results for a seed differ
+// from the app's.
+pub struct Simulation {
+    pub node_graph: NodeGraph,
+    // Vehicles on the network in the order they entered it
+    pub vehicles: Vec<Vehicle>,
+    pub crosswalks: Vec<Crosswalk>,
+    pub clock: SimulationClock,
+    pub rng: SimulationRng,
+    pub spawn_limiter: VehicleSpawnLimiter,
+    pub vehicle_id_generator: VehicleIdGenerator,
+    pub vehicle_types: VehicleTypes,
+    pub emergency_policy: EmergencyPolicy,
+    pub crosswalk_settings: CrosswalkSettings,
+    pub transit_lines: TransitLines,
+    pub parking_areas: ParkingAreas,
+    pub entry_queues: EntryQueues,
+    pub trip_log: TripLog,
+    pub transit_log: TransitLog,
+    pub parking_log: ParkingLog,
+    pub edge_metrics: EdgeMetrics,
+    time_step: Duration,
+    pub(crate) crosswalk_placement: CrosswalkPlacement,
+}
+
+impl Simulation {
+    pub fn new(node_graph: NodeGraph, config: &SimulationConfig) -> Self {
+        Simulation {
+            node_graph,
+            vehicles: Vec::new(),
+            crosswalks: Vec::new(),
+            clock: SimulationClock::default(),
+            rng: config
+                .seed
+                .map_or_else(SimulationRng::default, SimulationRng::from_seed),
+            spawn_limiter: VehicleSpawnLimiter::new(config.spawn_interval),
+            vehicle_id_generator: VehicleIdGenerator::default(),
+            vehicle_types: config.vehicle_types.clone(),
+            emergency_policy: config.emergency_policy.clone(),
+            crosswalk_settings: config.crosswalk_settings.clone(),
+            transit_lines: TransitLines::default(),
+            parking_areas: ParkingAreas::default(),
+            entry_queues: EntryQueues::default(),
+            trip_log: TripLog::default(),
+            transit_log: TransitLog::default(),
+            parking_log: ParkingLog::default(),
+            edge_metrics: EdgeMetrics::new(config.metrics_bin_duration),
+            time_step: config.time_step,
+            crosswalk_placement: CrosswalkPlacement::default(),
+        }
+    }
+
+    // Advances the simulation by the given time, in the same order as the
+    // systems of the app: vehicles enter the network, crosswalks are updated
+    // and then every vehicle drives
+    pub fn step(&mut self, delta: Duration) {
+        self.clock.tick(delta);
+
+        self.transit_lines.dispatch(
+            &self.node_graph,
+            &self.vehicle_types,
+            &mut self.vehicle_id_generator,
+            &mut self.rng.0,
+            &self.clock,
+            &mut self.entry_queues,
+        );
+        let vehicles: Vec<&Vehicle> = self.vehicles.iter().collect();
+        let entering_vehicles = vehicles::spawn_vehicles(
+            &self.node_graph,
+            &vehicles,
+            &mut self.spawn_limiter,
+            &mut self.vehicle_id_generator,
+            &mut self.rng.0,
+            &self.clock,
+            &self.vehicle_types,
+            &self.parking_areas,
+            &mut self.entry_queues,
+        );
+        self.vehicles.extend(entering_vehicles);
+        let vehicles: Vec<&Vehicle> = self.vehicles.iter().collect();
+        let leaving_parking = self.parking_areas.depart(
+            &self.node_graph,
+            &vehicles,
+            &self.vehicle_types,
+            &mut self.vehicle_id_generator,
+            &mut self.rng.0,
+            &self.clock,
+        );
+        self.vehicles.extend(leaving_parking);
+
+        if let Some(crosswalks) = crosswalks::replace_crosswalks(
+            &mut self.crosswalk_placement,
+            &self.node_graph,
+            &self.crosswalk_settings,
+        ) {
+            self.crosswalks = crosswalks;
+        }
+        for crosswalk in self.crosswalks.iter_mut() {
+            let is_vehicle_on_crosswalk = crosswalk.is_vehicle_on(&self.vehicles, &self.node_graph);
+            crosswalk.update(
+                self.clock.elapsed_seconds(),
+                self.clock.delta_seconds(),
+                is_vehicle_on_crosswalk,
+                &mut self.rng.0,
+            );
+        }
+
+        let mut vehicles: Vec<&mut Vehicle> = self.vehicles.iter_mut().collect();
+        let crosswalks: Vec<&Crosswalk> = self.crosswalks.iter().collect();
+        let left_network = vehicles::drive_vehicles(
+            &mut vehicles,
+            &mut self.node_graph,
+            &self.clock,
+            &self.emergency_policy,
+            &crosswalks,
+            &mut self.parking_areas,
+            &mut self.trip_log,
+            &mut self.transit_log,
+            &mut self.parking_log,
+            &mut self.edge_metrics,
+        );
+        for index in left_network.into_iter().rev() {
+            self.vehicles.remove(index);
+        }
+    }
+
+    // Runs for the given time like run_for, checked in windows of the given
+    // length. Returns the start of the first window in which no vehicle
+    // arrived while the entry queues grew, which happens when the network
+    // has locked up.
+    pub fn run_watching_for_stall(&mut self, duration: Duration, window: Duration) -> Option<f32> {
+        let mut stalled_at = None;
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            let window_start = self.clock.elapsed_seconds();
+            let arrivals = self.trip_log.records.len();
+            let queued = self.entry_queues.queued_vehicles().count();
+            let run_time = remaining.min(window);
+            self.run_for(run_time);
+            remaining -= run_time;
+            if stalled_at.is_none()
+                && self.trip_log.records.len() == arrivals
+                && self.entry_queues.queued_vehicles().count() > queued
+            {
+                stalled_at = Some(window_start);
+            }
+        }
+        stalled_at
+    }
+
+    // The trips of the vehicles on the network and waiting to enter it, up
+    // to the current time
+    pub fn unfinished_trips(&self) -> Vec<TripRecord> {
+        let now = self.clock.elapsed_seconds();
+        self.vehicles
+            .iter()
+            .chain(
+                self.entry_queues
+                    .queued_vehicles()
+                    .map(|queued| &queued.vehicle),
+            )
+            .map(|vehicle| vehicle.trip_so_far(now))
+            .collect()
+    }
+
+    // Steps the simulation by the configured time step until the given
+    // amount of simulation time has passed
+    pub fn run_for(&mut self, duration: Duration) {
+        let steps = (duration.as_secs_f64() / self.time_step.as_secs_f64()).round() as usize;
+        for _ in 0..steps {
+            self.step(self.time_step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulations_with_the_same_seed_run_identically() {
+        let config = SimulationConfig {
+            seed: Some(3),
+            ..SimulationConfig::default()
+        };
+        let mut simulations: Vec<Simulation> = (0..2)
+            .map(|_| Simulation::new(NodeGraph::create_highway_merge(), &config))
+            .collect();
+        for simulation in simulations.iter_mut() {
+            simulation.run_for(Duration::from_secs(30));
+        }
+
+        assert!(!simulations[0].trip_log.records.is_empty());
+        assert_eq!(
+            simulations[0].trip_log.records,
+            simulations[1].trip_log.records
+        );
+        assert_eq!(simulations[0].vehicles, simulations[1].vehicles);
+        for record in simulations[0].trip_log.records.iter() {
+            assert!(record.travel_time() > 0.);
+            assert!(record.distance > 40.);
+        }
+    }
+
+    #[test]
+    fn run_watching_for_stall_finds_locked_up_networks() {
+        let config = SimulationConfig {
+            seed: Some(2),
+            ..SimulationConfig::default()
+        };
+        let window = Duration::from_secs(20);
+        let mut simulation = Simulation::new(NodeGraph::create_highway_merge(), &config);
+        assert_eq!(
+            simulation.run_watching_for_stall(Duration::from_secs(60), window),
+            None
+        );
+
+        // Every route merges at node 2, held for a vehicle which never comes
+        let mut simulation = Simulation::new(NodeGraph::create_highway_merge(), &config);
+        simulation
+            .node_graph
+            .node_reservation_map
+            .insert(2, usize::MAX);
+        let stalled_at = simulation.run_watching_for_stall(Duration::from_secs(60), window);
+        assert!(stalled_at.is_some_and(|time| time < 60.));
+    }
+
+    #[test]
+    fn template_networks_keep_flowing() {
+        let templates: [fn() -> NodeGraph; 4] = [
+            NodeGraph::create,
+            || NodeGraph::create_roundabout(4),
+            NodeGraph::create_highway_merge,
+            NodeGraph::create_t_junction,
+        ];
+        let window = Duration::from_secs(30);
+        for (template_index, template) in templates.iter().enumerate() {
+            for seed in 0..3 {
+                let config = SimulationConfig {
+                    seed: Some(seed),
+                    ..SimulationConfig::default()
+                };
+                let mut simulation = Simulation::new(template(), &config);
+                // Demand is higher than any template can carry, so a network
+                // which locks up stops producing arrivals
+                let mut arrivals = 0;
+                for window_index in 0..6 {
+                    simulation.run_for(window);
+                    let new_arrivals = simulation.trip_log.records.len() - arrivals;
+                    assert!(
+                        new_arrivals > 0,
+                        "template {template_index} with seed {seed} had no arrivals in window {window_index}"
+                    );
+                    arrivals += new_arrivals;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+// The time inside the simulation. This is kept separate from the app's time
+// so that it can be saved and restored along with the rest of the simulation.
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimulationClock {
+    elapsed: Duration,
+    // The time advanced by the last update
+    delta: Duration,
+}
+
+impl SimulationClock {
+    pub fn tick(&mut self, delta: Duration) {
+        self.elapsed += delta;
+        self.delta = delta;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+}
+
+// Advances the simulation clock by the app's frame time
+#[cfg(feature = "bevy")]
+pub fn advance_simulation_clock(time: Res<Time>, mut clock: ResMut<SimulationClock>) {
+    clock.tick(time.delta());
+}
@@ -0,0 +1,24 @@
+#[cfg(feature = "bevy")]
+use bevy::prelude::Resource;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+// The random number generator behind every random choice in the simulation.
+// Its state is part of snapshots, so a restored simulation makes the same
+// choices as the one it was saved from.
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationRng(pub ChaCha8Rng);
+
+impl SimulationRng {
+    pub fn from_seed(seed: u64) -> Self {
+        SimulationRng(ChaCha8Rng::seed_from_u64(seed))
+    }
+}
+
+impl Default for SimulationRng {
+    fn default() -> Self {
+        SimulationRng(ChaCha8Rng::from_entropy())
+    }
+}
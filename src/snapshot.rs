@@ -0,0 +1,809 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crosswalks::{Crosswalk, CrosswalkPlacement, CrosswalkSettings},
+    emergency_policy::EmergencyPolicy,
+    entry_queues::{EntryQueues, QueuedVehicle},
+    node_graph::{EdgeAttributes, Node, NodeGraph},
+    osm_import::LocalProjection,
+    parking::{ParkingAreas, ParkingLog},
+    simulation::{Simulation, SimulationConfig},
+    simulation_clock::SimulationClock,
+    simulation_rng::SimulationRng,
+    transit::{TransitLines, TransitLog},
+    vehicle_id_generator::VehicleIdGenerator,
+    vehicle_spawn_limiter::VehicleSpawnLimiter,
+    vehicle_types::VehicleTypes,
+    vehicles::Vehicle,
+};
+#[cfg(feature = "bevy")]
+use crate::{edge_metrics::EdgeMetrics, node_graph_renderer::NodeGraphRenderer, trip_log::TripLog};
+
+// Increased whenever the layout of a snapshot changes
+const SNAPSHOT_VERSION: u32 = 3;
+
+// Where snapshots are saved and loaded from by the keyboard shortcuts
+#[cfg(feature = "bevy")]
+const SNAPSHOT_PATH: &str = "snapshot.json";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    // The snapshot references nodes which don't exist
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "failed to access snapshot file: {}", error),
+            SnapshotError::Json(error) => write!(f, "failed to parse snapshot: {}", error),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "snapshot version {} is not supported, expected version {}",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::Invalid(message) => write!(f, "invalid snapshot: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(error: serde_json::Error) -> Self {
+        SnapshotError::Json(error)
+    }
+}
+
+// The complete state of a running simulation, along with the settings it
+// runs with. Restoring a snapshot and running it always gives the same
+// results, so a network can be warmed up once and many experiments branched
+// from it, with a Simulation or in the app. Measurements such as the trip log
+// and edge metrics aren't included, they start again from the snapshot. The
+// parking and transit logs are, so parking searches agree with the counts of
+// the parking areas and buses already on their way report every stop.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SimulationSnapshot {
+    pub version: u32,
+    pub clock: SimulationClock,
+    pub nodes: Vec<[f32; 3]>,
+    pub edges: Vec<(usize, usize)>,
+    pub edge_attributes: Vec<((usize, usize), EdgeAttributes)>,
+    #[serde(default)]
+    pub projection: Option<LocalProjection>,
+    // Every route through the network. These are stored rather than
+    // recalculated so vehicles spawned after a restore take the same paths.
+    pub routes: Vec<Vec<usize>>,
+    // Node and vehicle id pairs
+    pub node_reservations: Vec<(usize, usize)>,
+    // In the order a Simulation updates them, by id when taken from the app
+    pub vehicles: Vec<Vehicle>,
+    // Vehicles waiting to enter the network, front to back by source node
+    #[serde(default)]
+    pub queued_vehicles: Vec<QueuedVehicle>,
+    // Occupied spaces and the types of the vehicles parked in them
+    pub parking_areas: ParkingAreas,
+    pub parking_log: ParkingLog,
+    pub transit_lines: TransitLines,
+    pub transit_log: TransitLog,
+    pub vehicle_types: VehicleTypes,
+    pub emergency_policy: EmergencyPolicy,
+    pub crosswalk_settings: CrosswalkSettings,
+    // With the pedestrians waiting at and crossing them, in the order a
+    // Simulation updates them, by edge when taken from the app
+    pub crosswalks: Vec<Crosswalk>,
+    pub vehicle_id_generator: VehicleIdGenerator,
+    pub spawn_limiter: VehicleSpawnLimiter,
+    pub rng: SimulationRng,
+}
+
+impl SimulationSnapshot {
+    // Copies the state out of a simulation. Collections without an order are
+    // sorted so the same state always gives the same snapshot.
+    pub fn capture(simulation: &Simulation) -> Self {
+        let node_graph = &simulation.node_graph;
+        let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+        edges.sort();
+        let mut edge_attributes: Vec<((usize, usize), EdgeAttributes)> = node_graph
+            .edge_attributes
+            .iter()
+            .map(|(edge, attributes)| (*edge, attributes.clone()))
+            .collect();
+        edge_attributes.sort_by_key(|(edge, _)| *edge);
+        let mut routes: Vec<Vec<usize>> = node_graph.shortest_path_map.values().cloned().collect();
+        routes.sort();
+        let mut node_reservations: Vec<(usize, usize)> = node_graph
+            .node_reservation_map
+            .iter()
+            .map(|(node, vehicle_id)| (*node, *vehicle_id))
+            .collect();
+        node_reservations.sort();
+
+        SimulationSnapshot {
+            version: SNAPSHOT_VERSION,
+            clock: simulation.clock.clone(),
+            nodes: node_graph
+                .nodes
+                .iter()
+                .map(|node| node.position.to_array())
+                .collect(),
+            edges,
+            edge_attributes,
+            projection: node_graph.projection,
+            routes,
+            node_reservations,
+            vehicles: simulation.vehicles.clone(),
+            queued_vehicles: simulation.entry_queues.queued_vehicles().cloned().collect(),
+            parking_areas: simulation.parking_areas.clone(),
+            parking_log: simulation.parking_log.clone(),
+            transit_lines: simulation.transit_lines.clone(),
+            transit_log: simulation.transit_log.clone(),
+            vehicle_types: simulation.vehicle_types.clone(),
+            emergency_policy: simulation.emergency_policy.clone(),
+            crosswalk_settings: simulation.crosswalk_settings.clone(),
+            crosswalks: simulation.crosswalks.clone(),
+            vehicle_id_generator: simulation.vehicle_id_generator.clone(),
+            spawn_limiter: simulation.spawn_limiter.clone(),
+            rng: simulation.rng.clone(),
+        }
+    }
+
+    // Creates a simulation which carries on from the snapshot. Only the time
+    // step and metrics bin duration are taken from the config, everything
+    // else the simulation runs with comes from the snapshot.
+    pub fn into_simulation(self, config: &SimulationConfig) -> Result<Simulation, SnapshotError> {
+        let node_graph = self.to_node_graph()?;
+        // The restored crosswalks are kept unless the snapshot was taken
+        // before they were first placed
+        let crosswalk_placement = if self.crosswalk_settings.enabled && self.crosswalks.is_empty() {
+            CrosswalkPlacement::default()
+        } else {
+            CrosswalkPlacement::current(&node_graph, &self.crosswalk_settings)
+        };
+        let config = SimulationConfig {
+            vehicle_types: self.vehicle_types,
+            emergency_policy: self.emergency_policy,
+            crosswalk_settings: self.crosswalk_settings,
+            ..config.clone()
+        };
+
+        let mut simulation = Simulation::new(node_graph, &config);
+        simulation.vehicles = self.vehicles;
+        simulation.crosswalks = self.crosswalks;
+        simulation.clock = self.clock;
+        simulation.rng = self.rng;
+        simulation.spawn_limiter = self.spawn_limiter;
+        simulation.vehicle_id_generator = self.vehicle_id_generator;
+        simulation.transit_lines = self.transit_lines;
+        simulation.parking_areas = self.parking_areas;
+        simulation.entry_queues = EntryQueues::restore(self.queued_vehicles);
+        simulation.transit_log = self.transit_log;
+        simulation.parking_log = self.parking_log;
+        simulation.crosswalk_placement = crosswalk_placement;
+        Ok(simulation)
+    }
+
+    // Copies the simulation state out of the world, through a simulation
+    // holding the same state so both are captured alike
+    #[cfg(feature = "bevy")]
+    pub fn capture_world(world: &mut World) -> Self {
+        let mut vehicles: Vec<Vehicle> = world.query::<&Vehicle>().iter(world).cloned().collect();
+        vehicles.sort_by_key(Vehicle::id);
+        let mut crosswalks: Vec<Crosswalk> =
+            world.query::<&Crosswalk>().iter(world).cloned().collect();
+        crosswalks.sort_by_key(|crosswalk| crosswalk.edge);
+
+        let config = SimulationConfig {
+            vehicle_types: cloned_resource(world),
+            emergency_policy: cloned_resource(world),
+            crosswalk_settings: cloned_resource(world),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(world.resource::<NodeGraph>().clone(), &config);
+        simulation.vehicles = vehicles;
+        simulation.crosswalks = crosswalks;
+        simulation.clock = world.resource::<SimulationClock>().clone();
+        simulation.rng = world.resource::<SimulationRng>().clone();
+        simulation.spawn_limiter = world.resource::<VehicleSpawnLimiter>().clone();
+        simulation.vehicle_id_generator = world.resource::<VehicleIdGenerator>().clone();
+        simulation.transit_lines = cloned_resource(world);
+        simulation.parking_areas = cloned_resource(world);
+        simulation.entry_queues = cloned_resource(world);
+        simulation.transit_log = cloned_resource(world);
+        simulation.parking_log = cloned_resource(world);
+        Self::capture(&simulation)
+    }
+
+    // Replaces the simulation state of the world with the snapshot, by way of
+    // the simulation it restores to. Existing vehicles and crosswalks are
+    // removed and the trip log and edge metrics are cleared.
+    #[cfg(feature = "bevy")]
+    pub fn restore_world(self, world: &mut World) -> Result<(), SnapshotError> {
+        let simulation = self.into_simulation(&SimulationConfig::default())?;
+
+        let existing_vehicles: Vec<Entity> = world
+            .query_filtered::<Entity, With<Vehicle>>()
+            .iter(world)
+            .collect();
+        for entity in existing_vehicles {
+            world.despawn(entity);
+        }
+        let existing_crosswalks: Vec<Entity> = world
+            .query_filtered::<Entity, With<Crosswalk>>()
+            .iter(world)
+            .collect();
+        for entity in existing_crosswalks {
+            world.despawn(entity);
+        }
+        for crosswalk in simulation.crosswalks {
+            world.spawn(crosswalk);
+        }
+        for vehicle in simulation.vehicles {
+            let position = vehicle.get_world_position(&simulation.node_graph);
+            world.spawn((
+                SpatialBundle::from_transform(Transform::from_translation(position)),
+                vehicle,
+            ));
+        }
+
+        world.insert_resource(simulation.node_graph);
+        world.insert_resource(simulation.clock);
+        world.insert_resource(simulation.vehicle_id_generator);
+        world.insert_resource(simulation.spawn_limiter);
+        world.insert_resource(simulation.entry_queues);
+        world.insert_resource(simulation.parking_areas);
+        world.insert_resource(simulation.parking_log);
+        world.insert_resource(simulation.transit_lines);
+        world.insert_resource(simulation.transit_log);
+        world.insert_resource(simulation.vehicle_types);
+        world.insert_resource(simulation.emergency_policy);
+        world.insert_resource(simulation.crosswalk_settings);
+        world.insert_resource(simulation.crosswalk_placement);
+        world.insert_resource(simulation.rng);
+        if let Some(mut trip_log) = world.get_resource_mut::<TripLog>() {
+            trip_log.records.clear();
+        }
+        if let Some(mut edge_metrics) = world.get_resource_mut::<EdgeMetrics>() {
+            edge_metrics.clear();
+        }
+        if let Some(mut node_graph_renderer) = world.get_resource_mut::<NodeGraphRenderer>() {
+            node_graph_renderer.clear_selection();
+            node_graph_renderer.edge_congestion.clear();
+        }
+        Ok(())
+    }
+
+    // Rebuilds the node graph, checking that everything in the snapshot
+    // refers to nodes which exist
+    fn to_node_graph(&self) -> Result<NodeGraph, SnapshotError> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(self.version));
+        }
+
+        let node_count = self.nodes.len();
+        let is_valid_edge = |edge: &(usize, usize)| edge.0 < node_count && edge.1 < node_count;
+        if let Some(edge) = self.edges.iter().find(|edge| !is_valid_edge(edge)) {
+            return Err(SnapshotError::Invalid(format!(
+                "edge ({}, {}) references a missing node",
+                edge.0, edge.1
+            )));
+        }
+        if let Some(route) = self
+            .routes
+            .iter()
+            .find(|route| route.is_empty() || route.iter().any(|node| *node >= node_count))
+        {
+            return Err(SnapshotError::Invalid(format!(
+                "route {:?} references a missing node",
+                route
+            )));
+        }
+        if let Some((node, _)) = self
+            .node_reservations
+            .iter()
+            .find(|(node, _)| *node >= node_count)
+        {
+            return Err(SnapshotError::Invalid(format!(
+                "node {} is reserved but doesn't exist",
+                node
+            )));
+        }
+
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|position| Node {
+                position: Vec3::from_array(*position),
+            })
+            .collect();
+        let edges: HashSet<(usize, usize)> = self.edges.iter().copied().collect();
+        let mut node_graph = NodeGraph::new(nodes, edges);
+        node_graph.edge_attributes = self.edge_attributes.iter().cloned().collect();
+        node_graph.projection = self.projection;
+        node_graph.shortest_path_map = self
+            .routes
+            .iter()
+            .map(|route| ((route[0], route[route.len() - 1]), route.clone()))
+            .collect();
+        node_graph.node_reservation_map = self.node_reservations.iter().copied().collect();
+
+        if let Some(vehicle) = self
+            .vehicles
+            .iter()
+            .chain(self.queued_vehicles.iter().map(|queued| &queued.vehicle))
+            .find(|vehicle| !vehicle.is_valid_for(&node_graph))
+        {
+            return Err(SnapshotError::Invalid(format!(
+                "vehicle {} has an invalid path",
+                vehicle.id()
+            )));
+        }
+        if let Some(crosswalk) = self
+            .crosswalks
+            .iter()
+            .find(|crosswalk| !node_graph.edges.contains(&crosswalk.edge))
+        {
+            return Err(SnapshotError::Invalid(format!(
+                "crosswalk on ({}, {}) is on a missing edge",
+                crosswalk.edge.0, crosswalk.edge.1
+            )));
+        }
+        self.parking_areas
+            .validate(&node_graph)
+            .map_err(|error| SnapshotError::Invalid(error.to_string()))?;
+        Ok(node_graph)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+// Clones a resource the app may not have, using the default in its place
+#[cfg(feature = "bevy")]
+fn cloned_resource<T: Resource + Clone + Default>(world: &World) -> T {
+    world.get_resource::<T>().cloned().unwrap_or_default()
+}
+
+// Saves a snapshot to the working directory when F5 is pressed
+#[cfg(feature = "bevy")]
+pub fn save_snapshot(world: &mut World) {
+    let keyboard = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard.just_pressed(KeyCode::F5) {
+        return;
+    }
+
+    match SimulationSnapshot::capture_world(world).save(SNAPSHOT_PATH) {
+        Ok(()) => info!("Saved simulation snapshot to {}", SNAPSHOT_PATH),
+        Err(error) => error!("Failed to save simulation snapshot: {}", error),
+    }
+}
+
+// Restores the snapshot in the working directory when F9 is pressed
+#[cfg(feature = "bevy")]
+pub fn load_snapshot(world: &mut World) {
+    let keyboard = world.resource::<ButtonInput<KeyCode>>();
+    if !keyboard.just_pressed(KeyCode::F9) {
+        return;
+    }
+
+    let result =
+        SimulationSnapshot::load(SNAPSHOT_PATH).and_then(|snapshot| snapshot.restore_world(world));
+    match result {
+        Ok(()) => info!("Restored simulation snapshot from {}", SNAPSHOT_PATH),
+        Err(error) => error!("Failed to restore simulation snapshot: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        transit::{TransitLine, TransitStop},
+        vehicle_types::{FleetShare, VehicleType},
+    };
+
+    // A warmed up simulation of the demo intersection with a bus line,
+    // crosswalks and settings which differ from the defaults
+    fn warmed_up_simulation() -> Simulation {
+        let config = SimulationConfig {
+            seed: Some(5),
+            vehicle_types: VehicleTypes {
+                fleet: vec![FleetShare {
+                    vehicle_type: VehicleType::car(),
+                    share: 1.,
+                }],
+            },
+            emergency_policy: EmergencyPolicy {
+                yielding: false,
+                ..EmergencyPolicy::default()
+            },
+            crosswalk_settings: CrosswalkSettings {
+                enabled: true,
+                arrival_rate: 0.5,
+                signal: None,
+            },
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(NodeGraph::create(), &config);
+        simulation.transit_lines = TransitLines {
+            lines: vec![TransitLine {
+                name: "1".to_string(),
+                nodes: vec![1, 9, 7],
+                stops: vec![TransitStop {
+                    name: "north".to_string(),
+                    edge: (1, 9),
+                    position: 0.5,
+                    dwell_time: 5.,
+                    scheduled_offset: 10.,
+                }],
+                departures: vec![1., 8., 15., 30.],
+            }],
+        };
+        simulation.run_for(Duration::from_secs(20));
+        simulation
+    }
+
+    #[test]
+    fn simulation_snapshots_round_trip_through_json() {
+        let simulation = warmed_up_simulation();
+        let snapshot = SimulationSnapshot::capture(&simulation);
+        assert!(!snapshot.vehicles.is_empty());
+        assert!(!snapshot.crosswalks.is_empty());
+        assert!(!snapshot.transit_log.arrivals.is_empty());
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let loaded: SimulationSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        // The settings the simulation ran with come back with it
+        let restored = loaded
+            .into_simulation(&SimulationConfig::default())
+            .unwrap();
+        assert_eq!(restored.vehicle_types, simulation.vehicle_types);
+        assert_eq!(restored.emergency_policy, simulation.emergency_policy);
+        assert_eq!(restored.crosswalk_settings, simulation.crosswalk_settings);
+        assert_eq!(restored.transit_lines, simulation.transit_lines);
+        assert_eq!(restored.transit_log, simulation.transit_log);
+        assert_eq!(SimulationSnapshot::capture(&restored), snapshot);
+    }
+
+    #[test]
+    fn simulations_branched_from_a_snapshot_run_like_the_original() {
+        let mut simulation = warmed_up_simulation();
+        let snapshot = SimulationSnapshot::capture(&simulation);
+        let snapshot_time = snapshot.clock.elapsed_seconds();
+        let arrivals_before = simulation.trip_log.records.len();
+
+        let mut branches: Vec<Simulation> = (0..2)
+            .map(|_| {
+                snapshot
+                    .clone()
+                    .into_simulation(&SimulationConfig::default())
+                    .unwrap()
+            })
+            .collect();
+        simulation.run_for(Duration::from_secs(20));
+        for branch in branches.iter_mut() {
+            branch.run_for(Duration::from_secs(20));
+        }
+
+        // Only trips completed after the snapshot are logged
+        let trips = &branches[0].trip_log.records;
+        assert!(!trips.is_empty());
+        assert!(trips
+            .iter()
+            .all(|record| record.arrival_time > snapshot_time));
+        assert_eq!(trips, &simulation.trip_log.records[arrivals_before..]);
+        assert_eq!(trips, &branches[1].trip_log.records);
+        let final_snapshot = SimulationSnapshot::capture(&simulation);
+        for branch in branches.iter() {
+            assert_eq!(SimulationSnapshot::capture(branch), final_snapshot);
+        }
+    }
+
+    #[test]
+    fn into_simulation_rejects_invalid_snapshots() {
+        let mut snapshot = SimulationSnapshot::capture(&warmed_up_simulation());
+        snapshot.vehicles[0] = Vehicle::new(
+            snapshot.vehicles[0].id(),
+            VehicleType::car(),
+            vec![1, 99],
+            0.,
+            &mut snapshot.rng.0,
+        );
+        assert!(matches!(
+            snapshot.into_simulation(&SimulationConfig::default()),
+            Err(SnapshotError::Invalid(_))
+        ));
+    }
+
+    // The same snapshots taken from and restored to the app
+    #[cfg(feature = "bevy")]
+    mod app {
+        use bevy::time::TimeUpdateStrategy;
+
+        use super::*;
+        use crate::{
+            crosswalks,
+            parking::{self, ParkingArea, ParkingSettings},
+            transit,
+            trip_log::TripRecord,
+            vehicles,
+        };
+
+        // The simulation time advanced by each update
+        const TIME_STEP: Duration = Duration::from_millis(50);
+
+        // Builds an app which runs the simulation systems in the same order as
+        // the window, without rendering and with a fixed time step
+        fn build_app_with(node_graph: NodeGraph) -> App {
+            let mut app = App::new();
+            app.add_plugins(MinimalPlugins)
+                .insert_resource(TimeUpdateStrategy::ManualDuration(TIME_STEP))
+                .add_systems(PreUpdate, crate::simulation_clock::advance_simulation_clock)
+                .add_systems(
+                    Update,
+                    (
+                        transit::dispatch_transit_vehicles,
+                        vehicles::spawn_vehicle,
+                        parking::depart_parked_vehicles,
+                        crosswalks::update_crosswalk_placement,
+                        crosswalks::update_crosswalks,
+                        vehicles::move_vehicles,
+                    )
+                        .chain(),
+                )
+                .insert_resource(node_graph)
+                .insert_resource(SimulationClock::default())
+                .insert_resource(SimulationRng::from_seed(7))
+                .insert_resource(NodeGraphRenderer::default())
+                .insert_resource(VehicleSpawnLimiter::new(Duration::from_millis(200)))
+                .insert_resource(VehicleIdGenerator::default())
+                .insert_resource(EntryQueues::default())
+                .insert_resource(VehicleTypes::default())
+                .insert_resource(EmergencyPolicy::default())
+                .insert_resource(CrosswalkSettings::default())
+                .insert_resource(CrosswalkPlacement::default())
+                .insert_resource(TripLog::default())
+                .insert_resource(TransitLines::default())
+                .insert_resource(TransitLog::default())
+                .insert_resource(ParkingAreas::default())
+                .insert_resource(ParkingLog::default())
+                .insert_resource(EdgeMetrics::default());
+            app.finish();
+            app.cleanup();
+            app
+        }
+
+        fn build_app() -> App {
+            build_app_with(NodeGraph::create())
+        }
+
+        fn run_for(app: &mut App, duration: Duration) {
+            let steps = (duration.as_secs_f64() / TIME_STEP.as_secs_f64()).round() as usize;
+            for _ in 0..steps {
+                app.update();
+            }
+        }
+
+        #[test]
+        fn snapshot_round_trips_through_json() {
+            let mut app = build_app();
+            run_for(&mut app, Duration::from_secs(10));
+            let snapshot = SimulationSnapshot::capture_world(app.world_mut());
+            assert!(!snapshot.vehicles.is_empty());
+
+            let json = serde_json::to_string(&snapshot).unwrap();
+            let loaded: SimulationSnapshot = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, snapshot);
+
+            // Restoring into a different network replaces it entirely
+            let mut other_app = build_app_with(NodeGraph::create_highway_merge());
+            loaded.clone().restore_world(other_app.world_mut()).unwrap();
+            assert_eq!(
+                SimulationSnapshot::capture_world(other_app.world_mut()),
+                snapshot
+            );
+
+            // Snapshots of the app carry on in a simulation without it
+            let simulation = loaded
+                .into_simulation(&SimulationConfig::default())
+                .unwrap();
+            assert_eq!(SimulationSnapshot::capture(&simulation), snapshot);
+        }
+
+        #[test]
+        fn restored_snapshots_run_identically() {
+            let mut app = build_app();
+            run_for(&mut app, Duration::from_secs(10));
+            let snapshot = SimulationSnapshot::capture_world(app.world_mut());
+
+            let mut branches: Vec<App> = (0..2).map(|_| build_app()).collect();
+            for branch in branches.iter_mut() {
+                snapshot.clone().restore_world(branch.world_mut()).unwrap();
+                run_for(branch, Duration::from_secs(20));
+            }
+
+            let trips: Vec<Vec<TripRecord>> = branches
+                .iter()
+                .map(|branch| branch.world().resource::<TripLog>().records.clone())
+                .collect();
+            assert!(!trips[0].is_empty());
+            assert_eq!(trips[0], trips[1]);
+            // Only trips completed after the snapshot are logged
+            let snapshot_time = snapshot.clock.elapsed_seconds();
+            assert!(trips[0]
+                .iter()
+                .all(|record| record.arrival_time > snapshot_time));
+            assert_eq!(
+                SimulationSnapshot::capture_world(branches[0].world_mut()),
+                SimulationSnapshot::capture_world(branches[1].world_mut())
+            );
+        }
+
+        #[test]
+        fn snapshots_keep_parked_vehicles() {
+            let build_parking_app = || {
+                let mut app = build_app();
+                app.insert_resource(ParkingAreas {
+                    areas: vec![ParkingArea {
+                        name: "curb".to_string(),
+                        edge: (9, 7),
+                        position: 0.5,
+                        capacity: 20,
+                        occupied: 0,
+                        arrivals: 0,
+                        turned_away: 0,
+                        parked_types: Vec::new(),
+                    }],
+                    settings: ParkingSettings {
+                        parking_share: 0.5,
+                        mean_parking_duration: 20.,
+                        maneuver_time: 1.,
+                    },
+                });
+                app
+            };
+            let mut app = build_parking_app();
+            run_for(&mut app, Duration::from_secs(20));
+            let snapshot = SimulationSnapshot::capture_world(app.world_mut());
+            assert!(snapshot.parking_areas.areas[0].occupied > 0);
+            assert!(!snapshot.parking_log.searches.is_empty());
+
+            let json = serde_json::to_string(&snapshot).unwrap();
+            let loaded: SimulationSnapshot = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, snapshot);
+
+            // The parked vehicles come back even in an app without parking areas,
+            // and leave the same way in both branches
+            let mut branches = [build_parking_app(), build_app()];
+            for branch in branches.iter_mut() {
+                loaded.clone().restore_world(branch.world_mut()).unwrap();
+                assert_eq!(
+                    branch.world().resource::<ParkingAreas>(),
+                    &snapshot.parking_areas
+                );
+                run_for(branch, Duration::from_secs(20));
+            }
+            let [first, second] = &mut branches;
+            let first_snapshot = SimulationSnapshot::capture_world(first.world_mut());
+            assert!(
+                first_snapshot.parking_log.searches.len() > snapshot.parking_log.searches.len()
+            );
+            assert_eq!(
+                first_snapshot,
+                SimulationSnapshot::capture_world(second.world_mut())
+            );
+        }
+
+        #[test]
+        fn snapshots_keep_pedestrians() {
+            let build_crosswalk_app = || {
+                let mut app = build_app();
+                app.insert_resource(CrosswalkSettings {
+                    enabled: true,
+                    arrival_rate: 0.5,
+                    signal: None,
+                });
+                app
+            };
+            let mut app = build_crosswalk_app();
+            run_for(&mut app, Duration::from_secs(20));
+            let snapshot = SimulationSnapshot::capture_world(app.world_mut());
+            assert!(!snapshot.crosswalks.is_empty());
+            assert!(snapshot
+                .crosswalks
+                .iter()
+                .any(|crosswalk| !crosswalk.waiting.is_empty() || !crosswalk.crossing.is_empty()));
+
+            let json = serde_json::to_string(&snapshot).unwrap();
+            let loaded: SimulationSnapshot = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, snapshot);
+
+            // The restored crosswalks aren't replaced by freshly placed empty
+            // ones, even in an app which had crosswalks turned off
+            let mut branches = [build_crosswalk_app(), build_app()];
+            for branch in branches.iter_mut() {
+                loaded.clone().restore_world(branch.world_mut()).unwrap();
+                branch.update();
+            }
+            let [first, second] = &mut branches;
+            let first_snapshot = SimulationSnapshot::capture_world(first.world_mut());
+            assert_eq!(first_snapshot.crosswalks.len(), snapshot.crosswalks.len());
+            // Waiting pedestrians only leave the curb by crossing
+            let pedestrians =
+                |crosswalk: &Crosswalk| crosswalk.waiting.len() + crosswalk.pedestrians_crossed;
+            assert!(first_snapshot
+                .crosswalks
+                .iter()
+                .zip(snapshot.crosswalks.iter())
+                .all(|(restored, saved)| pedestrians(restored) >= pedestrians(saved)));
+            assert_eq!(
+                first_snapshot,
+                SimulationSnapshot::capture_world(second.world_mut())
+            );
+        }
+
+        #[test]
+        fn restore_rejects_invalid_snapshots() {
+            let mut app = build_app();
+            let mut snapshot = SimulationSnapshot::capture_world(app.world_mut());
+            snapshot.edges.push((0, 99));
+            assert!(matches!(
+                snapshot.clone().restore_world(app.world_mut()),
+                Err(SnapshotError::Invalid(_))
+            ));
+
+            snapshot.edges.pop();
+            snapshot.parking_areas.areas.push(ParkingArea {
+                name: "nowhere".to_string(),
+                edge: (0, 1),
+                position: 0.5,
+                capacity: 1,
+                occupied: 0,
+                arrivals: 0,
+                turned_away: 0,
+                parked_types: Vec::new(),
+            });
+            assert!(matches!(
+                snapshot.clone().restore_world(app.world_mut()),
+                Err(SnapshotError::Invalid(_))
+            ));
+
+            snapshot.version = SNAPSHOT_VERSION + 1;
+            assert!(matches!(
+                snapshot.restore_world(app.world_mut()),
+                Err(SnapshotError::UnsupportedVersion(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,592 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+use glam::{Vec2, Vec3};
+
+use crate::node_graph::{EdgeAttributes, Node, NodeGraph};
+
+// Default values used by SUMO when writing a network
+const DEFAULT_LANE_WIDTH: f32 = 3.2;
+const DEFAULT_SPEED: f32 = 13.89;
+// Edges which a connection joins closer together than this share a node
+const MERGE_DISTANCE: f32 = 0.01;
+
+#[derive(Debug)]
+pub enum SumoNetworkError {
+    Io(io::Error),
+    Xml(roxmltree::Error),
+    MissingAttribute {
+        element: &'static str,
+        attribute: &'static str,
+    },
+    InvalidAttribute {
+        element: &'static str,
+        attribute: &'static str,
+        value: String,
+    },
+    UnknownEdge(String),
+    NoEdges,
+}
+
+impl fmt::Display for SumoNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SumoNetworkError::Io(error) => write!(f, "failed to read sumo network: {}", error),
+            SumoNetworkError::Xml(error) => write!(f, "failed to parse sumo network: {}", error),
+            SumoNetworkError::MissingAttribute { element, attribute } => {
+                write!(f, "<{}> is missing the '{}' attribute", element, attribute)
+            }
+            SumoNetworkError::InvalidAttribute {
+                element,
+                attribute,
+                value,
+            } => write!(
+                f,
+                "<{}> has an invalid '{}' attribute: '{}'",
+                element, attribute, value
+            ),
+            SumoNetworkError::UnknownEdge(id) => {
+                write!(f, "connection references unknown edge '{}'", id)
+            }
+            SumoNetworkError::NoEdges => write!(f, "the sumo network does not contain any edges"),
+        }
+    }
+}
+
+impl std::error::Error for SumoNetworkError {}
+
+impl From<io::Error> for SumoNetworkError {
+    fn from(error: io::Error) -> Self {
+        SumoNetworkError::Io(error)
+    }
+}
+
+impl From<roxmltree::Error> for SumoNetworkError {
+    fn from(error: roxmltree::Error) -> Self {
+        SumoNetworkError::Xml(error)
+    }
+}
+
+// Reads a SUMO .net.xml file from disk and converts it into a node graph
+pub fn load_sumo_network(path: impl AsRef<Path>) -> Result<NodeGraph, SumoNetworkError> {
+    let text = fs::read_to_string(path)?;
+    parse_sumo_network(&text)
+}
+
+// Writes a node graph to disk as a SUMO .net.xml file
+pub fn save_sumo_network(node_graph: &NodeGraph, path: impl AsRef<Path>) -> io::Result<()> {
+    fs::write(path, write_sumo_network(node_graph))
+}
+
+// Converts a SUMO network into a node graph.
+// SUMO edges end at the border of a junction and connections describe which
+// turns are allowed inside it. To keep those turn restrictions every SUMO edge
+// becomes a graph edge between its own start and end nodes, and every
+// connection becomes an edge from the end of one SUMO edge to the start of
+// another, the same way the internal nodes of `NodeGraph::create` work.
+// Connected edges which meet at a point, as in networks written by
+// write_sumo_network, share a node instead. Internal edges and junctions are
+// skipped.
+pub fn parse_sumo_network(text: &str) -> Result<NodeGraph, SumoNetworkError> {
+    let document = roxmltree::Document::parse(text)?;
+
+    let mut junction_positions: HashMap<&str, Vec3> = HashMap::new();
+    for element in document.root_element().children() {
+        if !element.has_tag_name("junction") || element.attribute("type") == Some("internal") {
+            continue;
+        }
+        let id = required_attribute(&element, "junction", "id")?;
+        let x = parse_attribute(&element, "junction", "x")?;
+        let y = parse_attribute(&element, "junction", "y")?;
+        junction_positions.insert(id, sumo_to_world(x, y));
+    }
+
+    // Sorted so that node indices are stable between imports
+    let mut sumo_edges: BTreeMap<&str, SumoEdge> = BTreeMap::new();
+    for element in document.root_element().children() {
+        let is_normal_edge = matches!(element.attribute("function"), None | Some("normal"));
+        if !element.has_tag_name("edge") || !is_normal_edge {
+            continue;
+        }
+        let id = required_attribute(&element, "edge", "id")?;
+        let from = required_attribute(&element, "edge", "from")?;
+        let to = required_attribute(&element, "edge", "to")?;
+
+        let mut lane_count = 0;
+        let mut speed_limit: Option<f32> = None;
+        let mut start_sum = Vec3::ZERO;
+        let mut end_sum = Vec3::ZERO;
+        for lane in element
+            .children()
+            .filter(|child| child.has_tag_name("lane"))
+        {
+            let speed: f32 = parse_attribute(&lane, "lane", "speed")?;
+            speed_limit = Some(speed_limit.map_or(speed, |limit| limit.max(speed)));
+            let shape = match lane.attribute("shape") {
+                Some(shape) => parse_shape(shape)?,
+                None => Vec::new(),
+            };
+            let (start, end) = match (shape.first(), shape.last()) {
+                (Some(start), Some(end)) if shape.len() > 1 => (*start, *end),
+                _ => (
+                    junction_position(&junction_positions, from)?,
+                    junction_position(&junction_positions, to)?,
+                ),
+            };
+            start_sum += start;
+            end_sum += end;
+            lane_count += 1;
+        }
+        // Edges without lanes are not valid, fall back to the junction positions
+        let (start, end) = if lane_count == 0 {
+            (
+                junction_position(&junction_positions, from)?,
+                junction_position(&junction_positions, to)?,
+            )
+        } else {
+            (start_sum / lane_count as f32, end_sum / lane_count as f32)
+        };
+
+        sumo_edges.insert(
+            id,
+            SumoEdge {
+                start,
+                end,
+                attributes: EdgeAttributes {
+                    speed_limit,
+                    lanes: (lane_count > 0).then_some(lane_count),
+                },
+            },
+        );
+    }
+
+    if sumo_edges.is_empty() {
+        return Err(SumoNetworkError::NoEdges);
+    }
+
+    let edge_indices: HashMap<&str, usize> = sumo_edges
+        .keys()
+        .enumerate()
+        .map(|(index, id)| (*id, index))
+        .collect();
+    let sumo_edges: Vec<SumoEdge> = sumo_edges.into_values().collect();
+
+    let mut connections = Vec::new();
+    for element in document.root_element().children() {
+        if !element.has_tag_name("connection") {
+            continue;
+        }
+        let from = required_attribute(&element, "connection", "from")?;
+        let to = required_attribute(&element, "connection", "to")?;
+        // Connections between internal edges are part of the junction geometry
+        if from.starts_with(':') || to.starts_with(':') {
+            continue;
+        }
+        let from_index = edge_indices
+            .get(from)
+            .ok_or_else(|| SumoNetworkError::UnknownEdge(from.to_string()))?;
+        let to_index = edge_indices
+            .get(to)
+            .ok_or_else(|| SumoNetworkError::UnknownEdge(to.to_string()))?;
+        connections.push((*from_index, *to_index));
+    }
+
+    // Every SUMO edge i has a slot for its start, 2i, and its end, 2i + 1.
+    // Slots joined by a connection without any length are merged, the lowest
+    // slot of each group gives the node its position and index order.
+    let mut slot_parents: Vec<usize> = (0..sumo_edges.len() * 2).collect();
+    for (from_index, to_index) in connections.iter() {
+        let from_end = sumo_edges[*from_index].end;
+        let to_start = sumo_edges[*to_index].start;
+        if from_end.distance(to_start) < MERGE_DISTANCE {
+            let from_root = find_slot_root(&mut slot_parents, from_index * 2 + 1);
+            let to_root = find_slot_root(&mut slot_parents, to_index * 2);
+            slot_parents[from_root.max(to_root)] = from_root.min(to_root);
+        }
+    }
+    let mut nodes = Vec::new();
+    let mut slot_nodes = vec![0; slot_parents.len()];
+    for slot in 0..slot_parents.len() {
+        let root = find_slot_root(&mut slot_parents, slot);
+        if root == slot {
+            let sumo_edge = &sumo_edges[slot / 2];
+            let position = if slot % 2 == 0 {
+                sumo_edge.start
+            } else {
+                sumo_edge.end
+            };
+            slot_nodes[slot] = nodes.len();
+            nodes.push(Node { position });
+        } else {
+            slot_nodes[slot] = slot_nodes[root];
+        }
+    }
+
+    let mut edges = HashSet::new();
+    let mut edge_attributes = HashMap::new();
+    for (index, sumo_edge) in sumo_edges.iter().enumerate() {
+        let edge = (slot_nodes[index * 2], slot_nodes[index * 2 + 1]);
+        if edge.0 == edge.1 {
+            continue;
+        }
+        edges.insert(edge);
+        if sumo_edge.attributes != EdgeAttributes::default() {
+            edge_attributes.insert(edge, sumo_edge.attributes.clone());
+        }
+    }
+    for (from_index, to_index) in connections {
+        let edge = (slot_nodes[from_index * 2 + 1], slot_nodes[to_index * 2]);
+        if edge.0 != edge.1 {
+            edges.insert(edge);
+        }
+    }
+
+    let mut graph = NodeGraph::new(nodes, edges);
+    graph.edge_attributes = edge_attributes;
+    Ok(graph)
+}
+
+// Converts a node graph into a SUMO network.
+// Every node becomes a junction and every edge becomes a SUMO edge. Lane
+// counts and speed limits are taken from the edge attributes when present.
+// Every turn possible in the graph becomes a connection between the first
+// lanes of the two edges. The network has no internal lanes, so SUMO should
+// be run with --no-internal-links.
+pub fn write_sumo_network(node_graph: &NodeGraph) -> String {
+    let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+    edges.sort();
+
+    let mut incoming_lanes: HashMap<usize, Vec<String>> = HashMap::new();
+    for (source, dest) in edges.iter() {
+        for lane in 0..lane_count(node_graph, *source, *dest) {
+            incoming_lanes
+                .entry(*dest)
+                .or_default()
+                .push(lane_id(*source, *dest, lane));
+        }
+    }
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(xml, r#"<net version="1.20" junctionCornerDetail="5">"#).unwrap();
+
+    for (source, dest) in edges.iter() {
+        let start = world_to_sumo(node_graph.nodes[*source].position);
+        let end = world_to_sumo(node_graph.nodes[*dest].position);
+        let length = start.distance(end);
+        let direction = (end - start).normalize_or_zero();
+        let right = Vec2::new(direction.y, -direction.x);
+        let lanes = lane_count(node_graph, *source, *dest);
+        let speed = node_graph
+            .edge_attributes
+            .get(&(*source, *dest))
+            .and_then(|attributes| attributes.speed_limit)
+            .unwrap_or(DEFAULT_SPEED);
+
+        writeln!(
+            xml,
+            r#"    <edge id="{}" from="{}" to="{}" priority="1">"#,
+            edge_id(*source, *dest),
+            junction_id(*source),
+            junction_id(*dest)
+        )
+        .unwrap();
+        for lane in 0..lanes {
+            // Lane 0 is the rightmost lane
+            let offset = right * ((lanes - 1) as f32 / 2. - lane as f32) * DEFAULT_LANE_WIDTH;
+            writeln!(
+                xml,
+                r#"        <lane id="{}" index="{}" speed="{:.2}" length="{:.2}" shape="{},{} {},{}"/>"#,
+                lane_id(*source, *dest, lane),
+                lane,
+                speed,
+                length,
+                start.x + offset.x,
+                start.y + offset.y,
+                end.x + offset.x,
+                end.y + offset.y
+            )
+            .unwrap();
+        }
+        writeln!(xml, "    </edge>").unwrap();
+    }
+
+    for (index, node) in node_graph.nodes.iter().enumerate() {
+        let position = world_to_sumo(node.position);
+        let junction_type =
+            if node_graph.source_nodes.contains(&index) || node_graph.dest_nodes.contains(&index) {
+                "dead_end"
+            } else {
+                "priority"
+            };
+        let incoming = incoming_lanes
+            .get(&index)
+            .map(|lanes| lanes.join(" "))
+            .unwrap_or_default();
+        writeln!(
+            xml,
+            r#"    <junction id="{}" type="{}" x="{}" y="{}" incLanes="{}" intLanes=""/>"#,
+            junction_id(index),
+            junction_type,
+            position.x,
+            position.y,
+            incoming
+        )
+        .unwrap();
+    }
+
+    for (source, dest) in edges.iter() {
+        let Some(next_nodes) = node_graph.node_map.get(dest) else {
+            continue;
+        };
+        let mut next_nodes: Vec<&usize> = next_nodes.iter().collect();
+        next_nodes.sort();
+        for next_node in next_nodes {
+            writeln!(
+                xml,
+                r#"    <connection from="{}" to="{}" fromLane="0" toLane="0" dir="s" state="M"/>"#,
+                edge_id(*source, *dest),
+                edge_id(*dest, *next_node)
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(xml, "</net>").unwrap();
+    xml
+}
+
+struct SumoEdge {
+    start: Vec3,
+    end: Vec3,
+    attributes: EdgeAttributes,
+}
+
+// Finds the slot a group of merged slots is represented by
+fn find_slot_root(slot_parents: &mut [usize], slot: usize) -> usize {
+    let mut root = slot;
+    while slot_parents[root] != root {
+        root = slot_parents[root];
+    }
+    let mut slot = slot;
+    while slot_parents[slot] != root {
+        let parent = slot_parents[slot];
+        slot_parents[slot] = root;
+        slot = parent;
+    }
+    root
+}
+
+fn lane_count(node_graph: &NodeGraph, source: usize, dest: usize) -> u32 {
+    node_graph
+        .edge_attributes
+        .get(&(source, dest))
+        .and_then(|attributes| attributes.lanes)
+        .unwrap_or(1)
+        .max(1)
+}
+
+fn junction_id(node: usize) -> String {
+    format!("n{}", node)
+}
+
+fn edge_id(source: usize, dest: usize) -> String {
+    format!("e{}_{}", source, dest)
+}
+
+fn lane_id(source: usize, dest: usize, lane: u32) -> String {
+    format!("{}_{}", edge_id(source, dest), lane)
+}
+
+// SUMO uses a y-up plane, north along +y maps onto the forward vector -z
+fn sumo_to_world(x: f32, y: f32) -> Vec3 {
+    Vec3::new(x, 0., -y)
+}
+
+fn world_to_sumo(position: Vec3) -> Vec2 {
+    Vec2::new(position.x, -position.z)
+}
+
+fn junction_position(
+    junction_positions: &HashMap<&str, Vec3>,
+    id: &str,
+) -> Result<Vec3, SumoNetworkError> {
+    junction_positions
+        .get(id)
+        .copied()
+        .ok_or_else(|| SumoNetworkError::InvalidAttribute {
+            element: "edge",
+            attribute: "from/to",
+            value: id.to_string(),
+        })
+}
+
+// Parses a shape of the form "x1,y1 x2,y2 ..."
+fn parse_shape(shape: &str) -> Result<Vec<Vec3>, SumoNetworkError> {
+    let invalid_shape = || SumoNetworkError::InvalidAttribute {
+        element: "lane",
+        attribute: "shape",
+        value: shape.to_string(),
+    };
+    shape
+        .split_whitespace()
+        .map(|point| {
+            let mut coordinates = point.split(',').map(|value| value.parse::<f32>());
+            match (coordinates.next(), coordinates.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => Ok(sumo_to_world(x, y)),
+                _ => Err(invalid_shape()),
+            }
+        })
+        .collect()
+}
+
+fn required_attribute<'a>(
+    element: &roxmltree::Node<'a, '_>,
+    element_name: &'static str,
+    attribute: &'static str,
+) -> Result<&'a str, SumoNetworkError> {
+    element
+        .attribute(attribute)
+        .ok_or(SumoNetworkError::MissingAttribute {
+            element: element_name,
+            attribute,
+        })
+}
+
+fn parse_attribute<T: std::str::FromStr>(
+    element: &roxmltree::Node,
+    element_name: &'static str,
+    attribute: &'static str,
+) -> Result<T, SumoNetworkError> {
+    let value = required_attribute(element, element_name, attribute)?;
+    value
+        .parse()
+        .map_err(|_| SumoNetworkError::InvalidAttribute {
+            element: element_name,
+            attribute,
+            value: value.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two roads meeting at a junction, only the turn from a to b is allowed
+    //
+    //    J0 --a--> J1 --b--> J2
+    //               \
+    //                c--> J3
+    const SUMO_NETWORK: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<net version="1.20">
+    <edge id=":J1_0" function="internal">
+        <lane id=":J1_0_0" index="0" speed="13.89" length="2.00" shape="95.00,-1.60 97.00,-1.60"/>
+    </edge>
+    <edge id="a" from="J0" to="J1" priority="1">
+        <lane id="a_0" index="0" speed="13.89" length="95.00" shape="0.00,-1.60 95.00,-1.60"/>
+    </edge>
+    <edge id="b" from="J1" to="J2" priority="1">
+        <lane id="b_0" index="0" speed="27.78" length="95.00" shape="105.00,-1.60 200.00,-1.60"/>
+        <lane id="b_1" index="1" speed="27.78" length="95.00" shape="105.00,1.60 200.00,1.60"/>
+    </edge>
+    <edge id="c" from="J1" to="J3" priority="1">
+        <lane id="c_0" index="0" speed="13.89" length="95.00" shape="101.60,-5.00 101.60,-100.00"/>
+    </edge>
+    <junction id="J0" type="dead_end" x="0.00" y="0.00"/>
+    <junction id="J1" type="priority" x="100.00" y="0.00"/>
+    <junction id="J2" type="dead_end" x="200.00" y="0.00"/>
+    <junction id="J3" type="dead_end" x="100.00" y="-100.00"/>
+    <junction id=":J1_0_0" type="internal" x="96.00" y="-1.60"/>
+    <connection from="a" to="b" fromLane="0" toLane="0" via=":J1_0_0" dir="s" state="M"/>
+    <connection from=":J1_0" to="b" fromLane="0" toLane="0" dir="s" state="M"/>
+</net>"#;
+
+    #[test]
+    fn parse_sumo_network_maps_edges_and_connections() {
+        let graph = parse_sumo_network(SUMO_NETWORK).unwrap();
+
+        // Edges a, b, c own nodes (0, 1), (2, 3), (4, 5)
+        assert_eq!(graph.nodes.len(), 6);
+        assert_eq!(graph.edges, HashSet::from([(0, 1), (2, 3), (4, 5), (1, 2)]));
+        assert_eq!(
+            graph.shortest_path_map.get(&(0, 3)),
+            Some(&vec![0, 1, 2, 3])
+        );
+        // The turn onto c has no connection so it can't be reached from a
+        assert_eq!(graph.shortest_path_map.get(&(0, 5)), None);
+
+        // The second lane of b is averaged into the edge's position
+        assert_eq!(graph.nodes[2].position, Vec3::new(105., 0., 0.));
+        assert_eq!(
+            graph.edge_attributes[&(2, 3)],
+            EdgeAttributes {
+                speed_limit: Some(27.78),
+                lanes: Some(2),
+            }
+        );
+    }
+
+    #[test]
+    fn write_sumo_network_round_trips() {
+        let mut graph = NodeGraph::create();
+        graph.edge_attributes.insert(
+            (1, 9),
+            EdgeAttributes {
+                speed_limit: Some(8.),
+                lanes: Some(2),
+            },
+        );
+        let xml = write_sumo_network(&graph);
+        assert!(xml.contains(r#"<edge id="e1_9" from="n1" to="n9" priority="1">"#));
+        assert!(xml.contains(r#"<lane id="e1_9_1" index="1" speed="8.00""#));
+        assert!(xml.contains(r#"<junction id="n1" type="dead_end" x="1" y="-10""#));
+        assert!(xml.contains(r#"<connection from="e1_9" to="e9_7""#));
+
+        // Connected edges meet at their junctions, so importing them joins
+        // them up again and gives back the same network
+        let imported = parse_sumo_network(&xml).unwrap();
+        assert!(imported.validate().is_valid());
+        assert_eq!(imported.nodes.len(), graph.nodes.len());
+        assert_eq!(imported.edges.len(), graph.edges.len());
+        assert_eq!(imported.source_nodes.len(), graph.source_nodes.len());
+        assert_eq!(imported.dest_nodes.len(), graph.dest_nodes.len());
+        assert_eq!(
+            imported.shortest_path_map.len(),
+            graph.shortest_path_map.len()
+        );
+
+        let edge_geometry = |graph: &NodeGraph| -> Vec<[i32; 4]> {
+            let mut geometry: Vec<[i32; 4]> = graph
+                .edges
+                .iter()
+                .map(|(source, dest)| {
+                    let (start, end) = (graph.nodes[*source].position, graph.nodes[*dest].position);
+                    [start.x, start.z, end.x, end.z].map(|value| (value * 100.).round() as i32)
+                })
+                .collect();
+            geometry.sort();
+            geometry
+        };
+        assert_eq!(edge_geometry(&imported), edge_geometry(&graph));
+        let lanes_of = |graph: &NodeGraph, position: Vec3| {
+            let node = graph
+                .nodes
+                .iter()
+                .position(|node| node.position.distance(position) < MERGE_DISTANCE)
+                .unwrap();
+            let next = graph.node_map[&node].iter().next().copied().unwrap();
+            graph.edge_attributes.get(&(node, next)).cloned()
+        };
+        assert_eq!(
+            lanes_of(&imported, graph.nodes[1].position),
+            Some(EdgeAttributes {
+                speed_limit: Some(8.),
+                lanes: Some(2),
+            })
+        );
+    }
+}
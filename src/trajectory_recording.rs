@@ -0,0 +1,371 @@
+use std::{
+    collections::HashSet,
+    fmt, fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    node_graph::{Node, NodeGraph},
+    simulation_clock::SimulationClock,
+    vehicles::Vehicle,
+};
+
+// Identifies trajectory files, followed by the format version
+const MAGIC: &[u8; 4] = b"TRAJ";
+const FORMAT_VERSION: u32 = 1;
+
+// Where recordings are saved by the keyboard shortcut
+const RECORDING_PATH: &str = "recording.traj";
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(io::Error),
+    InvalidFormat(String),
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(error) => write!(f, "failed to access recording: {}", error),
+            RecordingError::InvalidFormat(message) => {
+                write!(f, "invalid recording: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<io::Error> for RecordingError {
+    fn from(error: io::Error) -> Self {
+        RecordingError::Io(error)
+    }
+}
+
+// Where a vehicle was during a tick
+#[derive(Clone, Debug, PartialEq)]
+pub struct VehicleFrame {
+    pub vehicle_id: u32,
+    pub position: Vec3,
+    // Rotation around the y axis in radians
+    pub heading: f32,
+}
+
+// Every vehicle in the simulation during a tick, ordered by vehicle id
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryFrame {
+    // Simulation time in seconds
+    pub time: f32,
+    pub vehicles: Vec<VehicleFrame>,
+}
+
+// The network and the vehicle positions of each tick of a run.
+//
+// Files start with the magic bytes and version, followed by the node
+// positions, the edges and then the frames. Counts are written before each
+// list and every value is a little endian u32 or f32, so each vehicle takes
+// 20 bytes per frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrajectoryRecording {
+    pub nodes: Vec<Vec3>,
+    pub edges: Vec<(usize, usize)>,
+    // Frames in time order
+    pub frames: Vec<TrajectoryFrame>,
+}
+
+impl TrajectoryRecording {
+    // Starts an empty recording of the given network
+    pub fn new(node_graph: &NodeGraph) -> Self {
+        let mut edges: Vec<(usize, usize)> = node_graph.edges.iter().copied().collect();
+        edges.sort();
+        TrajectoryRecording {
+            nodes: node_graph.nodes.iter().map(|node| node.position).collect(),
+            edges,
+            frames: Vec::new(),
+        }
+    }
+
+    // Rebuilds the recorded network so it can be drawn
+    pub fn to_node_graph(&self) -> NodeGraph {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|position| Node {
+                position: *position,
+            })
+            .collect();
+        let edges: HashSet<(usize, usize)> = self.edges.iter().copied().collect();
+        NodeGraph::new(nodes, edges)
+    }
+
+    // The time of the last frame, or 0 if nothing was recorded
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0., |frame| frame.time)
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(writer, FORMAT_VERSION)?;
+
+        write_u32(writer, self.nodes.len() as u32)?;
+        for position in self.nodes.iter() {
+            write_vec3(writer, *position)?;
+        }
+
+        write_u32(writer, self.edges.len() as u32)?;
+        for (source, dest) in self.edges.iter() {
+            write_u32(writer, *source as u32)?;
+            write_u32(writer, *dest as u32)?;
+        }
+
+        write_u32(writer, self.frames.len() as u32)?;
+        for frame in self.frames.iter() {
+            write_f32(writer, frame.time)?;
+            write_u32(writer, frame.vehicles.len() as u32)?;
+            for vehicle in frame.vehicles.iter() {
+                write_u32(writer, vehicle.vehicle_id)?;
+                write_vec3(writer, vehicle.position)?;
+                write_f32(writer, vehicle.heading)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, RecordingError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RecordingError::InvalidFormat(
+                "not a trajectory recording".to_string(),
+            ));
+        }
+        let version = read_u32(reader)?;
+        if version != FORMAT_VERSION {
+            return Err(RecordingError::InvalidFormat(format!(
+                "version {} is not supported, expected version {}",
+                version, FORMAT_VERSION
+            )));
+        }
+
+        let node_count = read_u32(reader)? as usize;
+        let nodes = (0..node_count)
+            .map(|_| read_vec3(reader))
+            .collect::<io::Result<Vec<Vec3>>>()?;
+
+        let edge_count = read_u32(reader)? as usize;
+        let mut edges = Vec::new();
+        for _ in 0..edge_count {
+            let edge = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+            if edge.0 >= node_count || edge.1 >= node_count {
+                return Err(RecordingError::InvalidFormat(format!(
+                    "edge ({}, {}) references a missing node",
+                    edge.0, edge.1
+                )));
+            }
+            edges.push(edge);
+        }
+
+        let frame_count = read_u32(reader)?;
+        let mut frames = Vec::new();
+        for _ in 0..frame_count {
+            let time = read_f32(reader)?;
+            let vehicle_count = read_u32(reader)?;
+            let mut vehicles = Vec::new();
+            for _ in 0..vehicle_count {
+                vehicles.push(VehicleFrame {
+                    vehicle_id: read_u32(reader)?,
+                    position: read_vec3(reader)?,
+                    heading: read_f32(reader)?,
+                });
+            }
+            frames.push(TrajectoryFrame { time, vehicles });
+        }
+
+        Ok(TrajectoryRecording {
+            nodes,
+            edges,
+            frames,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        let mut reader = BufReader::new(fs::File::open(path)?);
+        Self::read(&mut reader)
+    }
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32(writer: &mut impl Write, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_vec3(writer: &mut impl Write, value: Vec3) -> io::Result<()> {
+    for component in value.to_array() {
+        write_f32(writer, component)?;
+    }
+    Ok(())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_vec3(reader: &mut impl Read) -> io::Result<Vec3> {
+    Ok(Vec3::new(
+        read_f32(reader)?,
+        read_f32(reader)?,
+        read_f32(reader)?,
+    ))
+}
+
+// Holds the recording in progress, if any
+#[derive(Resource, Default)]
+pub struct TrajectoryRecorder {
+    pub recording: Option<TrajectoryRecording>,
+}
+
+impl TrajectoryRecorder {
+    // Stops recording and saves what was recorded
+    fn finish(&mut self) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        match recording.save(RECORDING_PATH) {
+            Ok(()) => info!(
+                "Saved {} frames of vehicle trajectories to {}",
+                recording.frames.len(),
+                RECORDING_PATH
+            ),
+            Err(error) => error!("Failed to save trajectory recording: {}", error),
+        }
+    }
+}
+
+// Starts recording when R is pressed, pressing it again saves the recording
+pub fn toggle_trajectory_recording(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    node_graph: Res<NodeGraph>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+) {
+    if !keyboard.just_pressed(KeyCode::KeyR) {
+        return;
+    }
+
+    if recorder.recording.is_some() {
+        recorder.finish();
+    } else {
+        recorder.recording = Some(TrajectoryRecording::new(&node_graph));
+        info!("Recording vehicle trajectories");
+    }
+}
+
+// Adds the position of every vehicle to the recording in progress
+pub fn record_trajectories(
+    mut recorder: ResMut<TrajectoryRecorder>,
+    clock: Res<SimulationClock>,
+    vehicle_query: Query<(&Vehicle, &Transform)>,
+) {
+    let Some(recording) = recorder.recording.as_mut() else {
+        return;
+    };
+
+    let mut vehicles: Vec<VehicleFrame> = vehicle_query
+        .iter()
+        .map(|(vehicle, transform)| VehicleFrame {
+            vehicle_id: vehicle.id() as u32,
+            position: transform.translation,
+            heading: transform.rotation.to_euler(EulerRot::YXZ).0,
+        })
+        .collect();
+    vehicles.sort_by_key(|vehicle| vehicle.vehicle_id);
+    recording.frames.push(TrajectoryFrame {
+        time: clock.elapsed_seconds(),
+        vehicles,
+    });
+}
+
+// Saves the recording in progress when the app exits
+pub fn save_trajectory_recording(
+    mut exit_events: EventReader<AppExit>,
+    mut recorder: ResMut<TrajectoryRecorder>,
+) {
+    if exit_events.read().next().is_none() {
+        return;
+    }
+    recorder.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording() -> TrajectoryRecording {
+        let mut recording = TrajectoryRecording::new(&NodeGraph::create_highway_merge());
+        for tick in 1..=3 {
+            recording.frames.push(TrajectoryFrame {
+                time: tick as f32 * 0.05,
+                vehicles: (0..tick)
+                    .map(|vehicle_id| VehicleFrame {
+                        vehicle_id,
+                        position: Vec3::new(vehicle_id as f32, 0., -1.5),
+                        heading: 0.25,
+                    })
+                    .collect(),
+            });
+        }
+        recording
+    }
+
+    #[test]
+    fn recording_round_trips_through_bytes() {
+        let recording = recording();
+        let mut bytes = Vec::new();
+        recording.write(&mut bytes).unwrap();
+
+        // Header, 8 nodes, 7 edges, the frame count and 3 frames of 1, 2 and 3 vehicles
+        assert_eq!(bytes.len(), 8 + 4 + 8 * 12 + 4 + 7 * 8 + 4 + 3 * 8 + 6 * 20);
+        let loaded = TrajectoryRecording::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(loaded, recording);
+        assert_eq!(loaded.duration(), recording.frames[2].time);
+        assert_eq!(loaded.to_node_graph().edges.len(), 7);
+    }
+
+    #[test]
+    fn read_rejects_invalid_files() {
+        let mut bytes = Vec::new();
+        recording().write(&mut bytes).unwrap();
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(matches!(
+            TrajectoryRecording::read(&mut &truncated[..]),
+            Err(RecordingError::Io(_))
+        ));
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(
+            TrajectoryRecording::read(&mut wrong_magic.as_slice()),
+            Err(RecordingError::InvalidFormat(_))
+        ));
+    }
+}
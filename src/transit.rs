@@ -0,0 +1,546 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "bevy")]
+use crate::simulation_rng::SimulationRng;
+use crate::{
+    entry_queues::EntryQueues,
+    node_graph::NodeGraph,
+    simulation_clock::SimulationClock,
+    vehicle_id_generator::VehicleIdGenerator,
+    vehicle_types::{VehicleType, VehicleTypes},
+    vehicles::Vehicle,
+};
+
+#[derive(Debug)]
+pub enum TransitError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    // A line doesn't fit the network it is used with
+    Invalid(String),
+}
+
+impl fmt::Display for TransitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitError::Io(error) => write!(f, "failed to read transit lines: {}", error),
+            TransitError::Json(error) => write!(f, "failed to parse transit lines: {}", error),
+            TransitError::Invalid(message) => write!(f, "invalid transit line: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TransitError {}
+
+impl From<io::Error> for TransitError {
+    fn from(error: io::Error) -> Self {
+        TransitError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for TransitError {
+    fn from(error: serde_json::Error) -> Self {
+        TransitError::Json(error)
+    }
+}
+
+// A place on a line where buses stop to let passengers on and off
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitStop {
+    pub name: String,
+    // The edge the stop is on, which must be part of the line
+    pub edge: (usize, usize),
+    // How far along the edge the stop is, from 0 at the start to 1 at the end
+    #[serde(default = "default_stop_position")]
+    pub position: f32,
+    // Seconds spent at the stop
+    pub dwell_time: f32,
+    // When the bus is timetabled to arrive, in seconds after it departs
+    pub scheduled_offset: f32,
+}
+
+fn default_stop_position() -> f32 {
+    0.5
+}
+
+// A bus line following a fixed sequence of nodes, with stops in the order
+// they are served and the simulation times buses depart the first node
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitLine {
+    pub name: String,
+    pub nodes: Vec<usize>,
+    pub stops: Vec<TransitStop>,
+    pub departures: Vec<f32>,
+}
+
+impl TransitLine {
+    // Finds where each stop is along the line, checking that the line can be
+    // driven on the given network
+    fn stop_path_indices(&self, node_graph: &NodeGraph) -> Result<Vec<usize>, TransitError> {
+        let invalid =
+            |message: String| TransitError::Invalid(format!("{}: {}", self.name, message));
+        if self.nodes.len() < 2 {
+            return Err(invalid("lines need at least two nodes".to_string()));
+        }
+        for edge in self.nodes.windows(2) {
+            if !node_graph.edges.contains(&(edge[0], edge[1])) {
+                return Err(invalid(format!(
+                    "there is no edge from {} to {}",
+                    edge[0], edge[1]
+                )));
+            }
+        }
+
+        // Stops are served in order, so each is searched for after the last
+        let mut path_indices = Vec::new();
+        let mut search_start = 0;
+        for stop in self.stops.iter() {
+            if !(0. ..1.).contains(&stop.position) {
+                return Err(invalid(format!("stop {} is off its edge", stop.name)));
+            }
+            let Some(offset) = self.nodes[search_start..]
+                .windows(2)
+                .position(|edge| (edge[0], edge[1]) == stop.edge)
+            else {
+                return Err(invalid(format!(
+                    "stop {} isn't on the line after the previous stop",
+                    stop.name
+                )));
+            };
+            path_indices.push(search_start + offset);
+            search_start += offset;
+        }
+        Ok(path_indices)
+    }
+
+    pub fn validate(&self, node_graph: &NodeGraph) -> Result<(), TransitError> {
+        self.stop_path_indices(node_graph).map(|_| ())
+    }
+}
+
+// The transit lines run on the network
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransitLines {
+    pub lines: Vec<TransitLine>,
+}
+
+impl TransitLines {
+    // Creates a bus for every departure in the last tick and queues it at the
+    // first node of its line, so it only enters once there is room. Lines
+    // which don't fit the current network, e.g. after editing the network,
+    // are skipped.
+    pub fn dispatch(
+        &self,
+        node_graph: &NodeGraph,
+        vehicle_types: &VehicleTypes,
+        vehicle_id_generator: &mut VehicleIdGenerator,
+        rng: &mut impl Rng,
+        clock: &SimulationClock,
+        entry_queues: &mut EntryQueues,
+    ) {
+        let now = clock.elapsed_seconds();
+        let previous_tick = now - clock.delta_seconds();
+        let bus = vehicle_types
+            .get("bus")
+            .cloned()
+            .unwrap_or_else(VehicleType::bus);
+        for line in self.lines.iter() {
+            for departure in line.departures.iter() {
+                if *departure < previous_tick || *departure >= now {
+                    continue;
+                }
+                let Ok(transit_trip) = TransitTrip::new(line, *departure, node_graph) else {
+                    continue;
+                };
+                let bus = Vehicle::new(
+                    vehicle_id_generator.get_id(),
+                    bus.clone(),
+                    line.nodes.clone(),
+                    now,
+                    rng,
+                )
+                .with_transit_trip(transit_trip);
+                entry_queues.push(bus, now);
+            }
+        }
+    }
+}
+
+// Loads transit lines from a JSON file, checking they fit the network
+pub fn load_transit_lines(
+    path: impl AsRef<Path>,
+    node_graph: &NodeGraph,
+) -> Result<TransitLines, TransitError> {
+    let transit_lines: TransitLines = serde_json::from_str(&fs::read_to_string(path)?)?;
+    for line in transit_lines.lines.iter() {
+        line.validate(node_graph)?;
+    }
+    Ok(transit_lines)
+}
+
+// A stop as served by one trip of a line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledStop {
+    // The index of the stop's edge in the vehicle's path
+    pub path_index: usize,
+    pub position: f32,
+    pub dwell_time: f32,
+    // The simulation time the bus is timetabled to arrive
+    pub scheduled_time: f32,
+}
+
+// The progress of a bus through the stops of its line
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitTrip {
+    pub line: String,
+    pub departure: f32,
+    pub stops: Vec<ScheduledStop>,
+    // The stop the bus is driving to or waiting at
+    pub next_stop: usize,
+    // The time left at the current stop while the bus is stopped
+    pub dwell_remaining: Option<f32>,
+}
+
+impl TransitTrip {
+    pub fn new(
+        line: &TransitLine,
+        departure: f32,
+        node_graph: &NodeGraph,
+    ) -> Result<Self, TransitError> {
+        let stops = line
+            .stop_path_indices(node_graph)?
+            .into_iter()
+            .zip(line.stops.iter())
+            .map(|(path_index, stop)| ScheduledStop {
+                path_index,
+                position: stop.position,
+                dwell_time: stop.dwell_time,
+                scheduled_time: departure + stop.scheduled_offset,
+            })
+            .collect();
+        Ok(TransitTrip {
+            line: line.name.clone(),
+            departure,
+            stops,
+            next_stop: 0,
+            dwell_remaining: None,
+        })
+    }
+
+    pub fn is_dwelling(&self) -> bool {
+        self.dwell_remaining.is_some()
+    }
+
+    // Where the bus has to stop on the edge at the given path index, if the
+    // next stop is on that edge
+    pub fn stop_position(&self, path_index: usize) -> Option<f32> {
+        let stop = self.stops.get(self.next_stop)?;
+        (stop.path_index == path_index && !self.is_dwelling()).then_some(stop.position)
+    }
+
+    // Starts waiting at the next stop
+    pub fn arrive(&mut self) {
+        self.dwell_remaining = self.stops.get(self.next_stop).map(|stop| stop.dwell_time);
+    }
+
+    // Counts down the time at a stop, returning whether the bus has to keep
+    // waiting. Once the time is up the bus heads for the following stop.
+    pub fn dwell(&mut self, delta_seconds: f32) -> bool {
+        let Some(remaining) = self.dwell_remaining.as_mut() else {
+            return false;
+        };
+        *remaining -= delta_seconds;
+        if *remaining > 0. {
+            return true;
+        }
+        self.dwell_remaining = None;
+        self.next_stop += 1;
+        false
+    }
+}
+
+// A bus reaching one of its stops
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StopArrival {
+    pub line: String,
+    pub departure: f32,
+    pub stop: usize,
+    pub scheduled_time: f32,
+    pub arrival_time: f32,
+}
+
+impl StopArrival {
+    // Positive when the bus is late and negative when it is early
+    pub fn delay(&self) -> f32 {
+        self.arrival_time - self.scheduled_time
+    }
+}
+
+// How well a line kept to its timetable
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinePunctuality {
+    pub line: String,
+    pub arrival_count: usize,
+    pub mean_delay: f32,
+    pub max_delay: f32,
+    // The share of arrivals within the on time window
+    pub on_time_share: f32,
+}
+
+// Stores every stop arrival made by a bus
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransitLog {
+    pub arrivals: Vec<StopArrival>,
+    // Arrivals up to this many seconds early or late are on time
+    pub early_tolerance: f32,
+    pub late_tolerance: f32,
+}
+
+impl Default for TransitLog {
+    fn default() -> Self {
+        TransitLog {
+            arrivals: Vec::new(),
+            early_tolerance: 60.,
+            late_tolerance: 300.,
+        }
+    }
+}
+
+impl TransitLog {
+    // Summarizes the arrivals of each line, ordered by line name
+    pub fn punctuality(&self) -> Vec<LinePunctuality> {
+        let mut arrivals_by_line: BTreeMap<&str, Vec<&StopArrival>> = BTreeMap::new();
+        for arrival in self.arrivals.iter() {
+            arrivals_by_line
+                .entry(&arrival.line)
+                .or_default()
+                .push(arrival);
+        }
+
+        arrivals_by_line
+            .into_iter()
+            .map(|(line, arrivals)| {
+                let delays: Vec<f32> = arrivals.iter().map(|arrival| arrival.delay()).collect();
+                let on_time_count = delays
+                    .iter()
+                    .filter(|delay| (-self.early_tolerance..=self.late_tolerance).contains(*delay))
+                    .count();
+                LinePunctuality {
+                    line: line.to_string(),
+                    arrival_count: delays.len(),
+                    mean_delay: delays.iter().sum::<f32>() / delays.len() as f32,
+                    max_delay: delays.iter().copied().fold(f32::MIN, f32::max),
+                    on_time_share: on_time_count as f32 / delays.len() as f32,
+                }
+            })
+            .collect()
+    }
+
+    // Writes one row per stop arrival
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "line,departure,stop,scheduled_time,arrival_time,delay"
+        )?;
+        for arrival in self.arrivals.iter() {
+            writeln!(
+                writer,
+                "{},{:.3},{},{:.3},{:.3},{:.3}",
+                arrival.line,
+                arrival.departure,
+                arrival.stop,
+                arrival.scheduled_time,
+                arrival.arrival_time,
+                arrival.delay()
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn write_punctuality_table(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{:<16} {:>8} {:>14} {:>13} {:>8}",
+            "line", "arrivals", "mean delay (s)", "max delay (s)", "on time"
+        )?;
+        for punctuality in self.punctuality() {
+            writeln!(
+                writer,
+                "{:<16} {:>8} {:>14.1} {:>13.1} {:>7.0}%",
+                punctuality.line,
+                punctuality.arrival_count,
+                punctuality.mean_delay,
+                punctuality.max_delay,
+                punctuality.on_time_share * 100.
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes transit_stops.csv into the given directory
+    pub fn save(&self, directory: impl AsRef<Path>) -> io::Result<()> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        self.write_csv(&mut fs::File::create(directory.join("transit_stops.csv"))?)
+    }
+}
+
+// Queues the buses departing in the last tick, they are spawned along with
+// other vehicles entering the network
+#[cfg(feature = "bevy")]
+pub fn dispatch_transit_vehicles(
+    node_graph: Res<NodeGraph>,
+    transit_lines: Res<TransitLines>,
+    vehicle_types: Res<VehicleTypes>,
+    mut vehicle_id_generator: ResMut<VehicleIdGenerator>,
+    mut rng: ResMut<SimulationRng>,
+    clock: Res<SimulationClock>,
+    mut entry_queues: ResMut<EntryQueues>,
+) {
+    transit_lines.dispatch(
+        &node_graph,
+        &vehicle_types,
+        &mut vehicle_id_generator,
+        &mut rng.0,
+        &clock,
+        &mut entry_queues,
+    );
+}
+
+// Saves the stop arrivals to the working directory when the app exits
+#[cfg(feature = "bevy")]
+pub fn save_transit_log(mut exit_events: EventReader<AppExit>, transit_log: Res<TransitLog>) {
+    if exit_events.read().next().is_none() || transit_log.arrivals.is_empty() {
+        return;
+    }
+
+    match transit_log.save(".") {
+        Ok(()) => info!(
+            "Saved {} stop arrivals to transit_stops.csv",
+            transit_log.arrivals.len()
+        ),
+        Err(error) => error!("Failed to save transit log: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::simulation::{Simulation, SimulationConfig};
+
+    // Runs along the bottom of the default intersection and out to the right
+    fn line() -> TransitLine {
+        TransitLine {
+            name: "1".to_string(),
+            nodes: vec![1, 9, 7],
+            stops: vec![
+                TransitStop {
+                    name: "approach".to_string(),
+                    edge: (1, 9),
+                    position: 0.4,
+                    dwell_time: 5.,
+                    scheduled_offset: 2.,
+                },
+                TransitStop {
+                    name: "exit".to_string(),
+                    edge: (9, 7),
+                    position: 0.5,
+                    dwell_time: 5.,
+                    scheduled_offset: 12.,
+                },
+            ],
+            departures: vec![1., 31.],
+        }
+    }
+
+    #[test]
+    fn validate_checks_lines_fit_the_network() {
+        let graph = NodeGraph::create();
+        assert!(line().validate(&graph).is_ok());
+
+        let mut line_with_gap = line();
+        line_with_gap.nodes = vec![1, 7];
+        assert!(matches!(
+            line_with_gap.validate(&graph),
+            Err(TransitError::Invalid(_))
+        ));
+        let mut stops_out_of_order = line();
+        stops_out_of_order.stops.reverse();
+        assert!(stops_out_of_order.validate(&graph).is_err());
+    }
+
+    #[test]
+    fn dispatched_buses_queue_for_room_to_enter() {
+        let graph = NodeGraph::create();
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let mut clock = SimulationClock::default();
+        clock.tick(Duration::from_secs(2));
+        let mut entry_queues = EntryQueues::default();
+        TransitLines {
+            lines: vec![line()],
+        }
+        .dispatch(
+            &graph,
+            &VehicleTypes::default(),
+            &mut VehicleIdGenerator::default(),
+            &mut rng,
+            &clock,
+            &mut entry_queues,
+        );
+        assert_eq!(entry_queues.queue_length(1), 1);
+
+        // The bus waits behind a car which has only just entered the line
+        let car = Vehicle::new(9, VehicleType::car(), vec![1, 9, 7], 0., &mut rng);
+        let has_room = |bus: &Vehicle| bus.has_room_to_enter([&car].into_iter(), &graph);
+        assert!(entry_queues.release(2., has_room).is_empty());
+        let entered = entry_queues.release(3., |_| true);
+        assert!(entered[0].transit_trip().is_some());
+    }
+
+    #[test]
+    fn buses_dwell_at_their_stops() {
+        let config = SimulationConfig {
+            // Keep ordinary traffic out of the way
+            spawn_interval: Duration::from_secs(1000),
+            seed: Some(1),
+            ..SimulationConfig::default()
+        };
+        let mut simulation = Simulation::new(NodeGraph::create(), &config);
+        simulation.transit_lines = TransitLines {
+            lines: vec![line()],
+        };
+        simulation.run_for(Duration::from_secs(60));
+
+        let transit_log = &simulation.transit_log;
+        let stops: Vec<(f32, usize)> = transit_log
+            .arrivals
+            .iter()
+            .map(|arrival| (arrival.departure, arrival.stop))
+            .collect();
+        assert_eq!(stops, vec![(1., 0), (1., 1), (31., 0), (31., 1)]);
+
+        // The second stop is reached after dwelling at the first
+        let arrivals = &transit_log.arrivals;
+        assert!(arrivals[1].arrival_time - arrivals[0].arrival_time > 5.);
+        let punctuality = transit_log.punctuality();
+        assert_eq!(punctuality.len(), 1);
+        assert_eq!(punctuality[0].arrival_count, 4);
+        assert_eq!(punctuality[0].on_time_share, 1.);
+    }
+}
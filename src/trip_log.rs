@@ -0,0 +1,307 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+
+// Everything known about a vehicle's trip once it reaches its destination
+#[derive(Clone, Debug, PartialEq)]
+pub struct TripRecord {
+    pub vehicle_id: usize,
+    pub source_node: usize,
+    pub dest_node: usize,
+    // The node path the vehicle drove
+    pub route: Vec<usize>,
+    // Simulation times in seconds
+    pub spawn_time: f32,
+    pub arrival_time: f32,
+    // The world space distance driven
+    pub distance: f32,
+    // The total time spent stopped, and how many separate stops were made
+    pub stopped_time: f32,
+    pub stop_count: usize,
+    // The simulation time the vehicle reached each node of the route
+    pub node_times: Vec<f32>,
+    // The speed the vehicle drives at when nothing is in its way
+    pub free_flow_speed: f32,
+    // The name of the vehicle's type
+    pub vehicle_type: String,
+}
+
+impl TripRecord {
+    pub fn travel_time(&self) -> f32 {
+        self.arrival_time - self.spawn_time
+    }
+
+    // The time lost compared to driving the whole trip at the free flow speed
+    pub fn delay(&self) -> f32 {
+        (self.travel_time() - self.distance / self.free_flow_speed).max(0.)
+    }
+}
+
+// Travel time statistics for all trips between a source and destination
+#[derive(Clone, Debug, PartialEq)]
+pub struct OdPairSummary {
+    pub source_node: usize,
+    pub dest_node: usize,
+    pub trip_count: usize,
+    pub mean_travel_time: f32,
+    pub median_travel_time: f32,
+    pub p85_travel_time: f32,
+    pub p95_travel_time: f32,
+    pub mean_stopped_time: f32,
+}
+
+// Travel time statistics for all trips made by a type of vehicle, e.g. the
+// response times of emergency vehicles
+#[derive(Clone, Debug, PartialEq)]
+pub struct VehicleTypeSummary {
+    pub vehicle_type: String,
+    pub trip_count: usize,
+    pub mean_travel_time: f32,
+    pub p95_travel_time: f32,
+    pub mean_delay: f32,
+}
+
+// Stores a record of every completed trip
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Default)]
+pub struct TripLog {
+    pub records: Vec<TripRecord>,
+}
+
+impl TripLog {
+    pub fn record(&mut self, record: TripRecord) {
+        self.records.push(record);
+    }
+
+    // Summarizes travel times per source/destination pair, ordered by pair
+    pub fn summary(&self) -> Vec<OdPairSummary> {
+        let mut trips_by_od_pair: BTreeMap<(usize, usize), Vec<&TripRecord>> = BTreeMap::new();
+        for record in self.records.iter() {
+            trips_by_od_pair
+                .entry((record.source_node, record.dest_node))
+                .or_default()
+                .push(record);
+        }
+
+        trips_by_od_pair
+            .into_iter()
+            .map(|((source_node, dest_node), records)| {
+                let mut travel_times: Vec<f32> =
+                    records.iter().map(|record| record.travel_time()).collect();
+                travel_times.sort_by(f32::total_cmp);
+                let trip_count = records.len();
+                let stopped_time: f32 = records.iter().map(|record| record.stopped_time).sum();
+                OdPairSummary {
+                    source_node,
+                    dest_node,
+                    trip_count,
+                    mean_travel_time: travel_times.iter().sum::<f32>() / trip_count as f32,
+                    median_travel_time: percentile(&travel_times, 50.),
+                    p85_travel_time: percentile(&travel_times, 85.),
+                    p95_travel_time: percentile(&travel_times, 95.),
+                    mean_stopped_time: stopped_time / trip_count as f32,
+                }
+            })
+            .collect()
+    }
+
+    // Summarizes travel times per vehicle type, ordered by type name
+    pub fn vehicle_type_summary(&self) -> Vec<VehicleTypeSummary> {
+        let mut trips_by_type: BTreeMap<&str, Vec<&TripRecord>> = BTreeMap::new();
+        for record in self.records.iter() {
+            trips_by_type
+                .entry(&record.vehicle_type)
+                .or_default()
+                .push(record);
+        }
+
+        trips_by_type
+            .into_iter()
+            .map(|(vehicle_type, records)| {
+                let mut travel_times: Vec<f32> =
+                    records.iter().map(|record| record.travel_time()).collect();
+                travel_times.sort_by(f32::total_cmp);
+                let trip_count = records.len();
+                let delay: f32 = records.iter().map(|record| record.delay()).sum();
+                VehicleTypeSummary {
+                    vehicle_type: vehicle_type.to_string(),
+                    trip_count,
+                    mean_travel_time: travel_times.iter().sum::<f32>() / trip_count as f32,
+                    p95_travel_time: percentile(&travel_times, 95.),
+                    mean_delay: delay / trip_count as f32,
+                }
+            })
+            .collect()
+    }
+
+    pub fn write_vehicle_type_table(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "{:<12} {:>6} {:>20} {:>19} {:>14}",
+            "vehicle", "trips", "mean travel time (s)", "p95 travel time (s)", "mean delay (s)"
+        )?;
+        for summary in self.vehicle_type_summary() {
+            writeln!(
+                writer,
+                "{:<12} {:>6} {:>20.1} {:>19.1} {:>14.1}",
+                summary.vehicle_type,
+                summary.trip_count,
+                summary.mean_travel_time,
+                summary.p95_travel_time,
+                summary.mean_delay
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes one row per trip. The route is written as space separated node indices.
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "vehicle_id,source_node,dest_node,spawn_time,arrival_time,travel_time,distance,stopped_time,stop_count,route,vehicle_type"
+        )?;
+        for record in self.records.iter() {
+            let route: Vec<String> = record.route.iter().map(usize::to_string).collect();
+            writeln!(
+                writer,
+                "{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{},{},{}",
+                record.vehicle_id,
+                record.source_node,
+                record.dest_node,
+                record.spawn_time,
+                record.arrival_time,
+                record.travel_time(),
+                record.distance,
+                record.stopped_time,
+                record.stop_count,
+                route.join(" "),
+                record.vehicle_type
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes one row per source/destination pair
+    pub fn write_summary_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "source_node,dest_node,trip_count,mean_travel_time,median_travel_time,p85_travel_time,p95_travel_time,mean_stopped_time"
+        )?;
+        for summary in self.summary() {
+            writeln!(
+                writer,
+                "{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3}",
+                summary.source_node,
+                summary.dest_node,
+                summary.trip_count,
+                summary.mean_travel_time,
+                summary.median_travel_time,
+                summary.p85_travel_time,
+                summary.p95_travel_time,
+                summary.mean_stopped_time
+            )?;
+        }
+        Ok(())
+    }
+
+    // Writes trips.csv and trip_summary.csv into the given directory
+    pub fn save(&self, directory: impl AsRef<Path>) -> io::Result<()> {
+        let directory = directory.as_ref();
+        fs::create_dir_all(directory)?;
+        self.write_csv(&mut fs::File::create(directory.join("trips.csv"))?)?;
+        self.write_summary_csv(&mut fs::File::create(directory.join("trip_summary.csv"))?)
+    }
+}
+
+// Gets a percentile of sorted values using the nearest rank method
+fn percentile(sorted_values: &[f32], percentile: f32) -> f32 {
+    let rank = (percentile / 100. * sorted_values.len() as f32).ceil() as usize;
+    sorted_values[rank.clamp(1, sorted_values.len()) - 1]
+}
+
+// Saves the trip log to the working directory when the app exits
+#[cfg(feature = "bevy")]
+pub fn save_trip_log(mut exit_events: EventReader<AppExit>, trip_log: Res<TripLog>) {
+    if exit_events.read().next().is_none() {
+        return;
+    }
+
+    match trip_log.save(".") {
+        Ok(()) => info!("Saved {} trips to trips.csv", trip_log.records.len()),
+        Err(error) => error!("Failed to save trip log: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trip(vehicle_id: usize, od_pair: (usize, usize), travel_time: f32) -> TripRecord {
+        TripRecord {
+            vehicle_id,
+            source_node: od_pair.0,
+            dest_node: od_pair.1,
+            route: vec![od_pair.0, 9, od_pair.1],
+            spawn_time: 10.,
+            arrival_time: 10. + travel_time,
+            distance: 20.,
+            stopped_time: travel_time / 4.,
+            stop_count: 1,
+            node_times: vec![10., 10. + travel_time / 2., 10. + travel_time],
+            free_flow_speed: 8.,
+            vehicle_type: "car".to_string(),
+        }
+    }
+
+    #[test]
+    fn summary_groups_trips_by_od_pair() {
+        let mut trip_log = TripLog::default();
+        for (index, travel_time) in [4., 1., 3., 2., 10.].into_iter().enumerate() {
+            trip_log.record(trip(index, (1, 7), travel_time));
+        }
+        trip_log.record(trip(5, (1, 3), 8.));
+
+        let summary = trip_log.summary();
+        assert_eq!(summary.len(), 2);
+        assert_eq!(
+            summary[0],
+            OdPairSummary {
+                source_node: 1,
+                dest_node: 3,
+                trip_count: 1,
+                mean_travel_time: 8.,
+                median_travel_time: 8.,
+                p85_travel_time: 8.,
+                p95_travel_time: 8.,
+                mean_stopped_time: 2.,
+            }
+        );
+        assert_eq!(summary[1].trip_count, 5);
+        assert_eq!(summary[1].mean_travel_time, 4.);
+        assert_eq!(summary[1].median_travel_time, 3.);
+        assert_eq!(summary[1].p85_travel_time, 10.);
+        assert_eq!(summary[1].p95_travel_time, 10.);
+    }
+
+    #[test]
+    fn write_csv_writes_a_row_per_trip() {
+        let mut trip_log = TripLog::default();
+        trip_log.record(trip(3, (1, 7), 2.5));
+
+        let mut csv = Vec::new();
+        trip_log.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "3,1,7,10.000,12.500,2.500,20.000,0.625,1,1 9 7,car"
+        );
+    }
+}
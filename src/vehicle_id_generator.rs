@@ -1,6 +1,9 @@
-use bevy::prelude::*;
+#[cfg(feature = "bevy")]
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
 
-#[derive(Resource, Default)]
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct VehicleIdGenerator {
     id: usize,
 }
@@ -1,11 +1,15 @@
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+#[cfg(feature = "bevy")]
 use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
 
-#[derive(Resource, Default)]
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct VehicleSpawnLimiter {
     interval: Duration,
-    last_spawned: Option<Instant>,
+    // The simulation time of the last spawn
+    last_spawned: Option<Duration>,
 }
 
 impl VehicleSpawnLimiter {
@@ -16,13 +20,14 @@ impl VehicleSpawnLimiter {
         }
     }
 
-    pub fn try_spawn(&mut self) -> bool {
+    // Checks whether a vehicle can be spawned at the given simulation time
+    pub fn try_spawn(&mut self, now: Duration) -> bool {
         if let Some(last_spawned) = self.last_spawned {
-            if last_spawned.elapsed() < self.interval {
+            if now.saturating_sub(last_spawned) < self.interval {
                 return false;
             }
         }
-        self.last_spawned = Some(Instant::now());
+        self.last_spawned = Some(now);
         return true;
     }
 }
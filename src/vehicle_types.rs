@@ -0,0 +1,233 @@
+#[cfg(feature = "bevy")]
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+// The dimensions and performance of a kind of vehicle. Distances are in
+// world units and colors are sRGB.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VehicleType {
+    pub name: String,
+    pub length: f32,
+    pub width: f32,
+    pub height: f32,
+    // Each vehicle is given a desired speed between these
+    pub min_speed: f32,
+    pub max_speed: f32,
+    // How quickly the vehicle gets up to speed, in world units per second squared
+    pub acceleration: f32,
+    pub color: [f32; 3],
+    // Emergency vehicles are given priority at nodes and other vehicles pull
+    // over for them
+    #[serde(default)]
+    pub is_emergency: bool,
+}
+
+impl VehicleType {
+    pub fn car() -> Self {
+        VehicleType {
+            name: "car".to_string(),
+            length: 0.5,
+            width: 0.3,
+            height: 0.2,
+            min_speed: 4.,
+            max_speed: 10.,
+            acceleration: 3.,
+            color: [0.3, 0.3, 0.5],
+            is_emergency: false,
+        }
+    }
+
+    pub fn truck() -> Self {
+        VehicleType {
+            name: "truck".to_string(),
+            length: 1.2,
+            width: 0.4,
+            height: 0.35,
+            min_speed: 3.,
+            max_speed: 7.,
+            acceleration: 1.2,
+            color: [0.6, 0.35, 0.2],
+            is_emergency: false,
+        }
+    }
+
+    pub fn bus() -> Self {
+        VehicleType {
+            name: "bus".to_string(),
+            length: 1.3,
+            width: 0.4,
+            height: 0.4,
+            min_speed: 3.,
+            max_speed: 7.,
+            acceleration: 1.5,
+            color: [0.2, 0.55, 0.3],
+            is_emergency: false,
+        }
+    }
+
+    pub fn motorcycle() -> Self {
+        VehicleType {
+            name: "motorcycle".to_string(),
+            length: 0.25,
+            width: 0.12,
+            height: 0.2,
+            min_speed: 5.,
+            max_speed: 10.,
+            acceleration: 4.,
+            color: [0.7, 0.2, 0.2],
+            is_emergency: false,
+        }
+    }
+
+    pub fn emergency() -> Self {
+        VehicleType {
+            name: "emergency".to_string(),
+            length: 0.6,
+            width: 0.3,
+            height: 0.25,
+            min_speed: 9.,
+            max_speed: 11.,
+            acceleration: 3.5,
+            color: [0.95, 0.95, 0.95],
+            is_emergency: true,
+        }
+    }
+
+    // Picks a desired speed for a new vehicle of this type
+    pub fn random_speed(&self, rng: &mut impl Rng) -> f32 {
+        self.min_speed + (self.max_speed - self.min_speed) * rng.gen::<f32>()
+    }
+
+    #[cfg(feature = "bevy")]
+    pub fn color(&self) -> Color {
+        let [red, green, blue] = self.color;
+        Color::srgb(red, green, blue)
+    }
+}
+
+// Vehicles made before vehicle types existed were all cars
+impl Default for VehicleType {
+    fn default() -> Self {
+        VehicleType::car()
+    }
+}
+
+// A vehicle type and the share of spawned vehicles which are that type
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FleetShare {
+    pub vehicle_type: VehicleType,
+    // Relative to the other shares, they don't need to add up to 1
+    pub share: f32,
+}
+
+// The vehicle types which can be spawned and the fleet mix they are spawned in
+#[cfg_attr(feature = "bevy", derive(Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VehicleTypes {
+    pub fleet: Vec<FleetShare>,
+}
+
+impl Default for VehicleTypes {
+    fn default() -> Self {
+        VehicleTypes {
+            fleet: vec![
+                FleetShare {
+                    vehicle_type: VehicleType::car(),
+                    share: 0.8,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::truck(),
+                    share: 0.1,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::bus(),
+                    share: 0.03,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::motorcycle(),
+                    share: 0.07,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::emergency(),
+                    share: 0.01,
+                },
+            ],
+        }
+    }
+}
+
+impl VehicleTypes {
+    pub fn get(&self, name: &str) -> Option<&VehicleType> {
+        self.fleet
+            .iter()
+            .map(|fleet_share| &fleet_share.vehicle_type)
+            .find(|vehicle_type| vehicle_type.name == name)
+    }
+
+    // Picks the type of a new vehicle according to the fleet mix. Falls back
+    // to a car if no type has a positive share.
+    pub fn choose(&self, rng: &mut impl Rng) -> VehicleType {
+        self.fleet
+            .choose_weighted(rng, |fleet_share| fleet_share.share.max(0.))
+            .map_or_else(
+                |_| VehicleType::car(),
+                |fleet_share| fleet_share.vehicle_type.clone(),
+            )
+    }
+
+    // Picks the type of a vehicle which was parked before the simulation
+    // started. Buses and emergency vehicles don't park, so they are left out
+    // of the fleet mix.
+    pub fn choose_parked(&self, rng: &mut impl Rng) -> VehicleType {
+        let parked_fleet = VehicleTypes {
+            fleet: self
+                .fleet
+                .iter()
+                .filter(|fleet_share| {
+                    fleet_share.vehicle_type.name != "bus" && !fleet_share.vehicle_type.is_emergency
+                })
+                .cloned()
+                .collect(),
+        };
+        parked_fleet.choose(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn choose_follows_the_fleet_mix() {
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let vehicle_types = VehicleTypes {
+            fleet: vec![
+                FleetShare {
+                    vehicle_type: VehicleType::car(),
+                    share: 3.,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::truck(),
+                    share: 1.,
+                },
+                FleetShare {
+                    vehicle_type: VehicleType::bus(),
+                    share: 0.,
+                },
+            ],
+        };
+
+        let trucks = (0..4000)
+            .map(|_| vehicle_types.choose(&mut rng))
+            .inspect(|vehicle_type| assert_ne!(vehicle_type.name, "bus"))
+            .filter(|vehicle_type| vehicle_type.name == "truck")
+            .count();
+        assert!((900..1100).contains(&trucks), "{} trucks", trucks);
+
+        let empty = VehicleTypes { fleet: Vec::new() };
+        assert_eq!(empty.choose(&mut rng), VehicleType::car());
+    }
+}
@@ -1,23 +1,76 @@
 use core::f32;
 use std::collections::HashMap;
 
-use rand::{self, seq::IteratorRandom};
+use rand::{seq::SliceRandom, Rng};
 
+#[cfg(feature = "bevy")]
 use bevy::prelude::*;
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    node_graph::{Node, NodeGraph},
-    node_graph_renderer::NodeGraphRenderer,
+    crosswalks::Crosswalk,
+    edge_metrics::EdgeMetrics,
+    emergency_policy::EmergencyPolicy,
+    entry_queues::EntryQueues,
+    node_graph::{Node, NodeGraph, LANE_WIDTH},
+    parking::{self, ParkingAreas, ParkingLog, ParkingSearch, ParkingTrip},
+    simulation_clock::SimulationClock,
+    transit::{StopArrival, TransitLog, TransitTrip},
+    trip_log::{TripLog, TripRecord},
     vehicle_id_generator::VehicleIdGenerator,
     vehicle_spawn_limiter::VehicleSpawnLimiter,
+    vehicle_types::{VehicleType, VehicleTypes},
 };
+#[cfg(feature = "bevy")]
+use crate::{node_graph_renderer::NodeGraphRenderer, simulation_rng::SimulationRng};
 
-const MIN_SPEED: f32 = 4.;
-const MAX_SPEED: f32 = 10.;
+// The space kept between the back of a vehicle and the front of the one
+// behind it
+const MIN_GAP: f32 = 0.2;
+// The distance a vehicle should stay back from a node when waiting
+// Note: make sure this smaller than (min dist between connected nodes along a bidirectional edge / 2)
+const NODE_BUFFER: f32 = 0.9;
+// Vehicles entering the network wait at their first node while traffic
+// already on the network is this close to it
+const GIVE_WAY_DISTANCE: f32 = 2.;
+// Vehicles moving slower than this are considered to be stopped
+const STOPPED_SPEED: f32 = 0.1;
+// How close to its next node an emergency vehicle preempts it, in world units
+const PREEMPTION_DISTANCE: f32 = 6.;
+// How far ahead of an emergency vehicle other vehicles pull over for it. This
+// is longer than the preemption distance so vehicles waiting at a preempted
+// node never block the emergency vehicle it is held for.
+const YIELD_DISTANCE: f32 = 8.;
 
-#[derive(Component)]
+// What vehicles need to know about the other vehicles on their edge
+struct VehicleOnEdge {
+    edge_position: f32,
+    length: f32,
+    // Buses waiting at a stop
+    is_dwelling: bool,
+    // Vehicles pulled over for an emergency vehicle
+    is_yielding: bool,
+}
+
+// The other traffic vehicles react to while driving
+#[derive(Default)]
+struct Traffic {
+    vehicle_map: HashMap<(usize, usize), Vec<VehicleOnEdge>>,
+    // Nodes held clear for approaching emergency vehicles, mapped to the id of
+    // the vehicle they are held for
+    preempted_nodes: HashMap<usize, usize>,
+    // The start of each crosswalk vehicles have to stop for, in edge space
+    blocked_crosswalks: HashMap<(usize, usize), Vec<f32>>,
+}
+
+#[cfg_attr(feature = "bevy", derive(Component))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Vehicle {
     id: usize,
+    // The size and performance of the vehicle
+    #[serde(default)]
+    vehicle_type: VehicleType,
     // A pre-calculated node path through the network
     path: Vec<usize>,
     // The position of the vehicle along the node path
@@ -25,21 +78,181 @@ pub struct Vehicle {
     // A parameterized value along the edge described by
     // (path[path_index], path[path_index+1])
     edge_position: f32,
-    // The speed of the vehicle
+    // The speed the vehicle drives at when nothing is in its way
     speed: f32,
+    // The simulation time in seconds when the vehicle was spawned
+    spawn_time: f32,
+    // The total world space distance driven so far
+    distance_traveled: f32,
+    // The total time spent stopped, and the number of separate stops made
+    stopped_time: f32,
+    stop_count: usize,
+    // Whether the vehicle was stopped during the last update
+    is_stopped: bool,
+    // The simulation time the vehicle reached each node of the path so far
+    node_times: Vec<f32>,
+    // The speed driven during the last update
+    #[serde(default)]
+    current_speed: f32,
+    // The world space distance to the vehicle ahead on the same edge
+    #[serde(default)]
+    leader_gap: Option<f32>,
+    // The node the vehicle is waiting to be given a reservation for
+    #[serde(default)]
+    waiting_for_node: Option<usize>,
+    // The line and stops served by a bus, which follows the line as its path
+    #[serde(default)]
+    transit_trip: Option<TransitTrip>,
+    // Whether the vehicle is pulled over for an emergency vehicle behind it
+    #[serde(default)]
+    is_yielding: bool,
+    // Pulling into and out of parking spaces, and looking for one
+    #[serde(default)]
+    parking_trip: Option<ParkingTrip>,
 }
 
 impl Vehicle {
-    fn new(id: usize, path: Vec<usize>) -> Self {
+    pub fn new(
+        id: usize,
+        vehicle_type: VehicleType,
+        path: Vec<usize>,
+        spawn_time: f32,
+        rng: &mut impl Rng,
+    ) -> Self {
         Vehicle {
             id,
+            speed: vehicle_type.random_speed(rng),
+            vehicle_type,
             path,
             path_index: 0,
             edge_position: 0.,
-            speed: MIN_SPEED + (MAX_SPEED - MIN_SPEED) * rand::random::<f32>(),
+            spawn_time,
+            distance_traveled: 0.,
+            stopped_time: 0.,
+            stop_count: 0,
+            is_stopped: false,
+            node_times: vec![spawn_time],
+            current_speed: 0.,
+            leader_gap: None,
+            waiting_for_node: None,
+            transit_trip: None,
+            is_yielding: false,
+            parking_trip: None,
         }
     }
 
+    pub fn with_transit_trip(mut self, transit_trip: TransitTrip) -> Self {
+        self.transit_trip = Some(transit_trip);
+        self
+    }
+
+    pub fn transit_trip(&self) -> Option<&TransitTrip> {
+        self.transit_trip.as_ref()
+    }
+
+    // Starts the vehicle part way along its first edge, where it leaves or
+    // heads to a parking space
+    pub fn with_parking_trip(mut self, parking_trip: ParkingTrip, edge_position: f32) -> Self {
+        self.parking_trip = Some(parking_trip);
+        self.edge_position = edge_position;
+        self
+    }
+
+    pub fn parking_trip(&self) -> Option<&ParkingTrip> {
+        self.parking_trip.as_ref()
+    }
+
+    fn is_maneuvering(&self) -> bool {
+        self.parking_trip
+            .as_ref()
+            .is_some_and(ParkingTrip::is_maneuvering)
+    }
+
+    fn is_dwelling(&self) -> bool {
+        self.transit_trip
+            .as_ref()
+            .is_some_and(TransitTrip::is_dwelling)
+    }
+
+    pub fn is_emergency(&self) -> bool {
+        self.vehicle_type.is_emergency
+    }
+
+    pub fn is_yielding(&self) -> bool {
+        self.is_yielding
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn vehicle_type(&self) -> &VehicleType {
+        &self.vehicle_type
+    }
+
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+
+    pub fn path_index(&self) -> usize {
+        self.path_index
+    }
+
+    pub fn edge_position(&self) -> f32 {
+        self.edge_position
+    }
+
+    // The speed the vehicle drives at when nothing is in its way
+    pub fn desired_speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn current_speed(&self) -> f32 {
+        self.current_speed
+    }
+
+    pub fn leader_gap(&self) -> Option<f32> {
+        self.leader_gap
+    }
+
+    pub fn waiting_for_node(&self) -> Option<usize> {
+        self.waiting_for_node
+    }
+
+    // Checks that the vehicle's path fits the given graph, so the getters
+    // below won't panic
+    pub fn is_valid_for(&self, node_graph: &NodeGraph) -> bool {
+        self.path_index < self.path.len()
+            && self.path.iter().all(|node| *node < node_graph.nodes.len())
+    }
+
+    // Starts the trip when a queued vehicle is let into the network, so
+    // travel times don't include the time spent waiting to enter
+    pub fn enter_network(&mut self, time: f32) {
+        self.spawn_time = time;
+        self.node_times = vec![time];
+    }
+
+    // Checks whether the vehicle fits where it starts on its first edge, clear
+    // of the vehicles already driving along it
+    pub fn has_room_to_enter<'a>(
+        &self,
+        vehicles: impl Iterator<Item = &'a Vehicle>,
+        node_graph: &NodeGraph,
+    ) -> bool {
+        let first_edge = (
+            self.path[0],
+            self.path.get(1).copied().unwrap_or(self.path[0]),
+        );
+        has_room_at(
+            vehicles,
+            node_graph,
+            first_edge,
+            self.edge_position,
+            self.vehicle_type.length,
+        )
+    }
+
     // These getter functions will panic if the vehicle is in a malformed state or
     // if the node graph is mutated
     fn get_current_node<'a>(&self, node_graph: &'a NodeGraph) -> &'a Node {
@@ -67,14 +280,14 @@ impl Vehicle {
         Some(self.path[self.path_index + 1])
     }
 
-    fn get_edge(&self) -> Option<(usize, usize)> {
+    pub fn get_edge(&self) -> Option<(usize, usize)> {
         let next_node = self.get_next_node_index()?;
         Some((self.get_current_node_index(), next_node))
     }
 
     // Gets the world position of the vehicle by interpolating between the
     // positions of the current and next nodes
-    fn get_world_position(&self, node_graph: &NodeGraph) -> Vec3 {
+    pub fn get_world_position(&self, node_graph: &NodeGraph) -> Vec3 {
         let current_node_pos = self.get_current_node(node_graph).position;
         let Some(next_node) = self.get_next_node(node_graph) else {
             // If there is no next node, the position will just be the current(last) node.
@@ -83,23 +296,95 @@ impl Vehicle {
         current_node_pos + (next_node.position - current_node_pos) * self.edge_position
     }
 
-    // Gets the distance in edge space to the next vehicle on the current edge.
-    // Returns None if there are no vehicles in front of the vehicle.
+    // The world space distance left to drive to the next node, None at the end
+    // of the path
+    fn get_distance_to_next_node(&self, node_graph: &NodeGraph) -> Option<f32> {
+        let next_node = self.get_next_node(node_graph)?;
+        let edge_length = self
+            .get_current_node(node_graph)
+            .position
+            .distance(next_node.position);
+        Some((1. - self.edge_position) * edge_length)
+    }
+
+    // Checks whether an emergency vehicle is close behind on the same edge.
+    // Vehicles holding their next node keep going so they clear it.
+    fn should_yield(
+        &self,
+        emergency_positions: &HashMap<(usize, usize), Vec<f32>>,
+        node_graph: &NodeGraph,
+    ) -> bool {
+        if self.is_emergency() {
+            return false;
+        }
+        let Some(edge) = self.get_edge() else {
+            return false;
+        };
+        if node_graph.node_reservation_map.get(&edge.1) == Some(&self.id) {
+            return false;
+        }
+        let edge_length = node_graph.nodes[edge.0]
+            .position
+            .distance(node_graph.nodes[edge.1].position);
+        emergency_positions.get(&edge).is_some_and(|positions| {
+            positions.iter().any(|position| {
+                let distance = (self.edge_position - position) * edge_length;
+                distance > 0. && distance <= YIELD_DISTANCE
+            })
+        })
+    }
+
+    // Gets the position the vehicle is drawn at. Buses at a stop pull over to
+    // the curb lane so traffic can pass, vehicles yielding to an emergency
+    // vehicle pull right up to the curb and vehicles parking are half way
+    // into their space.
+    pub fn get_display_position(&self, node_graph: &NodeGraph) -> Vec3 {
+        let position = self.get_world_position(node_graph);
+        let Some(edge) = self.get_edge() else {
+            return position;
+        };
+        let lanes = node_graph.lane_count(edge) as f32;
+        let curb_offset = if self.is_yielding {
+            (lanes * LANE_WIDTH - self.vehicle_type.width) / 2.
+        } else if self.is_maneuvering() {
+            lanes * LANE_WIDTH / 2.
+        } else if self.is_dwelling() {
+            (lanes - 1.) * LANE_WIDTH / 2.
+        } else {
+            0.
+        };
+        let direction = node_graph.nodes[edge.1].position - node_graph.nodes[edge.0].position;
+        let right = direction.cross(Vec3::Y).normalize_or_zero();
+        position + right * curb_offset
+    }
+
+    // Gets the distance in edge space to the next vehicle on the current edge,
+    // along with the length of that vehicle. Returns None if there are no
+    // vehicles in front of the vehicle. Buses stopped at a stop are passed
+    // when there is more than one lane, and emergency vehicles pass vehicles
+    // which have pulled over for them.
     fn get_next_vehicle_edge_distance(
         &self,
-        vehicle_map: &HashMap<(usize, usize), Vec<f32>>,
-    ) -> Option<f32> {
+        vehicle_map: &HashMap<(usize, usize), Vec<VehicleOnEdge>>,
+        can_pass_stopped_buses: bool,
+    ) -> Option<(f32, f32)> {
         let edge = self.get_edge()?;
         let vehicles_on_edge = vehicle_map.get(&edge)?;
-        let mut closest_vehicle = None;
-        for edge_position in vehicles_on_edge {
-            let vehicle_distance = edge_position - self.edge_position;
+        let mut closest_vehicle: Option<(f32, f32)> = None;
+        for vehicle in vehicles_on_edge {
+            let vehicle_distance = vehicle.edge_position - self.edge_position;
             // Ignore self and trailing vehicles
             if vehicle_distance <= 0. {
                 continue;
             }
-            if closest_vehicle.is_none() || vehicle_distance < closest_vehicle? {
-                closest_vehicle = Some(vehicle_distance);
+            if vehicle.is_dwelling && can_pass_stopped_buses {
+                continue;
+            }
+            if vehicle.is_yielding && self.is_emergency() {
+                continue;
+            }
+            if closest_vehicle.is_none() || vehicle_distance < closest_vehicle?.0 {
+                closest_vehicle = Some((vehicle_distance, vehicle.length));
             }
         }
         closest_vehicle
@@ -108,12 +393,7 @@ impl Vehicle {
     // Attempts to drive along the current edge by a given world space distance.
     // If the vehicle hits the end of the edge, the path will be incremented and
     // the remaining distance will be returned.
-    fn drive_edge(
-        &mut self,
-        distance: f32,
-        node_graph: &mut NodeGraph,
-        vehicle_map: &HashMap<(usize, usize), Vec<f32>>,
-    ) -> f32 {
+    fn drive_edge(&mut self, distance: f32, node_graph: &mut NodeGraph, traffic: &Traffic) -> f32 {
         // Calculate the parameterized speed of the vehicle along the edge
         // by querying the current and next nodes
         let current_node = self.get_current_node(node_graph);
@@ -125,35 +405,84 @@ impl Vehicle {
         let edge_length = edge_vector.length();
         let mut edge_move_amount = distance / edge_length;
 
-        // Clamp move amount to not pass the next vehicle
-        if let Some(next_vehicle_distance) = self.get_next_vehicle_edge_distance(vehicle_map) {
-            let follow_distance = 0.7;
+        // Clamp move amount to not pass the next vehicle, keeping a gap
+        // between the two vehicles' bumpers. A vehicle which has just been
+        // passed may be too close already, so it waits rather than reversing.
+        let lanes = self
+            .get_edge()
+            .map_or(1, |edge| node_graph.lane_count(edge));
+        let next_vehicle = self.get_next_vehicle_edge_distance(&traffic.vehicle_map, lanes > 1);
+        self.leader_gap = next_vehicle.map(|(distance, _)| distance * edge_length);
+        if let Some((next_vehicle_distance, next_vehicle_length)) = next_vehicle {
+            let follow_distance = (self.vehicle_type.length + next_vehicle_length) / 2. + MIN_GAP;
             let edge_follow_distance = follow_distance / edge_length;
             let follow_point = next_vehicle_distance - edge_follow_distance;
             if follow_point < edge_move_amount {
-                edge_move_amount = follow_point;
+                edge_move_amount = follow_point.max(0.);
+            }
+        }
+
+        // Stop before crosswalks pedestrians are using. Vehicles already on
+        // one carry on across it.
+        let crosswalk_starts = self
+            .get_edge()
+            .and_then(|edge| traffic.blocked_crosswalks.get(&edge));
+        let stop_distance = (self.vehicle_type.length / 2. + MIN_GAP) / edge_length;
+        for crosswalk_start in crosswalk_starts.into_iter().flatten() {
+            let stop_point = crosswalk_start - stop_distance;
+            if self.edge_position <= stop_point + f32::EPSILON {
+                edge_move_amount = edge_move_amount.min((stop_point - self.edge_position).max(0.));
+            }
+        }
+
+        // Buses pull up at the next stop on their line
+        let stop_position = self
+            .transit_trip
+            .as_ref()
+            .and_then(|transit_trip| transit_trip.stop_position(self.path_index));
+        if let Some(stop_position) = stop_position {
+            if self.edge_position <= stop_position
+                && self.edge_position + edge_move_amount >= stop_position
+            {
+                edge_move_amount = stop_position - self.edge_position;
+                if let Some(transit_trip) = self.transit_trip.as_mut() {
+                    transit_trip.arrive();
+                }
+            }
+        }
+
+        // Vehicles heading for a parking area pull up at its entrance
+        let target_position = self.parking_trip.as_ref().and_then(|parking_trip| {
+            parking_trip.target_position(self.path_index, self.path.len())
+        });
+        if let Some(target_position) = target_position {
+            if self.edge_position <= target_position
+                && self.edge_position + edge_move_amount >= target_position
+            {
+                edge_move_amount = target_position - self.edge_position;
+                if let Some(parking_trip) = self.parking_trip.as_mut() {
+                    parking_trip.reached_target = true;
+                }
             }
         }
 
         let new_edge_position = self.edge_position + edge_move_amount;
 
-        // The distance a vehicle should stay back from a node when waiting
-        // Note: make sure this smaller than (min dist between connected nodes along a bidirectional edge / 2)
-        let node_buffer = 0.9;
-        let edge_buffer = node_buffer / edge_length;
+        let edge_buffer = NODE_BUFFER / edge_length;
 
         self.try_clear_node_reservation(edge_buffer, node_graph);
-        if self.should_wait_at_node(edge_buffer, new_edge_position, node_graph) {
+        if self.should_wait_at_node(edge_buffer, new_edge_position, node_graph, traffic) {
             // move vehicle as close to node as possible and wait for reservation
-            self.edge_position = 1.0 - edge_buffer;
+            self.waiting_for_node = self.get_next_node_index();
+            self.set_edge_position(1.0 - edge_buffer, edge_length);
             return 0.;
         }
 
         // Move the vehicle along the edge. If we go past the end of the
         // edge, increment to the next edge.
-        self.edge_position = new_edge_position;
-        if self.edge_position > 1. {
-            let overshoot = self.edge_position - 1.;
+        self.set_edge_position(new_edge_position.min(1.), edge_length);
+        if new_edge_position > 1. {
+            let overshoot = new_edge_position - 1.;
             self.path_index += 1;
             self.edge_position = 0.;
             return overshoot * edge_vector.length();
@@ -161,11 +490,137 @@ impl Vehicle {
         0.
     }
 
+    // Replaces the rest of the path after the current edge with a route from
+    // the end of the edge
+    fn reroute(&mut self, route: Vec<usize>) {
+        self.path.truncate(self.path_index + 2);
+        self.path.extend(route.into_iter().skip(1));
+    }
+
+    // Takes a space at the parking area the vehicle has pulled up at, or goes
+    // looking for one elsewhere if it is full. Vehicles which give up drive to
+    // the closest destination node instead.
+    fn arrive_at_parking_area(
+        &mut self,
+        parking_areas: &mut ParkingAreas,
+        parking_log: &mut ParkingLog,
+        node_graph: &NodeGraph,
+        time: f32,
+    ) {
+        let (Some(edge), Some(parking_trip)) = (self.get_edge(), self.parking_trip.as_mut()) else {
+            return;
+        };
+        let Some((area_index, _)) = parking_trip.target.filter(|_| parking_trip.reached_target)
+        else {
+            return;
+        };
+        let maneuver_time = parking_areas.settings.maneuver_time;
+        let Some(area) = parking_areas.areas.get_mut(area_index) else {
+            parking_trip.target = None;
+            return;
+        };
+
+        let (cruising_time, cruising_distance) =
+            parking_trip
+                .search_start
+                .map_or((0., 0.), |(start_time, start_distance)| {
+                    (time - start_time, self.distance_traveled - start_distance)
+                });
+        if area.has_space() {
+            area.park(self.vehicle_type.clone());
+            parking_trip.park(maneuver_time);
+            parking_log.searches.push(ParkingSearch {
+                vehicle_id: self.id,
+                area: Some(area.name.clone()),
+                areas_tried: parking_trip.areas_tried + 1,
+                cruising_time,
+                cruising_distance,
+            });
+            return;
+        }
+
+        area.turned_away += 1;
+        let next_area = parking_areas.next_area(node_graph, edge.1, area_index);
+        parking_trip.search(
+            next_area
+                .as_ref()
+                .map(|(index, _)| (*index, parking_areas.areas[*index].position)),
+            time,
+            self.distance_traveled,
+        );
+        if parking_trip.target.is_some() {
+            if let Some((_, route)) = next_area {
+                self.reroute(route);
+            }
+            return;
+        }
+
+        parking_log.searches.push(ParkingSearch {
+            vehicle_id: self.id,
+            area: None,
+            areas_tried: parking_trip.areas_tried,
+            cruising_time,
+            cruising_distance,
+        });
+        let exit_route = node_graph
+            .dest_nodes
+            .iter()
+            .filter_map(|dest_node| node_graph.find_path(edge.1, *dest_node))
+            .min_by_key(|route| (route.len(), route.last().copied()));
+        if let Some(exit_route) = exit_route {
+            self.reroute(exit_route);
+        }
+    }
+
+    // Moves the vehicle to a new position along the current edge while
+    // keeping track of the total distance driven
+    fn set_edge_position(&mut self, edge_position: f32, edge_length: f32) {
+        self.distance_traveled += (edge_position - self.edge_position).max(0.) * edge_length;
+        self.edge_position = edge_position;
+    }
+
+    // Updates the stop statistics of the vehicle based on the distance it
+    // moved during the last update
+    fn update_stopped_state(&mut self, distance_moved: f32, delta_seconds: f32) {
+        let is_stopped = distance_moved < STOPPED_SPEED * delta_seconds;
+        if is_stopped {
+            self.stopped_time += delta_seconds;
+            if !self.is_stopped {
+                self.stop_count += 1;
+            }
+        }
+        self.is_stopped = is_stopped;
+    }
+
+    // The trip driven so far by a vehicle which hasn't arrived yet, as if it
+    // ended now
+    pub fn trip_so_far(&self, now: f32) -> TripRecord {
+        self.to_trip_record(now)
+    }
+
+    fn to_trip_record(&self, arrival_time: f32) -> TripRecord {
+        TripRecord {
+            vehicle_id: self.id,
+            source_node: self.path[0],
+            dest_node: self.path[self.path.len() - 1],
+            route: self.path.clone(),
+            spawn_time: self.spawn_time,
+            arrival_time,
+            distance: self.distance_traveled,
+            stopped_time: self.stopped_time,
+            stop_count: self.stop_count,
+            node_times: self.node_times.clone(),
+            free_flow_speed: self.speed,
+            vehicle_type: self.vehicle_type.name.clone(),
+        }
+    }
+
     fn should_wait_at_node(
         &self,
         edge_buffer: f32,
         new_edge_position: f32,
         node_graph: &mut NodeGraph,
+        traffic: &Traffic,
     ) -> bool {
         // don't wait if there is no next node
         let Some(next_node_index) = self.get_next_node_index() else {
@@ -183,10 +638,24 @@ impl Vehicle {
             return false;
         }
 
-        // get the vehicle id which reserved the node
-        if let Some(vehicle_id_with_reservation) =
-            node_graph.node_reservation_map.get(&next_node_index)
+        // keep going if we already hold the node
+        let reservation = node_graph.node_reservation_map.get(&next_node_index);
+        if reservation == Some(&self.id) {
+            return false;
+        }
+
+        // nodes held for an emergency vehicle aren't given to anyone else, but
+        // vehicles which already hold them can still clear them
+        if traffic
+            .preempted_nodes
+            .get(&next_node_index)
+            .is_some_and(|emergency_vehicle_id| *emergency_vehicle_id != self.id)
         {
+            return true;
+        }
+
+        // get the vehicle id which reserved the node
+        if let Some(vehicle_id_with_reservation) = reservation {
             // TODO: update this to allow following cars through intersections
             // this can be accomplished by checking the direction of the car with
             // the reservation and if it is the same then overwrite the reservation
@@ -194,6 +663,17 @@ impl Vehicle {
 
             // stop driving if this node is reserved by another vehicle
             self.id != *vehicle_id_with_reservation
+        } else if !self.has_room_past_next_node(node_graph, &traffic.vehicle_map) {
+            // don't take the node without room to clear it, a vehicle stopped
+            // inside a junction holds it and can lock up a loop of them
+            true
+        } else if self.path_index == 0
+            && !self.is_emergency()
+            && self.must_give_way(node_graph, &traffic.vehicle_map)
+        {
+            // let traffic already on the network through first, otherwise
+            // entries can fill a loop such as a roundabout until it locks up
+            true
         } else {
             // there's no reservation, reserve it
             node_graph
@@ -203,6 +683,64 @@ impl Vehicle {
         }
     }
 
+    // Checks whether the vehicle could stop on the edge after its next node
+    // far enough along to have given the node up, behind the vehicles already
+    // on that edge
+    fn has_room_past_next_node(
+        &self,
+        node_graph: &NodeGraph,
+        vehicle_map: &HashMap<(usize, usize), Vec<VehicleOnEdge>>,
+    ) -> bool {
+        let (Some(next_node), Some(node_after)) = (
+            self.path.get(self.path_index + 1),
+            self.path.get(self.path_index + 2),
+        ) else {
+            return true;
+        };
+        let edge = (*next_node, *node_after);
+        let edge_length = node_graph.nodes[edge.0]
+            .position
+            .distance(node_graph.nodes[edge.1].position);
+        let can_pass_stopped_buses = node_graph.lane_count(edge) > 1;
+        vehicle_map
+            .get(&edge)
+            .into_iter()
+            .flatten()
+            .filter(|vehicle| !(vehicle.is_dwelling && can_pass_stopped_buses))
+            .all(|vehicle| {
+                let follow_distance = (self.vehicle_type.length + vehicle.length) / 2. + MIN_GAP;
+                vehicle.edge_position * edge_length - follow_distance >= NODE_BUFFER
+            })
+    }
+
+    // Checks whether a vehicle which didn't enter at a source is approaching
+    // the next node on another edge
+    fn must_give_way(
+        &self,
+        node_graph: &NodeGraph,
+        vehicle_map: &HashMap<(usize, usize), Vec<VehicleOnEdge>>,
+    ) -> bool {
+        let Some(next_node) = self.get_next_node_index() else {
+            return false;
+        };
+        let current_edge = self.get_edge();
+        vehicle_map
+            .iter()
+            .filter(|(edge, _)| {
+                edge.1 == next_node
+                    && Some(**edge) != current_edge
+                    && !node_graph.source_nodes.contains(&edge.0)
+            })
+            .any(|(edge, vehicles)| {
+                let edge_length = node_graph.nodes[edge.0]
+                    .position
+                    .distance(node_graph.nodes[edge.1].position);
+                vehicles
+                    .iter()
+                    .any(|vehicle| (1. - vehicle.edge_position) * edge_length < GIVE_WAY_DISTANCE)
+            })
+    }
+
     fn try_clear_node_reservation(&self, edge_buffer: f32, node_graph: &mut NodeGraph) {
         // check if we are outside the reservation range of the current node
         let current_node_index = self.get_current_node_index();
@@ -224,113 +762,627 @@ impl Vehicle {
     }
 
     // Drives along the vehicles node path by a specified world space distance
-    fn drive(
-        &mut self,
-        distance: f32,
-        node_graph: &mut NodeGraph,
-        vehicle_map: &HashMap<(usize, usize), Vec<f32>>,
-    ) {
+    fn drive(&mut self, distance: f32, node_graph: &mut NodeGraph, traffic: &Traffic) {
+        self.waiting_for_node = None;
         let mut remaining_distance = distance;
         while remaining_distance > 0. {
-            remaining_distance = self.drive_edge(remaining_distance, node_graph, vehicle_map);
+            remaining_distance = self.drive_edge(remaining_distance, node_graph, traffic);
+        }
+    }
+}
+
+// Checks whether a vehicle of the given length fits at a position along an
+// edge without overlapping the vehicles on it
+pub fn has_room_at<'a>(
+    vehicles: impl Iterator<Item = &'a Vehicle>,
+    node_graph: &NodeGraph,
+    edge: (usize, usize),
+    edge_position: f32,
+    length: f32,
+) -> bool {
+    let edge_length = node_graph.nodes[edge.0]
+        .position
+        .distance(node_graph.nodes[edge.1].position);
+    vehicles
+        .filter(|vehicle| vehicle.get_edge() == Some(edge))
+        .all(|vehicle| {
+            let follow_distance = (length + vehicle.vehicle_type.length) / 2. + MIN_GAP;
+            (vehicle.edge_position - edge_position).abs() * edge_length >= follow_distance
+        })
+}
+
+// Creates a vehicle with a random route, parking at an area on the way for
+// some. Returns None if the network doesn't have any routes.
+fn create_vehicle(
+    node_graph: &NodeGraph,
+    vehicle_id_generator: &mut VehicleIdGenerator,
+    rng: &mut impl Rng,
+    spawn_time: f32,
+    vehicle_types: &VehicleTypes,
+    parking_areas: &ParkingAreas,
+) -> Option<Vehicle> {
+    // Choose random source and destination nodes. Networks without any routes
+    // are reported by validation, so there is nothing to spawn here. Routes
+    // are sorted so the choice only depends on the state of the rng.
+    let mut routes: Vec<&(usize, usize)> = node_graph.shortest_path_map.keys().collect();
+    routes.sort();
+    let (source_node, dest_node) = routes.choose(rng).copied()?;
+    let mut node_path = node_graph.shortest_path_map[&(*source_node, *dest_node)].clone();
+
+    // Some vehicles drive to a parking area instead of leaving the network.
+    // The rng is only used when there are areas so runs without are unchanged.
+    let mut parking_trip = None;
+    if !parking_areas.areas.is_empty() && rng.gen::<f32>() < parking_areas.settings.parking_share {
+        let area_index = rng.gen_range(0..parking_areas.areas.len());
+        let area = &parking_areas.areas[area_index];
+        if let Some(route) = parking::route_to_area(node_graph, *source_node, area) {
+            node_path = route;
+            parking_trip = Some(ParkingTrip::to_area(area_index, area));
+        }
+    }
+
+    let vehicle_type = vehicle_types.choose(rng);
+    let mut vehicle = Vehicle::new(
+        vehicle_id_generator.get_id(),
+        vehicle_type,
+        node_path,
+        spawn_time,
+        rng,
+    );
+    if let Some(parking_trip) = parking_trip {
+        vehicle = vehicle.with_parking_trip(parking_trip, 0.);
+    }
+    Some(vehicle)
+}
+
+// Spawns a vehicle whenever the spawn interval has passed. New vehicles wait
+// in a queue at their source node until the start of their first edge is
+// clear, so they never appear on top of a vehicle that hasn't driven away
+// yet. Returns the vehicles entering the network.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_vehicles(
+    node_graph: &NodeGraph,
+    vehicles: &[&Vehicle],
+    spawn_limiter: &mut VehicleSpawnLimiter,
+    vehicle_id_generator: &mut VehicleIdGenerator,
+    rng: &mut impl Rng,
+    clock: &SimulationClock,
+    vehicle_types: &VehicleTypes,
+    parking_areas: &ParkingAreas,
+    entry_queues: &mut EntryQueues,
+) -> Vec<Vehicle> {
+    // Only allow vehicle spawning at certain intervals
+    if spawn_limiter.try_spawn(clock.elapsed()) {
+        let vehicle = create_vehicle(
+            node_graph,
+            vehicle_id_generator,
+            rng,
+            clock.elapsed_seconds(),
+            vehicle_types,
+            parking_areas,
+        );
+        if let Some(vehicle) = vehicle {
+            entry_queues.push(vehicle, clock.elapsed_seconds());
         }
     }
+
+    let entering_vehicles = entry_queues.release(clock.elapsed_seconds(), |vehicle| {
+        vehicle.has_room_to_enter(vehicles.iter().copied(), node_graph)
+    });
+    entry_queues.record_lengths(clock.delta_seconds());
+    entering_vehicles
 }
 
+// Spawns an entity for each vehicle entering the network
+#[cfg(feature = "bevy")]
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_vehicle(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     node_graph: Res<NodeGraph>,
     mut node_graph_renderer: ResMut<NodeGraphRenderer>,
     mut spawn_limiter: ResMut<VehicleSpawnLimiter>,
     mut vehicle_id_generator: ResMut<VehicleIdGenerator>,
+    mut rng: ResMut<SimulationRng>,
+    clock: Res<SimulationClock>,
+    vehicle_types: Res<VehicleTypes>,
+    parking_areas: Res<ParkingAreas>,
+    mut entry_queues: ResMut<EntryQueues>,
+    vehicle_query: Query<&Vehicle>,
 ) {
-    // Only allow vehicle spawning at certain intervals
-    if !spawn_limiter.try_spawn() {
-        return;
-    }
-
-    // Choose random source and destination nodes
-    let mut rng = rand::thread_rng();
-    let ((source_node, dest_node), node_path) = node_graph
-        .shortest_path_map
-        .iter()
-        .choose(&mut rng)
-        .expect("No path found");
-
-    let vehicle_id = vehicle_id_generator.get_id();
-
-    // Highlight this vehicle if there is no current highlight
-    let highlight_vehicle = node_graph_renderer.highlighted_vehicle_id.is_none();
-    let vehicle_color: Color;
-    if highlight_vehicle {
-        node_graph_renderer.highlighted_vehicle_id = Some(vehicle_id);
-        node_graph_renderer.highlighted_path_index = Some((*source_node, *dest_node));
-        vehicle_color = Color::srgb(1., 1., 0.);
+    let vehicles: Vec<&Vehicle> = vehicle_query.iter().collect();
+    let entering_vehicles = spawn_vehicles(
+        &node_graph,
+        &vehicles,
+        &mut spawn_limiter,
+        &mut vehicle_id_generator,
+        &mut rng.0,
+        &clock,
+        &vehicle_types,
+        &parking_areas,
+        &mut entry_queues,
+    );
+    for vehicle in entering_vehicles {
+        // Highlight this vehicle if there is no current highlight and one
+        // hasn't been picked
+        if node_graph_renderer.highlighted_vehicle_id.is_none()
+            && !node_graph_renderer.vehicle_selected_by_user
+        {
+            node_graph_renderer.highlighted_vehicle_id = Some(vehicle.id);
+            node_graph_renderer.highlighted_path_index =
+                Some((vehicle.path[0], vehicle.path[vehicle.path.len() - 1]));
+        }
+
+        // Spawn the vehicle entity at the correct position.
+        // If we don't get the position here, the entity will be displayed
+        // at the center of the scene for a frame.
+        let start_node_position = node_graph.nodes[vehicle.path[0]].position;
+        commands.spawn((
+            SpatialBundle::from_transform(Transform::from_translation(start_node_position)),
+            vehicle,
+        ));
+    }
+}
+
+// Creates the mesh and material a vehicle of the given type is drawn with
+#[cfg(feature = "bevy")]
+pub fn vehicle_mesh(
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    vehicle_type: &VehicleType,
+    is_highlighted: bool,
+) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+    let vehicle_color = if is_highlighted {
+        Color::srgb(1., 1., 0.)
     } else {
-        vehicle_color = Color::srgb(0.3, 0.3, 0.5);
-    }
-
-    // Spawn the vehicle entity at the correct position.
-    // If we don't get the position here, the entity will be displayed
-    // at the center of the scene for a frame.
-    let start_node_position = node_graph.nodes.get(*source_node).unwrap().position;
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(Cuboid::new(0.3, 0.2, 0.5).mesh()),
-            material: materials.add(vehicle_color),
-            transform: Transform::from_translation(start_node_position),
-            ..default()
-        },
-        Vehicle::new(vehicle_id, node_path.clone()),
-    ));
+        vehicle_type.color()
+    };
+    let size = Cuboid::new(vehicle_type.width, vehicle_type.height, vehicle_type.length);
+    (meshes.add(size.mesh()), materials.add(vehicle_color))
 }
 
-pub fn move_vehicles(
+// Gives newly spawned vehicles a mesh so they can be seen. This is kept
+// separate from spawning so that the simulation can run without rendering.
+#[cfg(feature = "bevy")]
+pub fn attach_vehicle_meshes(
     mut commands: Commands,
-    mut vehicle_query: Query<(Entity, &mut Transform, &mut Vehicle)>,
-    mut node_graph: ResMut<NodeGraph>,
-    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
-    time: Res<Time>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    node_graph_renderer: Res<NodeGraphRenderer>,
+    vehicle_query: Query<(Entity, &Vehicle), Added<Vehicle>>,
 ) {
-    // Build a map to communicate vehicle positions between vehicles
-    let mut vehicle_map: HashMap<(usize, usize), Vec<f32>> = HashMap::new();
-    for (_, _, vehicle) in &mut vehicle_query {
-        let Some(edge) = vehicle.get_edge() else {
+    for (entity, vehicle) in &vehicle_query {
+        let is_highlighted = node_graph_renderer.highlighted_vehicle_id == Some(vehicle.id);
+        commands.entity(entity).insert(vehicle_mesh(
+            &mut meshes,
+            &mut materials,
+            &vehicle.vehicle_type,
+            is_highlighted,
+        ));
+    }
+}
+
+// Moves every vehicle along its path for one update, measuring the traffic
+// as it goes. Returns the indices of the vehicles which have left the
+// network, either at the end of their path or into a parking space.
+#[allow(clippy::too_many_arguments)]
+pub fn drive_vehicles(
+    vehicles: &mut [&mut Vehicle],
+    node_graph: &mut NodeGraph,
+    clock: &SimulationClock,
+    emergency_policy: &EmergencyPolicy,
+    crosswalks: &[&Crosswalk],
+    parking_areas: &mut ParkingAreas,
+    trip_log: &mut TripLog,
+    transit_log: &mut TransitLog,
+    parking_log: &mut ParkingLog,
+    edge_metrics: &mut EdgeMetrics,
+) -> Vec<usize> {
+    // Find where emergency vehicles are, and preempt the nodes they are
+    // about to reach. The closest wins when several want the same node, since
+    // the others may be queued behind it.
+    let mut traffic = Traffic::default();
+    let mut emergency_positions: HashMap<(usize, usize), Vec<f32>> = HashMap::new();
+    let mut closest_emergency_vehicles: HashMap<usize, (f32, usize)> = HashMap::new();
+    for vehicle in vehicles.iter() {
+        let Some((edge, distance_to_next_node)) = vehicle
+            .get_edge()
+            .zip(vehicle.get_distance_to_next_node(node_graph))
+        else {
             continue;
         };
-        vehicle_map
+        if !vehicle.is_emergency() {
+            continue;
+        }
+        emergency_positions
             .entry(edge)
             .or_default()
             .push(vehicle.edge_position);
+        if emergency_policy.preemption && distance_to_next_node <= PREEMPTION_DISTANCE {
+            let candidate = (distance_to_next_node, vehicle.id);
+            let closest = closest_emergency_vehicles
+                .entry(edge.1)
+                .or_insert(candidate);
+            if candidate < *closest {
+                *closest = candidate;
+            }
+        }
+    }
+    traffic.preempted_nodes = closest_emergency_vehicles
+        .into_iter()
+        .map(|(node, (_, emergency_vehicle_id))| (node, emergency_vehicle_id))
+        .collect();
+    for crosswalk in crosswalks {
+        if crosswalk.is_blocked(clock.elapsed_seconds()) {
+            let (start, _) = crosswalk.extent(node_graph);
+            traffic
+                .blocked_crosswalks
+                .entry(crosswalk.edge)
+                .or_default()
+                .push(start);
+        }
+    }
+
+    // Build a map to communicate vehicle positions and lengths between
+    // vehicles, pulling over for emergency vehicles first
+    for vehicle in vehicles.iter_mut() {
+        let is_yielding =
+            emergency_policy.yielding && vehicle.should_yield(&emergency_positions, node_graph);
+        vehicle.is_yielding = is_yielding;
+        let Some(edge) = vehicle.get_edge() else {
+            continue;
+        };
+        traffic
+            .vehicle_map
+            .entry(edge)
+            .or_default()
+            .push(VehicleOnEdge {
+                edge_position: vehicle.edge_position,
+                length: vehicle.vehicle_type.length,
+                is_dwelling: vehicle.is_dwelling(),
+                is_yielding,
+            });
     }
 
-    for (entity, mut transform, mut vehicle) in &mut vehicle_query {
-        let speed = vehicle.speed;
+    let mut left_network = Vec::new();
+    for (index, vehicle) in vehicles.iter_mut().enumerate() {
+        // Buses stay put until they have finished at their stop
+        let is_dwelling = vehicle
+            .transit_trip
+            .as_mut()
+            .is_some_and(|transit_trip| transit_trip.dwell(clock.delta_seconds()));
+        // Vehicles pulling into or out of a parking space block the lane until
+        // they are done, then parked vehicles leave the network
+        let is_maneuvering = vehicle
+            .parking_trip
+            .as_mut()
+            .is_some_and(|parking_trip| parking_trip.maneuver(clock.delta_seconds()));
+        let is_parked = vehicle
+            .parking_trip
+            .as_ref()
+            .is_some_and(ParkingTrip::is_parked);
+        // Speed up from the speed driven last update, stopped vehicles have
+        // to get going again. Vehicles keep to the speed limit of their edge.
+        let target_speed = vehicle
+            .get_edge()
+            .and_then(|edge| node_graph.speed_limit(edge))
+            .map_or(vehicle.speed, |speed_limit| vehicle.speed.min(speed_limit));
+        let speed = if is_dwelling || vehicle.is_yielding || is_maneuvering || is_parked {
+            0.
+        } else {
+            (vehicle.current_speed + vehicle.vehicle_type.acceleration * clock.delta_seconds())
+                .min(target_speed)
+        };
+        let distance_traveled = vehicle.distance_traveled;
+        let path_index = vehicle.path_index;
 
-        // Drive the given distance and update the position of the transform
-        vehicle.drive(
-            speed * time.delta_seconds(),
-            node_graph.as_mut(),
-            &vehicle_map,
+        // Drive the given distance
+        vehicle.drive(speed * clock.delta_seconds(), node_graph, &traffic);
+        vehicle.arrive_at_parking_area(
+            parking_areas,
+            parking_log,
+            node_graph,
+            clock.elapsed_seconds(),
         );
-        transform.translation = vehicle.get_world_position(&node_graph);
+        let distance_moved = vehicle.distance_traveled - distance_traveled;
+        vehicle.update_stopped_state(distance_moved, clock.delta_seconds());
 
-        // Despawn the vehicle if it's on the final node.
-        let Some(next_node) = vehicle.get_next_node(&node_graph) else {
-            // Clear the highlight if this vehicle was being highlighted
-            if let Some(highlighted_vehicle_id) = node_graph_renderer.highlighted_vehicle_id {
-                if highlighted_vehicle_id == vehicle.id {
-                    node_graph_renderer.highlighted_vehicle_id = None;
-                    node_graph_renderer.highlighted_path_index = None;
-                }
+        // Measure the edges the vehicle finished and the one it is now on
+        for index in path_index..vehicle.path_index {
+            edge_metrics.record_crossing((vehicle.path[index], vehicle.path[index + 1]));
+            vehicle.node_times.push(clock.elapsed_seconds());
+        }
+        if let Some(edge) = vehicle.get_edge() {
+            vehicle.current_speed = if clock.delta_seconds() > 0. {
+                distance_moved / clock.delta_seconds()
+            } else {
+                0.
+            };
+            edge_metrics.record_vehicle(
+                edge,
+                vehicle.current_speed,
+                vehicle.speed,
+                vehicle.is_stopped,
+            );
+        }
+        // Record buses pulling up at a stop
+        if let Some(transit_trip) = vehicle.transit_trip.as_ref() {
+            if !is_dwelling && transit_trip.is_dwelling() {
+                let stop = &transit_trip.stops[transit_trip.next_stop];
+                transit_log.arrivals.push(StopArrival {
+                    line: transit_trip.line.clone(),
+                    departure: transit_trip.departure,
+                    stop: transit_trip.next_stop,
+                    scheduled_time: stop.scheduled_time,
+                    arrival_time: clock.elapsed_seconds(),
+                });
             }
+        }
+
+        // Vehicles leave the network at the final node or once parked
+        if is_parked || vehicle.get_next_node(node_graph).is_none() {
+            trip_log.record(vehicle.to_trip_record(clock.elapsed_seconds()));
+            left_network.push(index);
+        }
+    }
+
+    edge_metrics.end_tick(clock.elapsed_seconds(), clock.delta_seconds(), node_graph);
+    left_network
+}
+
+// Drives the vehicles and moves their entities to match, despawning the ones
+// which have left the network
+#[cfg(feature = "bevy")]
+#[allow(clippy::too_many_arguments)]
+pub fn move_vehicles(
+    mut commands: Commands,
+    mut vehicle_query: Query<(Entity, &mut Transform, &mut Vehicle)>,
+    mut node_graph: ResMut<NodeGraph>,
+    mut node_graph_renderer: ResMut<NodeGraphRenderer>,
+    mut trip_log: ResMut<TripLog>,
+    mut transit_log: ResMut<TransitLog>,
+    mut edge_metrics: ResMut<EdgeMetrics>,
+    clock: Res<SimulationClock>,
+    emergency_policy: Res<EmergencyPolicy>,
+    crosswalk_query: Query<&Crosswalk>,
+    mut parking_areas: ResMut<ParkingAreas>,
+    mut parking_log: ResMut<ParkingLog>,
+) {
+    let mut entities: Vec<(Entity, Mut<Transform>, Mut<Vehicle>)> =
+        vehicle_query.iter_mut().collect();
+    let mut vehicles: Vec<&mut Vehicle> = entities
+        .iter_mut()
+        .map(|(_, _, vehicle)| vehicle.as_mut())
+        .collect();
+    let crosswalks: Vec<&Crosswalk> = crosswalk_query.iter().collect();
+    let left_network = drive_vehicles(
+        &mut vehicles,
+        &mut node_graph,
+        &clock,
+        &emergency_policy,
+        &crosswalks,
+        &mut parking_areas,
+        &mut trip_log,
+        &mut transit_log,
+        &mut parking_log,
+        &mut edge_metrics,
+    );
 
-            commands.entity(entity).despawn();
+    for (index, (entity, transform, vehicle)) in entities.iter_mut().enumerate() {
+        if left_network.contains(&index) {
+            // Clear the highlight if this vehicle was being highlighted
+            if node_graph_renderer.highlighted_vehicle_id == Some(vehicle.id) {
+                node_graph_renderer.highlighted_vehicle_id = None;
+                node_graph_renderer.highlighted_path_index = None;
+                node_graph_renderer.vehicle_selected_by_user = false;
+            }
+            commands.entity(*entity).despawn();
             continue;
+        }
+
+        transform.translation = vehicle.get_display_position(&node_graph);
+        if let Some(next_node) = vehicle.get_next_node(&node_graph) {
+            transform.look_at(next_node.position, Dir3::Y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::node_graph::EdgeAttributes;
+
+    fn vehicle_on_edge(edge_position: f32, length: f32, is_yielding: bool) -> VehicleOnEdge {
+        VehicleOnEdge {
+            edge_position,
+            length,
+            is_dwelling: false,
+            is_yielding,
+        }
+    }
+
+    // A straight road from node 0 through node 1 to node 2
+    fn straight_road() -> NodeGraph {
+        let nodes = (0..3)
+            .map(|index| Node {
+                position: Vec3::new(0., 0., index as f32 * -20.),
+            })
+            .collect();
+        NodeGraph::new(nodes, HashSet::from([(0, 1), (1, 2)]))
+    }
+
+    #[test]
+    fn following_distance_depends_on_vehicle_lengths() {
+        let nodes = vec![
+            Node {
+                position: Vec3::ZERO,
+            },
+            Node {
+                position: Vec3::new(0., 0., -20.),
+            },
+        ];
+        let mut graph = NodeGraph::new(nodes, HashSet::from([(0, 1)]));
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        // A truck halfway along the edge, seen by the car behind it
+        let truck_length = VehicleType::truck().length;
+        let traffic = Traffic {
+            vehicle_map: HashMap::from([(
+                (0, 1),
+                vec![
+                    vehicle_on_edge(0.5, truck_length, false),
+                    vehicle_on_edge(0., 0.5, false),
+                ],
+            )]),
+            ..Default::default()
+        };
+        let mut car = Vehicle::new(0, VehicleType::car(), vec![0, 1], 0., &mut rng);
+        car.drive(15., &mut graph, &traffic);
+
+        // The car stops with a gap between its front and the back of the truck
+        let follow_distance = (0.5 + truck_length) / 2. + MIN_GAP;
+        assert!((car.distance_traveled - (10. - follow_distance)).abs() < 1e-4);
+        car.drive(1., &mut graph, &traffic);
+        assert!((car.leader_gap.unwrap() - follow_distance).abs() < 1e-4);
+        assert!((car.distance_traveled - (10. - follow_distance)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn preempted_nodes_are_held_for_emergency_vehicles() {
+        let mut graph = straight_road();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let traffic = Traffic {
+            preempted_nodes: HashMap::from([(1, 5)]),
+            ..Default::default()
         };
 
-        transform.look_at(next_node.position, Dir3::Y);
+        // A car reaching node 1 waits for the emergency vehicle
+        let mut car = Vehicle::new(0, VehicleType::car(), vec![0, 1, 2], 0., &mut rng);
+        car.drive(19.5, &mut graph, &traffic);
+        assert_eq!(car.waiting_for_node, Some(1));
+        assert!(!graph.node_reservation_map.contains_key(&1));
+
+        // The emergency vehicle takes the node and drives through it
+        let mut emergency = Vehicle::new(5, VehicleType::emergency(), vec![0, 1, 2], 0., &mut rng);
+        emergency.drive(21., &mut graph, &traffic);
+        assert_eq!(emergency.path_index, 1);
+        assert_eq!(graph.node_reservation_map.get(&1), Some(&5));
+
+        // A car which already holds a preempted node still clears it
+        graph.node_reservation_map.insert(1, 0);
+        car.drive(1., &mut graph, &traffic);
+        assert_eq!(car.path_index, 1);
+    }
+
+    #[test]
+    fn vehicles_pull_over_for_emergency_vehicles_behind_them() {
+        let graph = straight_road();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let emergency_positions = HashMap::from([((0, 1), vec![0.2])]);
+
+        // Only the car close in front of the emergency vehicle yields
+        let mut car = Vehicle::new(0, VehicleType::car(), vec![0, 1, 2], 0., &mut rng);
+        car.edge_position = 0.4;
+        assert!(car.should_yield(&emergency_positions, &graph));
+        car.edge_position = 0.7;
+        assert!(!car.should_yield(&emergency_positions, &graph));
+        car.edge_position = 0.1;
+        assert!(!car.should_yield(&emergency_positions, &graph));
+
+        // The emergency vehicle passes the yielding car but not other traffic
+        let mut graph = straight_road();
+        let traffic = Traffic {
+            vehicle_map: HashMap::from([(
+                (0, 1),
+                vec![
+                    vehicle_on_edge(0.4, 0.5, true),
+                    vehicle_on_edge(0.7, 0.5, false),
+                ],
+            )]),
+            ..Default::default()
+        };
+        let mut emergency = Vehicle::new(5, VehicleType::emergency(), vec![0, 1, 2], 0., &mut rng);
+        emergency.edge_position = 0.2;
+        emergency.drive(20., &mut graph, &traffic);
+        let follow_distance = (0.6 + 0.5) / 2. + MIN_GAP;
+        assert!((emergency.edge_position * 20. - (14. - follow_distance)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn vehicles_keep_to_speed_limits() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let mut graph = straight_road();
+        graph.edge_attributes.insert(
+            (0, 1),
+            EdgeAttributes {
+                speed_limit: Some(2.),
+                lanes: None,
+            },
+        );
+        let mut car = Vehicle::new(0, VehicleType::car(), vec![0, 1, 2], 0., &mut rng);
+        assert!(car.speed > 2.);
+
+        let mut clock = SimulationClock::default();
+        let mut drive = |car: &mut Vehicle, graph: &mut NodeGraph| {
+            clock.tick(Duration::from_millis(50));
+            drive_vehicles(
+                &mut [car],
+                graph,
+                &clock,
+                &EmergencyPolicy::default(),
+                &[],
+                &mut ParkingAreas::default(),
+                &mut TripLog::default(),
+                &mut TransitLog::default(),
+                &mut ParkingLog::default(),
+                &mut EdgeMetrics::default(),
+            );
+        };
+
+        // The car is held to the limit along the slow edge, then speeds up
+        // to its own speed on the next one
+        for _ in 0..60 {
+            drive(&mut car, &mut graph);
+            if car.path_index == 0 {
+                assert!(car.current_speed <= 2. + 1e-4);
+            }
+        }
+        assert_eq!(car.path_index, 0);
+        assert!((car.current_speed - 2.).abs() < 1e-4);
+        while car.path_index == 0 {
+            drive(&mut car, &mut graph);
+        }
+        for _ in 0..60 {
+            drive(&mut car, &mut graph);
+        }
+        assert!((car.current_speed - car.speed).abs() < 1e-4);
+    }
+
+    #[test]
+    fn vehicles_stop_before_blocked_crosswalks() {
+        let mut graph = straight_road();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let traffic = Traffic {
+            blocked_crosswalks: HashMap::from([((0, 1), vec![0.5])]),
+            ..Default::default()
+        };
+
+        // The car stops with its front short of the crosswalk
+        let mut car = Vehicle::new(0, VehicleType::car(), vec![0, 1, 2], 0., &mut rng);
+        car.drive(15., &mut graph, &traffic);
+        let stop_point = 10. - 0.25 - MIN_GAP;
+        assert!((car.distance_traveled - stop_point).abs() < 1e-4);
+        car.drive(1., &mut graph, &traffic);
+        assert!((car.distance_traveled - stop_point).abs() < 1e-4);
+
+        // A car already on the crosswalk drives off it
+        let mut car = Vehicle::new(1, VehicleType::car(), vec![0, 1, 2], 0., &mut rng);
+        car.edge_position = 0.5;
+        car.drive(2., &mut graph, &traffic);
+        assert!((car.edge_position - 0.6).abs() < 1e-4);
     }
 }